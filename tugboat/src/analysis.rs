@@ -1,26 +1,581 @@
 use super::*;
-use std::collections::HashMap;
+use hardware::{Hardware, HARDWARE};
+use joypad::{Joypad, JOYPAD};
+use lexer::TokenKind;
+use sound::SOUND;
+use std::collections::{HashMap, HashSet};
+
+/// Built-in functions, which are expanded by the compiler rather than called like user functions.
+pub const INTRINSICS: [&str; 17] = [
+    "copy",
+    "fill",
+    "zero",
+    "sizeof",
+    "vram_copy",
+    "wait_vblank",
+    "lcd_off",
+    "lcd_on",
+    "joypad.update",
+    "oam_dma",
+    "sound.play",
+    "sound.update",
+    "bg_palette",
+    "obj_palette",
+    "vram_bank",
+    "wram_bank",
+    "cgb_double_speed",
+];
+
+pub fn is_intrinsic(name: &str) -> bool {
+    INTRINSICS.contains(&name)
+}
+
+/// The number of sprites OAM has room for.
+pub(crate) const SPRITE_COUNT: u16 = 40;
 
 // When I introduce a type system, this might need to live elsewhere...
-#[derive(PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum ValueType {
-    UnsignedByte,
-    Function,
+    UnsignedByte {
+        memory: Memory,
+        bank: Option<u16>,
+        size: u16,
+    },
+    Function {
+        memory: Memory,
+        bank: Option<u16>,
+    },
+    Register {
+        address: u16,
+    },
+    Constant {
+        value: u8,
+    },
+    Region {
+        address: u16,
+        size: u16,
+    },
+    /// The shadow OAM, an array of sprites accessed by field.
+    Sprites,
+    /// A byte maintained by the runtime, which programs can read but not write.
+    State {
+        label: &'static str,
+    },
 }
 
-pub fn generate_directory(ast: &Vec<Declaration>) -> HashMap<String, ValueType> {
+pub fn generate_directory(
+    ast: &[Declaration],
+    options: &Options,
+) -> Result<HashMap<String, ValueType>, Vec<Diagnostic>> {
     let mut directory: HashMap<String, ValueType> = HashMap::new();
+    let mut errors: Vec<Diagnostic> = Vec::new();
+
+    for (namespace, table) in [("hw", &HARDWARE), ("sound", &SOUND)] {
+        for (name, hardware) in table.entries() {
+            let value_type = match *hardware {
+                Hardware::Register(address) => ValueType::Register { address },
+                Hardware::Constant(value) => ValueType::Constant { value },
+                Hardware::Region { address, size } => ValueType::Region { address, size },
+            };
+            directory.insert(format!("{}.{}", namespace, name), value_type);
+        }
+    }
+
+    for (name, joypad) in JOYPAD.entries() {
+        let value_type = match *joypad {
+            Joypad::State(label) => ValueType::State { label },
+            Joypad::Button(value) => ValueType::Constant { value },
+        };
+        directory.insert(format!("joypad.{}", name), value_type);
+    }
+
+    if options.sprites {
+        directory.insert(String::from("oam"), ValueType::Sprites);
+    }
+    let builtins: HashSet<String> = directory.keys().cloned().collect();
+
+    // Imported symbols are registered first so that local definitions can't silently shadow them.
+    for symbol in options.imports.iter().flat_map(|interface| interface.symbols.iter()) {
+        let value_type = match &symbol.kind {
+            SymbolKind::Function { placement } => ValueType::Function {
+                memory: placement.memory,
+                bank: placement.bank,
+            },
+            SymbolKind::Variable { placement, size } => ValueType::UnsignedByte {
+                memory: placement.memory,
+                bank: placement.bank,
+                size: *size,
+            },
+        };
+        directory.insert(symbol.name.clone(), value_type);
+    }
 
+    let mut defined: HashMap<&str, Span> = HashMap::new();
     for dec in ast.iter() {
+        let (name, value_type) = match dec {
+            Declaration::Function { name, placement, .. } => (
+                name,
+                ValueType::Function {
+                    memory: placement.memory,
+                    bank: placement.bank,
+                },
+            ),
+            Declaration::Variable {
+                name, placement, size, ..
+            } => (
+                name,
+                ValueType::UnsignedByte {
+                    memory: placement.memory,
+                    bank: placement.bank,
+                    size: size.unwrap_or(0),
+                },
+            ),
+        };
+
+        if is_intrinsic(&name.lexeme) {
+            errors.push(
+                Diagnostic::error(
+                    name.span,
+                    format!("{} is a built-in function and can't be redefined", name.lexeme),
+                )
+                .with_note("choose another name for it"),
+            );
+        } else if directory.insert(name.lexeme.clone(), value_type).is_some() {
+            let error = Diagnostic::error(name.span, format!("Duplicate definition: {}", name.lexeme));
+            errors.push(match defined.get(name.lexeme.as_str()) {
+                Some(first) => error.with_label(*first, "first defined here"),
+                None if builtins.contains(&name.lexeme) => {
+                    error.with_note(format!("{} is a built-in name, choose another one", name.lexeme))
+                }
+                None => error.with_note(format!("{} is already defined by an imported unit", name.lexeme)),
+            });
+        }
+        defined.entry(&name.lexeme).or_insert(name.span);
+    }
+
+    if errors.is_empty() {
+        Ok(directory)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Everything programs can refer to without declaring it, with a short description of each.
+pub fn builtins(options: &Options) -> Vec<Builtin> {
+    let directory = generate_directory(&[], options).unwrap_or_default();
+    let mut builtins: Vec<Builtin> = directory
+        .into_iter()
+        .filter_map(|(name, value_type)| {
+            let description = match value_type {
+                ValueType::Register { address } => format!("hardware register at ${:04X}", address),
+                ValueType::Constant { value } => format!("constant ${:02X} ({})", value, value),
+                ValueType::Region { address, size } => format!("{} bytes of memory at ${:04X}", size, address),
+                ValueType::Sprites => String::from("shadow OAM of 40 sprites, each with y, x, tile and flags"),
+                ValueType::State { .. } => String::from("read-only byte kept up to date by the runtime"),
+                // Imported symbols are declared elsewhere rather than built in.
+                ValueType::UnsignedByte { .. } | ValueType::Function { .. } => return None,
+            };
+            Some(Builtin {
+                name,
+                description,
+                callable: false,
+            })
+        })
+        .collect();
+
+    builtins.extend(INTRINSICS.iter().map(|name| Builtin {
+        name: name.to_string(),
+        description: String::from("built-in function"),
+        callable: true,
+    }));
+    builtins.sort_by(|a, b| a.name.cmp(&b.name));
+    builtins
+}
+
+/// Works out the size of every `incbin` asset from its file, checking the file exists and any slice of it
+/// actually fits.
+pub fn resolve_assets(ast: &mut [Declaration], options: &Options) -> Result<(), Vec<Diagnostic>> {
+    let mut errors: Vec<Diagnostic> = Vec::new();
+
+    for dec in ast.iter_mut() {
+        let Declaration::Variable {
+            name,
+            size,
+            initializer: Some(initializer),
+            ..
+        } = dec
+        else {
+            continue;
+        };
+
+        let (path, resolved) = match initializer {
+            Initializer::Values(_) => continue,
+            Initializer::Incbin { path, offset, length } => {
+                let resolved = resolve_incbin(options.base_dir.join(&path.lexeme), &path.lexeme, *offset, *length);
+                if let Ok(resolved) = resolved {
+                    *length = Some(resolved);
+                }
+                (path, resolved)
+            }
+            Initializer::Graphics {
+                path,
+                output,
+                tall,
+                dedupe,
+                data,
+            } => {
+                let resolved = graphics::convert(&options.base_dir.join(&path.lexeme), *tall, *dedupe)
+                    .map_err(|err| format!("Unable to convert \"{}\": {}", path.lexeme, err))
+                    .and_then(|tileset| {
+                        *data = match output {
+                            GraphicsOutput::Tiles => tileset.tiles,
+                            GraphicsOutput::Tilemap => tileset.map,
+                        };
+                        u16::try_from(data.len())
+                            .map_err(|_| format!("Graphics from \"{}\" are too large", path.lexeme))
+                    });
+                (path, resolved)
+            }
+        };
+
+        let error = |msg: String| Diagnostic::error(path.span, msg);
+        let resolved = match resolved {
+            Ok(resolved) => resolved,
+            Err(msg) => {
+                errors.push(error(msg));
+                continue;
+            }
+        };
+
+        match size {
+            // The size was left for the asset to decide.
+            None => *size = Some(resolved),
+            Some(size) if *size < resolved => errors.push(error(format!(
+                "{} is too small for \"{}\" ({} bytes)",
+                name.lexeme, path.lexeme, resolved
+            ))),
+            Some(_) => {}
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// The number of bytes an `incbin` includes from its file.
+fn resolve_incbin(file: PathBuf, path: &str, offset: u16, length: Option<u16>) -> Result<u16, String> {
+    let file_size = std::fs::metadata(file)
+        .map_err(|err| format!("Unable to read asset \"{}\": {}", path, err))?
+        .len();
+
+    if u64::from(offset) > file_size {
+        return Err(format!(
+            "Offset {} is past the end of \"{}\" ({} bytes)",
+            offset, path, file_size
+        ));
+    }
+
+    let available = file_size - u64::from(offset);
+    match length {
+        Some(length) if u64::from(length) > available => Err(format!(
+            "Slice of \"{}\" runs past the end of the file ({} bytes)",
+            path, file_size
+        )),
+        Some(length) => Ok(length),
+        None => u16::try_from(available).map_err(|_| format!("Asset \"{}\" is too large ({} bytes)", path, available)),
+    }
+}
+
+/// The number of bytes available in HRAM ($FF80-$FFFE).
+const HRAM_SIZE: u32 = 127;
+
+/// The size in bytes of the OAM DMA routine copied into HRAM at startup.
+const OAM_DMA_SIZE: u32 = 8;
+
+/// Checks for problems that don't stop compilation but are probably mistakes. Each finding is a warning unless the
+/// lint's level says otherwise, in which case it is dropped or becomes an error.
+pub fn lint(ast: &[Declaration], directory: &HashMap<String, ValueType>, options: &Options) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    let references: Vec<(&str, HashSet<&str>)> = ast
+        .iter()
+        .filter_map(|dec| match dec {
+            Declaration::Function { name, body, .. } => Some((name.lexeme.as_str(), referenced_names(body))),
+            Declaration::Variable { .. } => None,
+        })
+        .collect();
+    // A function calling itself doesn't count as a use.
+    let used = |name: &str| {
+        references
+            .iter()
+            .any(|(function, names)| *function != name && names.contains(name))
+    };
+    // Only items nothing outside the program can reach are checked for uses: with the startup code the program is
+    // self-contained, and separate units only export `pub` items.
+    let checks_uses = |public: bool| !public && (options.startup || options.separate);
+
+    // The runtime always keeps whether interrupts are enabled in HRAM. Bank switching keeps track of the current bank
+    // there too, and the OAM DMA routine has to run from there.
+    let mut hram_used: u32 = 1;
+    if options.mbc != Mbc::None {
+        hram_used += 1;
+    }
+    if options.sprites {
+        hram_used += OAM_DMA_SIZE;
+    }
+
+    for dec in ast.iter() {
+        let mut found: Vec<Diagnostic> = Vec::new();
+
         match dec {
-            Declaration::Function { name, .. } => {
-                directory.insert(name.lexeme.clone(), ValueType::Function);
+            Declaration::Variable {
+                name,
+                size,
+                public,
+                placement,
+                lints,
+                ..
+            } => {
+                if checks_uses(*public) && !used(&name.lexeme) {
+                    found.push(Diagnostic::warning(
+                        Lint::UnusedVariable,
+                        name.span,
+                        format!("Variable {} is never used", name.lexeme),
+                    ));
+                }
+
+                if placement.memory == Memory::Hram {
+                    let fits = hram_used <= HRAM_SIZE;
+                    hram_used += u32::from(size.unwrap_or(0));
+                    if fits && hram_used > HRAM_SIZE {
+                        found.push(
+                            Diagnostic::warning(
+                                Lint::HramBudget,
+                                name.span,
+                                format!(
+                                    "HRAM budget exceeded by {}: {} bytes used, {} available",
+                                    name.lexeme, hram_used, HRAM_SIZE
+                                ),
+                            )
+                            .with_note("move some variables out of hram into WRAM"),
+                        );
+                    }
+                }
+
+                diagnostics.extend(found.into_iter().filter_map(|found| apply_level(found, lints, options)));
+            }
+            Declaration::Function {
+                name,
+                body,
+                public,
+                interrupt,
+                lints,
+                ..
+            } => {
+                let entry = options.startup && name.lexeme == "main";
+                if checks_uses(*public) && !entry && interrupt.is_none() && !used(&name.lexeme) {
+                    found.push(Diagnostic::warning(
+                        Lint::UnusedFunction,
+                        name.span,
+                        format!("Function {} is never called", name.lexeme),
+                    ));
+                }
+
+                lint_body(body, directory, &mut found);
+                diagnostics.extend(found.into_iter().filter_map(|found| apply_level(found, lints, options)));
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Drops or promotes a lint's warning according to its level, from the declaration it was found in or otherwise
+/// the options.
+fn apply_level(mut diagnostic: Diagnostic, lints: &[(Lint, LintLevel)], options: &Options) -> Option<Diagnostic> {
+    let lint = diagnostic.code?;
+    let level = lints
+        .iter()
+        .rev()
+        .find(|(other, _)| *other == lint)
+        .map(|(_, level)| *level)
+        .or(options.lints.get(&lint).copied())
+        .unwrap_or(LintLevel::Warn);
+
+    match level {
+        LintLevel::Allow => None,
+        LintLevel::Warn => Some(diagnostic),
+        LintLevel::Deny => {
+            diagnostic.level = Level::Error;
+            Some(diagnostic)
+        }
+    }
+}
+
+/// Looks for unreachable statements, assignments with no effect and constant arithmetic that overflows.
+fn lint_body(body: &[Stmt], directory: &HashMap<String, ValueType>, found: &mut Vec<Diagnostic>) {
+    // There's no way out of a loop whose condition is always true.
+    let mut endless: Option<Span> = None;
+
+    for stmt in body {
+        if let Some(loop_span) = endless {
+            found.push(
+                Diagnostic::warning(Lint::UnreachableCode, stmt.span(), "Unreachable statement")
+                    .with_label(loop_span, "this loop never ends"),
+            );
+            break;
+        }
+
+        match stmt {
+            Stmt::Halt { .. } | Stmt::DisableInterrupts { .. } | Stmt::EnableInterrupts { .. } => {}
+            Stmt::Atomic { body, .. } => lint_body(body, directory, found),
+            Stmt::While {
+                keyword,
+                condition,
+                body,
+            } => {
+                lint_expr(condition, directory, found);
+                lint_body(body, directory, found);
+                if constant(condition, directory).is_some_and(|value| value != 0) {
+                    endless = Some(keyword.span);
+                }
+            }
+            Stmt::Assign { target, value } => {
+                lint_expr(target, directory, found);
+                lint_expr(value, directory, found);
+                if same_place(target, value, directory) {
+                    found.push(Diagnostic::warning(
+                        Lint::NoEffect,
+                        stmt.span(),
+                        "Assigning a variable to itself has no effect",
+                    ));
+                }
+            }
+            Stmt::Expression { expr } => lint_expr(expr, directory, found),
+        }
+    }
+}
+
+fn lint_expr(expr: &Expr, directory: &HashMap<String, ValueType>, found: &mut Vec<Diagnostic>) {
+    match expr {
+        Expr::Literal { .. } | Expr::Variable { .. } => {}
+        Expr::Indexed { index, .. } => lint_expr(index, directory, found),
+        Expr::Binary { operator, left, right } => {
+            lint_expr(left, directory, found);
+            lint_expr(right, directory, found);
+
+            let operands = constant(left, directory).zip(constant(right, directory));
+            if let (Some((left, right)), Some(result)) = (operands, constant(expr, directory)) {
+                // Operands that overflowed themselves have already been reported.
+                let in_range = |value: i32| (0..=255).contains(&value);
+                if in_range(left) && in_range(right) && !in_range(result) {
+                    found.push(Diagnostic::warning(
+                        Lint::Overflow,
+                        expr.span(),
+                        format!(
+                            "Constant arithmetic overflows a u8: {} {} {} wraps to {}",
+                            left,
+                            operator.lexeme,
+                            right,
+                            result.rem_euclid(256)
+                        ),
+                    ));
+                }
             }
-            Declaration::Variable { name, .. } => {
-                directory.insert(name.lexeme.clone(), ValueType::UnsignedByte);
+        }
+        Expr::Call { arguments, .. } => {
+            for argument in arguments {
+                lint_expr(argument, directory, found);
+            }
+        }
+    }
+}
+
+/// The value of an expression made only of constants and sizes, without wrapping it to a u8.
+pub(crate) fn constant(expr: &Expr, directory: &HashMap<String, ValueType>) -> Option<i32> {
+    match expr {
+        Expr::Literal { value, .. } => Some(i32::from(*value)),
+        Expr::Variable { name } => match directory.get(&name.lexeme) {
+            Some(ValueType::Constant { value }) => Some(i32::from(*value)),
+            _ => None,
+        },
+        Expr::Binary { operator, left, right } => {
+            let (left, right) = (constant(left, directory)?, constant(right, directory)?);
+            match operator.kind {
+                TokenKind::Plus => Some(left + right),
+                TokenKind::Minus => Some(left - right),
+                TokenKind::Ampersand => Some(left & right),
+                TokenKind::Pipe => Some(left | right),
+                _ => None,
             }
         }
+        Expr::Call { callee, arguments } if callee.lexeme == "sizeof" => size_of(arguments, directory).map(i32::from),
+        Expr::Indexed { .. } | Expr::Call { .. } => None,
+    }
+}
+
+/// The size in bytes of the variable or memory region passed to `sizeof`, if it is one.
+fn size_of(arguments: &[Expr], directory: &HashMap<String, ValueType>) -> Option<u16> {
+    let [Expr::Variable { name }] = arguments else {
+        return None;
+    };
+    match directory.get(&name.lexeme)? {
+        ValueType::UnsignedByte { size, .. } | ValueType::Region { size, .. } => Some(*size),
+        ValueType::Sprites => Some(SPRITE_COUNT * 4),
+        _ => None,
+    }
+}
+
+/// Whether an assignment reads and writes the same memory. Hardware registers are left out, since reading or
+/// writing them can do something.
+fn same_place(target: &Expr, value: &Expr, directory: &HashMap<String, ValueType>) -> bool {
+    let in_memory = |name: &Token| {
+        matches!(
+            directory.get(&name.lexeme),
+            Some(ValueType::UnsignedByte { .. } | ValueType::Sprites)
+        )
+    };
+
+    match (target, value) {
+        (Expr::Variable { name }, Expr::Variable { name: other }) => name.lexeme == other.lexeme && in_memory(name),
+        (
+            Expr::Indexed { name, index, field },
+            Expr::Indexed {
+                name: other,
+                index: other_index,
+                field: other_field,
+            },
+        ) => {
+            let index = constant(index, directory);
+            name.lexeme == other.lexeme
+                && in_memory(name)
+                && field.as_ref().map(|f| &f.lexeme) == other_field.as_ref().map(|f| &f.lexeme)
+                && index.is_some()
+                && index == constant(other_index, directory)
+        }
+        _ => false,
+    }
+}
+
+/// Every name a function body refers to, whether it reads, writes or calls it.
+fn referenced_names(body: &[Stmt]) -> HashSet<&str> {
+    let mut names = ReferencedNames(HashSet::new());
+    for stmt in body {
+        names.visit_stmt(stmt);
     }
+    names.0
+}
 
-    directory
+struct ReferencedNames<'a>(HashSet<&'a str>);
+
+impl<'a> Visitor<'a> for ReferencedNames<'a> {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        if let Expr::Variable { name } | Expr::Indexed { name, .. } | Expr::Call { callee: name, .. } = expr {
+            self.0.insert(&name.lexeme);
+        }
+        visit::walk_expr(self, expr);
+    }
 }
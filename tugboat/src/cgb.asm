@@ -0,0 +1,30 @@
+
+SECTION "CGB", ROM0
+
+; Writes b bytes from hl to the palette data register at $FF00 + c, which steps through the palette by itself
+tgCopyPalette::
+  .untilPaletteIsCopied
+    ld a, [hli]
+    ldh [c], a
+    dec b
+  jr nz, .untilPaletteIsCopied
+  ret
+
+; Switches the CPU to double speed, unless it's already there. The switch happens on stop, which mustn't be
+; woken by an interrupt or the joypad part way through.
+tgDoubleSpeed::
+  ldh a, [$FF4D]
+  add a
+  ret c
+  ldh a, [$FFFF]
+  push af
+  xor a
+  ldh [$FFFF], a
+  ld a, $30
+  ldh [$FF00], a
+  ld a, 1
+  ldh [$FF4D], a
+  stop
+  pop af
+  ldh [$FFFF], a
+  ret
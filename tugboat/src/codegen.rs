@@ -1,122 +1,774 @@
 use crate::lexer::TokenKind;
 
-use self::analysis::ValueType;
+use self::analysis::{ValueType, SPRITE_COUNT};
 use super::*;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
 
-type Directory = HashMap<String, ValueType>;
-type GenResult = Result<String, CompilationError>;
+mod intrinsics;
 
-pub fn gen(ast: Vec<Declaration>, directory: &Directory) -> Result<String, Vec<CompilationError>> {
+pub type Directory = HashMap<String, ValueType>;
+type GenResult = Result<String, Diagnostic>;
+
+/// Everything code generation needs to know about its surroundings while working through a function.
+#[derive(Clone, Copy)]
+struct Context<'a> {
+    directory: &'a Directory,
+    options: &'a Options,
+    /// The switchable bank the current function lives in, or `None` for code in ROM0.
+    bank: Option<u16>,
+    /// Whether interrupts are known to be disabled, either inside a handler or an `atomic` block.
+    atomic: bool,
+    /// Functions in other banks that need a trampoline emitted for them.
+    far_calls: &'a RefCell<BTreeSet<String>>,
+}
+
+impl Context<'_> {
+    fn lookup(&self, name: &Token) -> Result<&ValueType, Diagnostic> {
+        self.directory.get(&name.lexeme).ok_or(error(
+            name.span,
+            format!("Undefined variable: {}", name.lexeme).as_str(),
+        ))
+    }
+
+    /// Whether something in the given ROM location can be reached without switching banks first.
+    fn is_mapped(&self, memory: Memory, bank: Option<u16>) -> bool {
+        // Without an MBC, bank 1 is the only switchable bank and is always mapped.
+        memory != Memory::RomX || self.options.mbc == Mbc::None || bank == self.bank
+    }
+}
+
+/// Makes sure the program has somewhere to start, then lays its declarations out into sections with variables
+/// ahead of functions. Declarations that can't be placed are left out of every section.
+pub fn lower(ast: &[Declaration], options: &Options) -> (Vec<Section>, Vec<Diagnostic>) {
+    let mut errors: Vec<Diagnostic> = Vec::new();
+
+    if !options.separate && options.startup {
+        if let Err(err) = check_main(ast, options) {
+            errors.push(err);
+        }
+    }
+
+    // The default sections are always present.
+    let mut sections: Vec<Section> = vec![
+        Section::new(String::from("Variables"), Placement::default()),
+        Section::new(String::from("Functions"), Placement::default_code()),
+    ];
+    let declarations = ast.iter().enumerate();
+    let variables = declarations.clone().filter(|(_, dec)| is_variable(dec));
+    let functions = declarations.filter(|(_, dec)| is_function(dec));
+    for (index, dec) in variables.chain(functions) {
+        if let Err(err) = check_bank(dec, options).and_then(|_| add_to_section(&mut sections, dec, index)) {
+            errors.push(err);
+        }
+    }
+
+    (sections, errors)
+}
+
+pub fn gen(
+    ast: &[Declaration],
+    sections: &[Section],
+    directory: &Directory,
+    options: &Options,
+) -> Result<String, Vec<Diagnostic>> {
     let mut output = String::new();
-    let mut errors: Vec<CompilationError> = Vec::new();
+    let mut errors: Vec<Diagnostic> = Vec::new();
+
+    // Separately compiled units link against a single shared copy of the runtime instead.
+    if !options.separate {
+        output.push_str(gen_core(RuntimeUses::of(ast), options).as_str());
+        if options.startup {
+            output.push_str(gen_startup(ast, options).as_str());
+        }
+    }
 
-    output.push_str(include_str!("core.asm"));
+    let far_calls = RefCell::new(BTreeSet::new());
+    let ctx = Context {
+        directory,
+        options,
+        bank: None,
+        atomic: false,
+        far_calls: &far_calls,
+    };
+    for section in sections.iter() {
+        if section.declarations.is_empty() && section.name != "Variables" && section.name != "Functions" {
+            continue;
+        }
 
-    // Define all variables in memory first
-    output.push_str(String::from("SECTION \"Variables\", WRAM0\n").as_str());
-    for dec in ast.iter().filter(is_variable) {
-        match gen_declaration(dec, directory) {
-            Ok(asm) => output.push_str(asm.as_str()),
-            Err(err) => errors.push(err),
+        output.push_str(gen_section(&section.name, &section.placement, options).as_str());
+        for index in section.declarations.iter() {
+            match gen_declaration(&ast[*index], ctx) {
+                Ok(asm) => output.push_str(asm.as_str()),
+                Err(err) => errors.push(err),
+            }
         }
     }
 
-    // Now output all functions
-    output.push_str(String::from("SECTION \"Functions\", ROM0\n").as_str());
-    for dec in ast.iter().filter(is_function) {
-        match gen_declaration(dec, directory) {
-            Ok(asm) => output.push_str(asm.as_str()),
-            Err(err) => errors.push(err),
+    match gen_initial_values(ast, ctx) {
+        Ok(asm) => output.push_str(asm.as_str()),
+        Err(err) => errors.push(err),
+    }
+
+    match gen_vectors(ast, options) {
+        Ok(asm) => output.push_str(asm.as_str()),
+        Err(err) => errors.push(err),
+    }
+
+    if !far_calls.borrow().is_empty() {
+        output.push_str(gen_section("Far calls", &Placement::default_code(), options).as_str());
+        for function in far_calls.borrow().iter() {
+            output.push_str(gen_trampoline(function).as_str());
         }
     }
 
-    if errors.len() == 0 {
+    if errors.is_empty() {
         Ok(output)
     } else {
         Err(errors)
     }
 }
 
+/// A section of the output, holding declarations given by their index in the program.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Section {
+    pub name: String,
+    pub placement: Placement,
+    pub declarations: Vec<usize>,
+}
+
+impl Section {
+    fn new(name: String, placement: Placement) -> Self {
+        Section {
+            name,
+            placement,
+            declarations: Vec::new(),
+        }
+    }
+}
+
+impl Placement {
+    fn default_code() -> Self {
+        Placement {
+            memory: Memory::Rom0,
+            ..Placement::default()
+        }
+    }
+}
+
+fn add_to_section(sections: &mut Vec<Section>, dec: &Declaration, index: usize) -> Result<(), Diagnostic> {
+    let (name, placement, is_data) = match dec {
+        Declaration::Variable { name, placement, .. } => (name, placement, true),
+        Declaration::Function { name, placement, .. } => (name, placement, false),
+    };
+
+    let section_name = section_name(name, placement, is_data);
+    // The name is compared separately, since default sections don't have one set on their placement.
+    let placement = Placement {
+        section: None,
+        ..placement.clone()
+    };
+
+    match sections.iter_mut().find(|section| section.name == section_name) {
+        Some(section) if section.placement == placement => section.declarations.push(index),
+        Some(_) => {
+            return Err(error(
+                name.span,
+                format!("Section \"{}\" is already placed elsewhere", section_name).as_str(),
+            ))
+        }
+        None => {
+            let mut section = Section::new(section_name, placement);
+            section.declarations.push(index);
+            sections.push(section);
+        }
+    }
+
+    Ok(())
+}
+
+fn section_name(name: &Token, placement: &Placement, is_data: bool) -> String {
+    if let Some(section) = &placement.section {
+        return section.clone();
+    }
+
+    // Fixed addresses can't share a section, so each gets one named after its declaration.
+    if placement.address.is_some() {
+        return name.lexeme.clone();
+    }
+
+    let mut section = String::from(match placement.memory {
+        Memory::Wram0 => "Variables",
+        Memory::WramX => "WRAMX Variables",
+        Memory::Hram => "HRAM Variables",
+        Memory::Rom0 | Memory::RomX if is_data => "Data",
+        Memory::Rom0 | Memory::RomX => "Functions",
+    });
+    if let Some(bank) = placement.bank {
+        section.push_str(format!(" (bank {})", bank).as_str());
+    }
+    if let Some(align) = placement.align {
+        section.push_str(format!(" (align {})", align).as_str());
+    }
+
+    section
+}
+
+/// Makes sure a declaration's ROM bank can actually be switched to by the cartridge's MBC.
+fn check_bank(dec: &Declaration, options: &Options) -> Result<(), Diagnostic> {
+    let (name, placement) = match dec {
+        Declaration::Variable { name, placement, .. } => (name, placement),
+        Declaration::Function { name, placement, .. } => (name, placement),
+    };
+
+    match placement.bank {
+        Some(bank) if placement.memory == Memory::RomX && bank > options.mbc.banks() => Err(error(
+            name.span,
+            format!(
+                "ROM bank {} is not available with {} (banks 1 to {})",
+                bank,
+                options.mbc,
+                options.mbc.banks()
+            )
+            .as_str(),
+        )),
+        // The DMG has a single WRAMX bank, the CGB switches between seven with SVBK.
+        Some(bank) if placement.memory == Memory::WramX && bank > 1 && !options.cgb => Err(error(
+            name.span,
+            format!("WRAM bank {} needs CGB mode (only bank 1 without it)", bank).as_str(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Points each interrupt vector with a handler at it.
+fn gen_vectors(ast: &[Declaration], options: &Options) -> GenResult {
+    let mut output = String::new();
+    let mut handled: Vec<(InterruptKind, Span)> = Vec::new();
+
+    for dec in ast.iter() {
+        let Declaration::Function {
+            name,
+            interrupt: Some(interrupt),
+            ..
+        } = dec
+        else {
+            continue;
+        };
+
+        if let Some((_, first)) = handled.iter().find(|(kind, _)| kind == interrupt) {
+            return Err(error(
+                name.span,
+                format!("The {} interrupt already has a handler", interrupt.name()).as_str(),
+            )
+            .with_label(*first, "first handler defined here"));
+        }
+        handled.push((*interrupt, name.span));
+
+        let placement = Placement {
+            address: Some(interrupt.vector()),
+            ..Placement::default_code()
+        };
+        output.push_str(gen_section(format!("{} interrupt", interrupt.name()).as_str(), &placement, options).as_str());
+        output.push_str(format!("\tjp {}\n", name.lexeme).as_str());
+    }
+
+    Ok(output)
+}
+
+pub const RUNTIME: &str = include_str!("core.asm");
+const JOYPAD: &str = include_str!("joypad.asm");
+const SPRITES: &str = include_str!("sprites.asm");
+const SOUND: &str = include_str!("sound.asm");
+const CGB: &str = include_str!("cgb.asm");
+const STARTUP: &str = include_str!("startup.asm");
+
+/// The runtime for a set of options, as shared by separately compiled units.
+pub fn gen_runtime(options: &Options) -> String {
+    // There's no telling which units will need the optional parts, so they are all included.
+    let uses = RuntimeUses {
+        joypad: true,
+        sound: true,
+    };
+    let mut output = gen_core(uses, options);
+    // Initialised variables aren't allowed in separate units, so there is never any data to copy here.
+    if options.startup {
+        output.push_str(gen_startup(&[], options).as_str());
+    }
+    output
+}
+
+/// The optional parts of the runtime a program relies on.
+#[derive(Default, Clone, Copy)]
+struct RuntimeUses {
+    joypad: bool,
+    sound: bool,
+}
+
+impl RuntimeUses {
+    fn of(ast: &[Declaration]) -> RuntimeUses {
+        let mut uses = RuntimeUses::default();
+        visit::walk_ast(&mut uses, ast);
+        uses
+    }
+}
+
+impl Visitor<'_> for RuntimeUses {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Variable { name } | Expr::Indexed { name, .. } | Expr::Call { callee: name, .. } = expr {
+            self.joypad |= name.lexeme.starts_with("joypad.");
+            // The sound registers are plain hardware, only the effect player lives in the runtime.
+            self.sound |= matches!(name.lexeme.as_str(), "sound.play" | "sound.update");
+        }
+        visit::walk_expr(self, expr);
+    }
+}
+
+/// The core subroutines, plus bank switching when there is an MBC to drive and whatever else the program uses.
+fn gen_core(uses: RuntimeUses, options: &Options) -> String {
+    let mut output = String::from(RUNTIME);
+    if uses.joypad {
+        output.push_str(JOYPAD);
+    }
+    if uses.sound {
+        output.push_str(SOUND);
+    }
+    if options.sprites {
+        output.push_str(SPRITES);
+    }
+    if options.cgb {
+        output.push_str(CGB);
+    }
+
+    if let Some(register) = options.mbc.bank_register() {
+        output.push_str("\nSECTION \"Bank switching\", ROM0\n\n");
+        output.push_str("; Switches to ROM bank a, remembering it so far calls can switch back afterwards\n");
+        output.push_str("tgSetBank::\n");
+        output.push_str("\tldh [hCurrentBank], a\n");
+        output.push_str(format!("\tld [${:04X}], a\n", register).as_str());
+        output.push_str("\tret\n");
+        output.push_str("\nSECTION \"Bank switching state\", HRAM\n");
+        output.push_str("hCurrentBank:: ds 1\n");
+    }
+
+    output
+}
+
+/// The entry point and boot initialisation, finishing with a call to main.
+fn gen_startup(ast: &[Declaration], options: &Options) -> String {
+    let mut output = gen_header(options);
+    output.push_str(format!("\n{}", STARTUP).as_str());
+
+    // The bank number was just cleared along with the rest of HRAM, but bank 1 is what's actually mapped.
+    if options.mbc != Mbc::None {
+        output.push_str("\tld a, 1\n\tcall tgSetBank\n");
+    }
+
+    // DMA blocks everything but HRAM, so the routine that starts it has to run from there.
+    if options.sprites {
+        output.push_str("\tld de, tgOamDmaRoutine\n\tld hl, hOamDma\n");
+        output.push_str("\tld bc, tgOamDmaRoutine.end - tgOamDmaRoutine\n\tcall tgCopyMemory\n");
+    }
+
+    for (name, size) in ast.iter().filter_map(initialised_ram) {
+        output.push_str(format!("\tld de, tgInit_{}\n", name.lexeme).as_str());
+        output.push_str(format!("\tld hl, {}\n", name.lexeme).as_str());
+        output.push_str(format!("\tld bc, {}\n", size).as_str());
+        output.push_str("\tcall tgCopyMemory\n");
+    }
+
+    output.push_str("\tcall main\n");
+    output.push_str(".afterMain\n\thalt\n\tjr .afterMain\n");
+    output
+}
+
+/// The cartridge header's entry point, which jumps over the rest of the header to the startup code.
+fn gen_header(options: &Options) -> String {
+    let mut output = String::from("\nSECTION \"Header\", ROM0[$100]\n\n");
+    output.push_str("\tdi\n\tjp tgStart\n");
+    output.push_str("\t; The rest of the header is filled in by rgbfix\n");
+
+    // The compiler doesn't check that colour features fall back gracefully, so the ROM is marked CGB only.
+    if options.cgb {
+        output.push_str("\tds $143 - @, 0\n\tdb $C0\n");
+    }
+
+    output.push_str("\tds $150 - @, 0\n");
+    output
+}
+
+/// Makes sure there is a main function the startup code can call.
+fn check_main(ast: &[Declaration], options: &Options) -> Result<(), Diagnostic> {
+    let main = ast.iter().find(|dec| match dec {
+        Declaration::Variable { name, .. } | Declaration::Function { name, .. } => name.lexeme == "main",
+    });
+
+    match main {
+        None => Err(error(
+            Span {
+                line: 1,
+                column: 1,
+                ..Span::default()
+            },
+            "No main function to start the program from",
+        )),
+        Some(Declaration::Variable { name, .. }) => Err(error(name.span, "main must be a function")),
+        Some(Declaration::Function {
+            name,
+            arguments,
+            placement,
+            interrupt,
+            ..
+        }) => {
+            if !arguments.is_empty() {
+                Err(error(name.span, "main can't take any arguments"))
+            } else if interrupt.is_some() {
+                Err(error(name.span, "main can't be an interrupt handler"))
+            } else if placement.memory == Memory::RomX && options.mbc != Mbc::None {
+                Err(error(name.span, "main must be in ROM0 to be called at startup"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The name and size of a RAM variable whose initial value needs copying in at startup.
+fn initialised_ram(dec: &Declaration) -> Option<(&Token, u16)> {
+    match dec {
+        Declaration::Variable {
+            name,
+            size,
+            placement,
+            initializer: Some(_),
+            ..
+        } if !matches!(placement.memory, Memory::Rom0 | Memory::RomX) => Some((name, size.unwrap_or(0))),
+        _ => None,
+    }
+}
+
+/// Stores the initial values of RAM variables in ROM, ready to be copied over at startup.
+fn gen_initial_values(ast: &[Declaration], ctx: Context) -> GenResult {
+    let mut output = String::new();
+
+    for dec in ast.iter() {
+        let Declaration::Variable {
+            name,
+            size,
+            initializer: Some(initializer),
+            ..
+        } = dec
+        else {
+            continue;
+        };
+        if initialised_ram(dec).is_none() {
+            continue;
+        }
+
+        if output.is_empty() {
+            output.push_str(gen_section("Initial values", &Placement::default_code(), ctx.options).as_str());
+        }
+        output.push_str(format!("tgInit_{}:\n", name.lexeme).as_str());
+        output.push_str(gen_data(initializer, size.unwrap_or(0), ctx)?.as_str());
+    }
+
+    Ok(output)
+}
+
 static mut UID: u32 = 0;
 
 fn get_uid() -> u32 {
     // There's certainly a way to do this without using unsafe, but this seems OK for now.
     unsafe {
-        UID = UID + 1;
+        UID += 1;
         UID
     }
 }
 
 fn is_variable(dec: &&Declaration) -> bool {
-    match dec {
-        Declaration::Variable { .. } => true,
-        _ => false,
-    }
+    matches!(dec, Declaration::Variable { .. })
 }
 
 fn is_function(dec: &&Declaration) -> bool {
-    match dec {
-        Declaration::Function { .. } => true,
-        _ => false,
+    matches!(dec, Declaration::Function { .. })
+}
+
+fn error(span: Span, msg: &str) -> Diagnostic {
+    Diagnostic::error(span, msg)
+}
+
+fn gen_section(name: &str, placement: &Placement, options: &Options) -> String {
+    // Every unit contributes to the same sections, so they are declared as fragments for the linker to merge.
+    let mut output = String::from(if options.separate {
+        "SECTION FRAGMENT"
+    } else {
+        "SECTION"
+    });
+
+    output.push_str(format!(" \"{}\", {}", name, placement.memory).as_str());
+
+    if let Some(address) = placement.address {
+        output.push_str(format!("[${:04X}]", address).as_str());
     }
+    if let Some(bank) = placement.bank {
+        output.push_str(format!(", BANK[{}]", bank).as_str());
+    }
+    if let Some(align) = placement.align {
+        output.push_str(format!(", ALIGN[{}]", align).as_str());
+    }
+
+    output.push('\n');
+    output
+}
+
+fn gen_declaration(dec: &Declaration, ctx: Context) -> GenResult {
+    // Doc comments carry over to the label they describe.
+    let mut output: String = dec
+        .doc()
+        .iter()
+        .map(|line| format!("; {}", line).trim_end().to_string() + "\n")
+        .collect();
+
+    let asm = match dec {
+        Declaration::Variable {
+            name,
+            size,
+            public,
+            placement,
+            initializer,
+            ..
+        } => gen_variable(name, size.unwrap_or(0), *public, placement, initializer, ctx),
+        Declaration::Function {
+            name,
+            arguments,
+            body,
+            public,
+            placement,
+            interrupt,
+            ..
+        } => gen_function(
+            name,
+            arguments,
+            body,
+            *public,
+            interrupt.is_some(),
+            Context {
+                bank: placement.bank,
+                ..ctx
+            },
+        ),
+    }?;
+    output.push_str(asm.as_str());
+    Ok(output)
 }
 
-fn error(line: u32, msg: &str) -> CompilationError {
-    CompilationError {
-        line,
-        msg: msg.to_string(),
+fn gen_label(name: &Token, public: bool, options: &Options) -> String {
+    // Everything is exported when compiling a whole program, otherwise only `pub` items are visible to other units.
+    if public || !options.separate {
+        format!("{}::", name.lexeme)
+    } else {
+        format!("{}:", name.lexeme)
     }
 }
 
-fn lookup<'a>(identifier: &String, directory: &'a Directory, line: u32) -> Result<&'a ValueType, CompilationError> {
-    directory
-        .get(identifier)
-        .ok_or(error(line, format!("Undefined variable: {}", identifier).as_str()))
+fn gen_variable(
+    name: &Token,
+    size: u16,
+    public: bool,
+    placement: &Placement,
+    initializer: &Option<Initializer>,
+    ctx: Context,
+) -> GenResult {
+    let label = gen_label(name, public, ctx.options);
+
+    let Some(initializer) = initializer else {
+        return Ok(format!("{} ds {}\n", label, size));
+    };
+
+    // RAM only gets its initial values from the startup code, which is only generated for a whole program.
+    if !matches!(placement.memory, Memory::Rom0 | Memory::RomX) {
+        if ctx.options.separate {
+            return Err(error(
+                name.span,
+                "RAM variables can only be initialised when compiling a whole program",
+            ));
+        }
+        if !ctx.options.startup {
+            return Err(error(
+                name.span,
+                "RAM variables can only be initialised with the startup runtime enabled",
+            ));
+        }
+        return Ok(format!("{} ds {}\n", label, size));
+    }
+
+    let mut output = format!("{}\n", label);
+    output.push_str(gen_data(initializer, size, ctx)?.as_str());
+    Ok(output)
 }
 
-fn gen_declaration(dec: &Declaration, directory: &Directory) -> GenResult {
-    match dec {
-        Declaration::Variable { name, size } => Ok(gen_variable(name, size)),
-        Declaration::Function { name, arguments, body } => gen_function(name, arguments, body, directory),
+/// Lays out an initialiser as bytes, padded out with zeroes to the variable's size.
+fn gen_data(initializer: &Initializer, size: u16, ctx: Context) -> GenResult {
+    let mut output = String::new();
+
+    let used = match initializer {
+        Initializer::Values(values) => {
+            if !values.is_empty() {
+                let bytes = values
+                    .iter()
+                    .map(|value| gen_constant(value, ctx).map(|byte| byte.to_string()))
+                    .collect::<Result<Vec<String>, Diagnostic>>()?;
+                output.push_str(format!("\tdb {}\n", bytes.join(", ")).as_str());
+            }
+            values.len()
+        }
+        Initializer::Incbin { path, offset, length } => {
+            let length = length.unwrap_or(size);
+            let path = ctx.options.base_dir.join(&path.lexeme);
+            output.push_str(format!("\tINCBIN \"{}\", {}, {}\n", path.display(), offset, length).as_str());
+            usize::from(length)
+        }
+        Initializer::Graphics { data, .. } => {
+            // One tile's worth of bytes per line.
+            for chunk in data.chunks(16) {
+                let bytes: Vec<String> = chunk.iter().map(|byte| byte.to_string()).collect();
+                output.push_str(format!("\tdb {}\n", bytes.join(", ")).as_str());
+            }
+            data.len()
+        }
+    };
+
+    // Anything the initialiser leaves out is zeroed.
+    if usize::from(size) > used {
+        output.push_str(format!("\tds {}, 0\n", usize::from(size) - used).as_str());
     }
+
+    Ok(output)
+}
+
+/// Works out the value of an expression at compile time, for data baked into ROM.
+fn gen_constant(expr: &Expr, ctx: Context) -> Result<u8, Diagnostic> {
+    if let Expr::Variable { name } = expr {
+        ctx.lookup(name)?;
+    }
+    let value = analysis::constant(expr, ctx.directory)
+        .ok_or(error(expr.span(), "ROM data can only be initialised with constants"))?;
+    u8::try_from(value).map_err(|_| error(expr.span(), format!("{} doesn't fit in a u8", value).as_str()))
 }
 
-fn gen_variable(name: &Token, size: &u8) -> String {
-    format!("{}:: ds {}\n", name.lexeme, size)
+/// The value of an expression, if it is known at compile time and isn't negative.
+fn constant(expr: &Expr, ctx: Context) -> Option<u16> {
+    analysis::constant(expr, ctx.directory).and_then(|value| u16::try_from(value).ok())
 }
 
-fn gen_function(name: &Token, _arguments: &Vec<Token>, body: &Vec<Stmt>, directory: &Directory) -> GenResult {
-    let mut output = format!("{}::\n", name.lexeme);
+fn gen_function(
+    name: &Token,
+    _arguments: &Vec<Token>,
+    body: &Vec<Stmt>,
+    public: bool,
+    interrupt: bool,
+    ctx: Context,
+) -> GenResult {
+    let mut output = format!("{}\n", gen_label(name, public, ctx.options));
+
+    // Handlers can fire at any point, so every register the code they interrupted was using must survive.
+    if interrupt {
+        output.push_str("\tpush af\n\tpush bc\n\tpush de\n\tpush hl\n");
+        // The hardware disables interrupts on the way in and `reti` enables them again on the way out.
+        output.push_str("\txor a\n\tldh [hInterruptsEnabled], a\n");
+    }
 
+    let ctx = Context {
+        atomic: interrupt,
+        ..ctx
+    };
     for stmt in body {
-        output.push_str(gen_statement(stmt, directory)?.as_str());
+        output.push_str(gen_statement(stmt, ctx)?.as_str());
+    }
+
+    if interrupt {
+        output.push_str("\tld a, 1\n\tldh [hInterruptsEnabled], a\n");
+        output.push_str("\tpop hl\n\tpop de\n\tpop bc\n\tpop af\n");
+        output.push_str("\treti\n");
+    } else {
+        output.push_str("\tret\n");
     }
-    output.push_str("\tret\n");
 
     Ok(output)
 }
 
-fn gen_statement(stmt: &Stmt, directory: &Directory) -> GenResult {
+/// Calls `function` from any bank, switching to its bank for the duration of the call and back again afterwards.
+fn gen_trampoline(function: &str) -> String {
+    let mut output = format!("tgFar_{}:\n", function);
+    output.push_str("\tldh a, [hCurrentBank]\n");
+    output.push_str("\tpush af\n");
+    output.push_str(format!("\tld a, BANK({})\n", function).as_str());
+    output.push_str("\tcall tgSetBank\n");
+    output.push_str(format!("\tcall {}\n", function).as_str());
+    output.push_str("\tpop af\n");
+    output.push_str("\tjp tgSetBank\n");
+    output
+}
+
+fn gen_statement(stmt: &Stmt, ctx: Context) -> GenResult {
     match stmt {
-        Stmt::While { condition, body } => gen_while_loop(condition, body, directory),
-        Stmt::Assign { target, value } => gen_assign(target, value, directory),
-        Stmt::Expression { expr } => gen_expression(expr, directory),
-        Stmt::Halt => Ok(gen_halt()),
+        Stmt::While { condition, body, .. } => gen_while_loop(condition, body, ctx),
+        Stmt::Assign { target, value } => gen_assign(target, value, ctx),
+        Stmt::Expression { expr } => gen_expression(expr, ctx),
+        Stmt::Halt { .. } => Ok(gen_halt()),
+        Stmt::DisableInterrupts { .. } => Ok(String::from("\tdi\n\txor a\n\tldh [hInterruptsEnabled], a\n")),
+        Stmt::EnableInterrupts { .. } => Ok(String::from("\tld a, 1\n\tldh [hInterruptsEnabled], a\n\tei\n")),
+        Stmt::Atomic { keyword, body } => gen_atomic(keyword, body, ctx),
+    }
+}
+
+fn gen_atomic(keyword: &Token, body: &Vec<Stmt>, ctx: Context) -> GenResult {
+    let inner = Context { atomic: true, ..ctx };
+
+    // Nested blocks and handlers already run with interrupts disabled, so there is nothing to save or restore.
+    if ctx.atomic {
+        let mut output = String::new();
+        for stmt in body {
+            output.push_str(gen_statement(stmt, inner)?.as_str());
+        }
+        return Ok(output);
+    }
+
+    // The hardware can't report whether interrupts are enabled, so the runtime keeps track of it instead. Whatever
+    // was there before the block is put back afterwards, so a caller that disabled interrupts keeps them disabled.
+    if !ctx.options.startup {
+        return Err(error(
+            keyword.span,
+            "atomic blocks need the startup code to track whether interrupts are enabled",
+        ));
+    }
+
+    let uid = get_uid();
+    let mut output = String::from("\tldh a, [hInterruptsEnabled]\n\tpush af\n");
+    output.push_str("\tdi\n\txor a\n\tldh [hInterruptsEnabled], a\n");
+    for stmt in body {
+        output.push_str(gen_statement(stmt, inner)?.as_str());
     }
+
+    output.push_str("\tpop af\n\tldh [hInterruptsEnabled], a\n");
+    output.push_str("\tor a\n");
+    output.push_str(format!("\tjr z, .endAtomic_{}\n", uid).as_str());
+    output.push_str("\tei\n");
+    output.push_str(format!(".endAtomic_{}\n", uid).as_str());
+    Ok(output)
 }
 
-fn gen_while_loop(condition: &Expr, body: &Vec<Stmt>, directory: &Directory) -> GenResult {
+fn gen_while_loop(condition: &Expr, body: &Vec<Stmt>, ctx: Context) -> GenResult {
     let uid = get_uid();
     let mut output = format!(".startWhile_{}\n", uid);
 
     // Check the loop condition
-    output.push_str(gen_evaluate(condition, directory)?.as_str());
+    output.push_str(gen_evaluate(condition, ctx)?.as_str());
     output.push_str("\tor a\n");
     output.push_str(format!("\tjr z, .endWhile_{}\n", uid).as_str());
 
     for stmt in body {
-        output.push_str(gen_statement(stmt, directory)?.as_str());
+        output.push_str(gen_statement(stmt, ctx)?.as_str());
     }
 
     output.push_str(format!("\tjr .startWhile_{}\n", uid).as_str());
@@ -125,53 +777,137 @@ fn gen_while_loop(condition: &Expr, body: &Vec<Stmt>, directory: &Directory) ->
     Ok(output)
 }
 
-fn gen_assign(target: &Expr, value: &Expr, directory: &Directory) -> GenResult {
+fn gen_assign(target: &Expr, value: &Expr, ctx: Context) -> GenResult {
     match target {
-        Expr::Variable { name } => gen_assign_variable(name, value, directory),
-        Expr::Indexed { name, index } => gen_assign_indexed(name, index, value, directory),
-        Expr::Literal { token, .. } => Err(error(token.line, "Cannot assign to non-variable.")),
-        Expr::Binary { operator, .. } => Err(error(operator.line, "Cannot assign to non-variable.")),
+        Expr::Variable { name } => gen_assign_variable(name, value, ctx),
+        Expr::Indexed { name, index, field } => gen_assign_indexed(name, index, field.as_ref(), value, ctx),
+        Expr::Literal { token, .. } => Err(error(token.span, "Cannot assign to non-variable.")),
+        Expr::Binary { operator, .. } => Err(error(operator.span, "Cannot assign to non-variable.")),
+        Expr::Call { callee, .. } => Err(error(callee.span, "Cannot assign to non-variable.")),
     }
 }
 
-fn gen_assign_variable(target: &Token, value: &Expr, directory: &Directory) -> GenResult {
-    let def = lookup(&target.lexeme, directory, target.line)?;
+fn gen_assign_variable(target: &Token, value: &Expr, ctx: Context) -> GenResult {
+    let def = ctx.lookup(target)?;
 
-    // Can't assign to functions...
-    if *def == ValueType::Function {
-        return Err(error(target.line, "Cannot assign to function"));
-    }
+    let store = match def {
+        ValueType::UnsignedByte {
+            memory: Memory::Rom0 | Memory::RomX,
+            ..
+        } => return Err(error(target.span, "Cannot assign to ROM data")),
+        ValueType::UnsignedByte {
+            memory: Memory::Hram, ..
+        } => format!("\tldh [{}], a\n", target.lexeme),
+        ValueType::UnsignedByte { memory, bank, .. } if in_wram_bank(*memory, *bank) => {
+            let mut store = String::from("\tld e, a\n");
+            store.push_str(
+                gen_switch_wram_bank(target, format!("\tld a, e\n\tld [{}], a\n", target.lexeme).as_str()).as_str(),
+            );
+            store
+        }
+        ValueType::UnsignedByte { .. } => format!("\tld [{}], a\n", target.lexeme),
+        ValueType::Register { address } => gen_store_address(*address),
+        // Can't assign to functions...
+        ValueType::Function { .. } => return Err(error(target.span, "Cannot assign to function")),
+        ValueType::Constant { .. } => return Err(error(target.span, "Cannot assign to constant")),
+        ValueType::State { .. } => {
+            return Err(error(
+                target.span,
+                format!("Cannot assign to {}, it is read-only", target.lexeme).as_str(),
+            ))
+        }
+        ValueType::Region { .. } | ValueType::Sprites => {
+            return Err(error(
+                target.span,
+                "Cannot assign to memory region (index into it instead)",
+            ))
+        }
+    };
 
     // Evaluate expression into a, then store into memory
-    let mut output = gen_evaluate(value, directory)?;
-    output.push_str(format!("\tld [{}], a\n", target.lexeme).as_str());
+    let mut output = gen_evaluate(value, ctx)?;
+    output.push_str(store.as_str());
 
     Ok(output)
 }
 
-fn gen_assign_indexed(name: &Token, index: &Box<Expr>, value: &Expr, directory: &Directory) -> GenResult {
-    // Load indexed pointer into hl, evaluate new value into a, then set.
-    let mut output = gen_indexed(name, index, directory)?;
-    output.push_str(gen_evaluate(value, directory)?.as_str());
-    output.push_str("\tld [hl], a\n");
+fn gen_assign_indexed(name: &Token, index: &Expr, field: Option<&Token>, value: &Expr, ctx: Context) -> GenResult {
+    let store = match ctx.lookup(name)? {
+        ValueType::UnsignedByte {
+            memory: Memory::Rom0 | Memory::RomX,
+            ..
+        } => return Err(error(name.span, "Cannot assign to ROM data")),
+        ValueType::UnsignedByte { memory, bank, .. } if in_wram_bank(*memory, *bank) => {
+            let mut store = String::from("\tld e, a\n");
+            store.push_str(gen_switch_wram_bank(name, "\tld [hl], e\n").as_str());
+            store
+        }
+        _ => String::from("\tld [hl], a\n"),
+    };
+
+    // Evaluate the new value first and keep it on the stack, since reading it can need hl, then load the indexed
+    // pointer into hl and set.
+    let mut output = gen_evaluate(value, ctx)?;
+    output.push_str("\tpush af\n");
+    output.push_str(gen_indexed(name, index, field, ctx)?.as_str());
+    output.push_str("\tpop af\n");
+    output.push_str(store.as_str());
 
     Ok(output)
 }
 
-fn gen_expression(expr: &Expr, directory: &Directory) -> GenResult {
-    gen_evaluate(expr, directory)
+fn gen_expression(expr: &Expr, ctx: Context) -> GenResult {
+    match expr {
+        Expr::Call { callee, arguments } => gen_call(callee, arguments, ctx),
+        _ => gen_evaluate(expr, ctx),
+    }
+}
+
+fn gen_call(callee: &Token, arguments: &[Expr], ctx: Context) -> GenResult {
+    if let Some(result) = intrinsics::gen_intrinsic(callee, arguments, ctx) {
+        return result;
+    }
+
+    let ValueType::Function { memory, bank } = ctx.lookup(callee)? else {
+        return Err(error(
+            callee.span,
+            format!("Cannot call {}, it is not a function", callee.lexeme).as_str(),
+        ));
+    };
+    if !arguments.is_empty() {
+        return Err(error(
+            callee.span,
+            "Passing arguments to functions is not supported yet",
+        ));
+    }
+
+    if ctx.is_mapped(*memory, *bank) {
+        return Ok(format!("\tcall {}\n", callee.lexeme));
+    }
+
+    // Calls into another bank go through a trampoline in ROM0, which stays mapped while the banks are switched.
+    check_bank_switching(callee, ctx)?;
+    ctx.far_calls.borrow_mut().insert(callee.lexeme.clone());
+    Ok(format!("\tcall tgFar_{}\n", callee.lexeme))
 }
 
 fn gen_halt() -> String {
     String::from("\thalt\n")
 }
 
-fn gen_evaluate(expr: &Expr, directory: &Directory) -> GenResult {
+fn gen_evaluate(expr: &Expr, ctx: Context) -> GenResult {
     match expr {
         Expr::Literal { value, .. } => Ok(gen_evaluate_literal(value)),
-        Expr::Variable { name } => gen_evaluate_variable(name, directory),
-        Expr::Indexed { name, index } => gen_evaluate_indexed(name, index, directory),
-        Expr::Binary { operator, left, right } => gen_evaluate_binary(operator, left, right, directory),
+        Expr::Variable { name } => gen_evaluate_variable(name, ctx),
+        Expr::Indexed { name, index, field } => gen_evaluate_indexed(name, index, field.as_ref(), ctx),
+        Expr::Binary { operator, left, right } => gen_evaluate_binary(operator, left, right, ctx),
+        Expr::Call { callee, arguments } if callee.lexeme == "sizeof" => {
+            intrinsics::gen_size_of(callee, arguments, ctx)
+        }
+        Expr::Call { callee, .. } => Err(error(
+            callee.span,
+            format!("{} does not return a value", callee.lexeme).as_str(),
+        )),
     }
 }
 
@@ -179,58 +915,239 @@ fn gen_evaluate_literal(value: &u8) -> String {
     format!("\tld a, {}\n", value)
 }
 
-fn gen_evaluate_variable(name: &Token, directory: &Directory) -> GenResult {
-    let _ = lookup(&name.lexeme, directory, name.line)?;
+fn gen_evaluate_variable(name: &Token, ctx: Context) -> GenResult {
+    let def = ctx.lookup(name)?;
 
     // Is it allowed to load the value of a function here? Maybe for function pointers...
     // (which will require support for 16-bit loads too)
 
-    Ok(format!("\tld a, [{}]\n", name.lexeme))
+    match def {
+        ValueType::Register { address } => Ok(gen_load_address(*address)),
+        ValueType::Constant { value } => Ok(gen_evaluate_literal(value)),
+        ValueType::State { label } => Ok(format!("\tld a, [{}]\n", label)),
+        ValueType::Region { .. } | ValueType::Sprites => Err(error(
+            name.span,
+            "Cannot use memory region as a value (index into it instead)",
+        )),
+        ValueType::UnsignedByte {
+            memory: Memory::Hram, ..
+        } => Ok(format!("\tldh a, [{}]\n", name.lexeme)),
+        ValueType::UnsignedByte { memory, bank, .. } if !ctx.is_mapped(*memory, *bank) => {
+            let mut output = format!("\tld hl, {}\n", name.lexeme);
+            output.push_str(gen_banked_read(name, *bank, ctx)?.as_str());
+            Ok(output)
+        }
+        ValueType::UnsignedByte { memory, bank, .. } if in_wram_bank(*memory, *bank) => {
+            let mut output = format!("\tld hl, {}\n", name.lexeme);
+            output.push_str(gen_wram_read(name).as_str());
+            Ok(output)
+        }
+        _ => Ok(format!("\tld a, [{}]\n", name.lexeme)),
+    }
+}
+
+/// Reads the byte at hl from ROM data in another bank, switching back to the current bank afterwards.
+fn gen_banked_read(name: &Token, bank: Option<u16>, ctx: Context) -> GenResult {
+    let mut output = gen_switch_bank(name, bank, String::from("\tld e, [hl]\n"), ctx)?;
+    output.push_str("\tld a, e\n");
+    Ok(output)
 }
 
-fn gen_evaluate_indexed(name: &Token, index: &Box<Expr>, directory: &Directory) -> GenResult {
-    let mut output = gen_indexed(name, index, directory)?;
-    output.push_str("\tld a, [hl]\n");
+/// Runs `body` with the bank holding `name` mapped in, preserving every register but a.
+fn gen_switch_bank(name: &Token, bank: Option<u16>, body: String, ctx: Context) -> GenResult {
+    // Switching banks from banked code would pull the code itself out from under the CPU.
+    if let (Some(current), Some(bank)) = (ctx.bank, bank) {
+        return Err(error(
+            name.span,
+            format!(
+                "Cannot read {} in ROM bank {} from code in ROM bank {}",
+                name.lexeme, bank, current
+            )
+            .as_str(),
+        ));
+    }
+
+    check_bank_switching(name, ctx)?;
+
+    let mut output = String::from("\tldh a, [hCurrentBank]\n");
+    output.push_str("\tpush af\n");
+    output.push_str(format!("\tld a, BANK({})\n", name.lexeme).as_str());
+    output.push_str("\tcall tgSetBank\n");
+    output.push_str(body.as_str());
+    output.push_str("\tpop af\n");
+    output.push_str("\tcall tgSetBank\n");
     Ok(output)
 }
 
-fn gen_evaluate_binary(operator: &Token, left: &Box<Expr>, right: &Box<Expr>, directory: &Directory) -> GenResult {
+/// The bank in use is saved before switching and restored after, so it has to be set up by the startup code first.
+fn check_bank_switching(name: &Token, ctx: Context) -> Result<(), Diagnostic> {
+    if ctx.options.startup {
+        Ok(())
+    } else {
+        Err(error(
+            name.span,
+            format!(
+                "Switching ROM banks to reach {} needs the startup code to set the current bank",
+                name.lexeme
+            )
+            .as_str(),
+        ))
+    }
+}
+
+/// Whether a WRAMX variable is in one of the CGB's extra banks, which are only mapped while it's switched to them.
+fn in_wram_bank(memory: Memory, bank: Option<u16>) -> bool {
+    memory == Memory::WramX && bank.is_some_and(|bank| bank > 1)
+}
+
+/// Runs `body` with the WRAMX bank holding `name` mapped in, preserving every register but a. Unlike the MBC, SVBK
+/// can be read back, so the bank mapped before is restored without having to keep track of it.
+fn gen_switch_wram_bank(name: &Token, body: &str) -> String {
+    let mut output = String::from("\tldh a, [$FF70]\n");
+    output.push_str("\tpush af\n");
+    output.push_str(format!("\tld a, BANK({})\n", name.lexeme).as_str());
+    output.push_str("\tldh [$FF70], a\n");
+    output.push_str(body);
+    output.push_str("\tpop af\n");
+    output.push_str("\tldh [$FF70], a\n");
+    output
+}
+
+/// Reads the byte at hl from a variable in another WRAMX bank.
+fn gen_wram_read(name: &Token) -> String {
+    let mut output = gen_switch_wram_bank(name, "\tld e, [hl]\n");
+    output.push_str("\tld a, e\n");
+    output
+}
+
+fn gen_load_address(address: u16) -> String {
+    if hardware::is_high_page(address) {
+        format!("\tldh a, [${:04X}]\n", address)
+    } else {
+        format!("\tld a, [${:04X}]\n", address)
+    }
+}
+
+fn gen_store_address(address: u16) -> String {
+    if hardware::is_high_page(address) {
+        format!("\tldh [${:04X}], a\n", address)
+    } else {
+        format!("\tld [${:04X}], a\n", address)
+    }
+}
+
+fn gen_evaluate_indexed(name: &Token, index: &Expr, field: Option<&Token>, ctx: Context) -> GenResult {
+    let mut output = gen_indexed(name, index, field, ctx)?;
+    match ctx.lookup(name)? {
+        ValueType::UnsignedByte { memory, bank, .. } if !ctx.is_mapped(*memory, *bank) => {
+            output.push_str(gen_banked_read(name, *bank, ctx)?.as_str())
+        }
+        ValueType::UnsignedByte { memory, bank, .. } if in_wram_bank(*memory, *bank) => {
+            output.push_str(gen_wram_read(name).as_str())
+        }
+        _ => output.push_str("\tld a, [hl]\n"),
+    }
+    Ok(output)
+}
+
+fn gen_evaluate_binary(operator: &Token, left: &Expr, right: &Expr, ctx: Context) -> GenResult {
     // Until we figure out how to juggle registers, binary expressions cannot be nested.
 
     // Evaluate left into a, store in c.
-    let mut output = gen_evaluate(left, directory)?;
+    let mut output = gen_evaluate(left, ctx)?;
     output.push_str("\tld d, a\n");
 
     // Evaluate right into a, store in b, get left back into a.
-    output.push_str(gen_evaluate(right, directory)?.as_str());
+    output.push_str(gen_evaluate(right, ctx)?.as_str());
     output.push_str("\tld b, a\n");
     output.push_str("\tld a, d\n");
 
     let op = match operator.kind {
         TokenKind::Plus => "\tadd a, b\n",
         TokenKind::Minus => "\tsub a, b\n",
-        _ => return Err(error(operator.line, "Unexpected operator in binary expression.")),
+        TokenKind::Ampersand => "\tand a, b\n",
+        TokenKind::Pipe => "\tor a, b\n",
+        _ => return Err(error(operator.span, "Unexpected operator in binary expression.")),
     };
     output.push_str(op);
 
     Ok(output)
 }
 
-fn gen_indexed(name: &Token, index: &Box<Expr>, directory: &Directory) -> GenResult {
-    let def = lookup(&name.lexeme, directory, name.line)?;
+fn gen_indexed(name: &Token, index: &Expr, field: Option<&Token>, ctx: Context) -> GenResult {
+    let def = ctx.lookup(name)?;
 
-    // Cannot index function pointer
-    if *def == ValueType::Function {
-        return Err(error(name.line, "Cannot index a function identifier"));
+    if let Some(field) = field.filter(|_| *def != ValueType::Sprites) {
+        return Err(error(
+            field.span,
+            format!("{} has no field {}", name.lexeme, field.lexeme).as_str(),
+        ));
     }
 
+    let base = match def {
+        // Cannot index function pointer
+        ValueType::Function { .. } => return Err(error(name.span, "Cannot index a function identifier")),
+        ValueType::Register { .. } | ValueType::Constant { .. } => {
+            return Err(error(name.span, "Cannot index a hardware register or constant"))
+        }
+        ValueType::State { .. } => return Err(error(name.span, format!("Cannot index {}", name.lexeme).as_str())),
+        ValueType::Region { address, size } => {
+            if let Expr::Literal { value, token } = index {
+                if u16::from(*value) >= *size {
+                    return Err(error(token.span, "Index out of bounds for memory region"));
+                }
+            }
+            format!("${:04X}", address)
+        }
+        ValueType::UnsignedByte { .. } => name.lexeme.clone(),
+        ValueType::Sprites => return gen_sprite_field(name, index, field, ctx),
+    };
+
     // This assumes that the expression will evaluate to an 8-bit value
     // meaning we can only access up to elements 255 of an array.
     // This will definitely need rethinking!
-    let mut output = gen_evaluate(index, directory)?;
+    let mut output = gen_evaluate(index, ctx)?;
     output.push_str("\tld b, 0\n\tld c, a\n");
-    output.push_str(format!("\tld hl, {}\n", name.lexeme).as_str());
+    output.push_str(format!("\tld hl, {}\n", base).as_str());
     output.push_str("\tadd hl, bc\n");
 
     Ok(output)
 }
+
+/// Points hl at a field of a sprite in the shadow OAM. The buffer is aligned to 256 bytes, so only the low byte of
+/// the address has to be worked out.
+fn gen_sprite_field(name: &Token, index: &Expr, field: Option<&Token>, ctx: Context) -> GenResult {
+    let Some(field) = field else {
+        return Err(error(
+            name.span,
+            format!("Sprites in {} are accessed by field (y, x, tile or flags)", name.lexeme).as_str(),
+        ));
+    };
+    let offset = match field.lexeme.as_str() {
+        "y" => 0,
+        "x" => 1,
+        "tile" => 2,
+        "flags" => 3,
+        _ => {
+            return Err(error(
+                field.span,
+                format!("Sprites have no field {} (expected y, x, tile or flags)", field.lexeme).as_str(),
+            ))
+        }
+    };
+
+    if let Some(sprite) = constant(index, ctx) {
+        if sprite >= SPRITE_COUNT {
+            return Err(error(index.span(), "Index out of bounds for oam (40 sprites)"));
+        }
+        return Ok(format!("\tld hl, wShadowOam + {}\n", sprite * 4 + offset));
+    }
+
+    let mut output = gen_evaluate(index, ctx)?;
+    output.push_str("\tadd a\n\tadd a\n");
+    if offset > 0 {
+        output.push_str(format!("\tadd a, {}\n", offset).as_str());
+    }
+    output.push_str("\tld l, a\n\tld h, HIGH(wShadowOam)\n");
+    Ok(output)
+}
@@ -0,0 +1,451 @@
+use super::*;
+
+/// Lengths up to this many bytes are cheaper to unroll inline than to set up a call into the runtime.
+const UNROLL_LIMIT: u16 = 8;
+
+/// Generates a call to a built-in function, or `None` if `callee` isn't one of [`analysis::INTRINSICS`].
+pub(super) fn gen_intrinsic(callee: &Token, arguments: &[Expr], ctx: Context) -> Option<GenResult> {
+    let result = match callee.lexeme.as_str() {
+        "copy" => gen_copy(callee, arguments, false, ctx),
+        "vram_copy" => gen_copy(callee, arguments, true, ctx),
+        "fill" => gen_fill(callee, arguments, ctx),
+        "zero" => gen_zero(callee, arguments, ctx),
+        "sizeof" => gen_size_of(callee, arguments, ctx),
+        "wait_vblank" => {
+            check_arguments(callee, arguments, "wait_vblank()", &[0]).map(|_| String::from("\tcall tgWaitVBlank\n"))
+        }
+        "lcd_off" => check_arguments(callee, arguments, "lcd_off()", &[0]).map(|_| String::from("\tcall tgLcdOff\n")),
+        "lcd_on" => check_arguments(callee, arguments, "lcd_on()", &[0])
+            .map(|_| String::from("\tldh a, [$FF40]\n\tset 7, a\n\tldh [$FF40], a\n")),
+        "joypad.update" => {
+            check_arguments(callee, arguments, "joypad.update()", &[0]).map(|_| String::from("\tcall tgReadJoypad\n"))
+        }
+        "oam_dma" => gen_oam_dma(callee, arguments, ctx),
+        "sound.play" => gen_play_sound(callee, arguments, ctx),
+        "bg_palette" => gen_palette(callee, arguments, 0x68, ctx),
+        "obj_palette" => gen_palette(callee, arguments, 0x6A, ctx),
+        "vram_bank" => gen_select_bank(callee, arguments, 0x4F, 0..=1, ctx),
+        "wram_bank" => gen_select_bank(callee, arguments, 0x70, 1..=7, ctx),
+        "cgb_double_speed" => check_arguments(callee, arguments, "cgb_double_speed()", &[0])
+            .and_then(|_| check_cgb(callee, ctx))
+            .map(|_| String::from("\tcall tgDoubleSpeed\n")),
+        "sound.update" => {
+            check_arguments(callee, arguments, "sound.update()", &[0]).map(|_| String::from("\tcall tgUpdateSound\n"))
+        }
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Somewhere in memory that an intrinsic reads from or writes to.
+struct Buffer {
+    name: Token,
+    address: String,
+    size: u16,
+    /// Whether the buffer lives in VRAM, which is off limits while the PPU is drawing.
+    in_vram: bool,
+    /// Set for ROM data that has to be switched in before it can be read.
+    bank: Option<Option<u16>>,
+    /// Set for variables in the CGB's extra WRAMX banks, which have to be switched in to be read or written.
+    wram_bank: Option<u16>,
+}
+
+enum Length {
+    Constant(u16),
+    /// Evaluated into a at runtime.
+    Dynamic(String),
+}
+
+fn check_arguments(callee: &Token, arguments: &[Expr], usage: &str, counts: &[usize]) -> Result<(), Diagnostic> {
+    if counts.contains(&arguments.len()) {
+        Ok(())
+    } else {
+        Err(error(
+            callee.span,
+            format!("Wrong number of arguments, expected {}", usage).as_str(),
+        ))
+    }
+}
+
+fn buffer(callee: &Token, argument: &Expr, writable: bool, ctx: Context) -> Result<Buffer, Diagnostic> {
+    let Expr::Variable { name } = argument else {
+        return Err(error(
+            argument.span(),
+            format!("{} expects a variable or memory region", callee.lexeme).as_str(),
+        ));
+    };
+
+    match ctx.lookup(name)? {
+        ValueType::UnsignedByte {
+            memory: Memory::Rom0 | Memory::RomX,
+            ..
+        } if writable => Err(error(
+            name.span,
+            format!("{} can't write to ROM data", callee.lexeme).as_str(),
+        )),
+        ValueType::UnsignedByte { memory, bank, size } => Ok(Buffer {
+            name: name.clone(),
+            address: name.lexeme.clone(),
+            size: *size,
+            in_vram: false,
+            bank: (!ctx.is_mapped(*memory, *bank)).then_some(*bank),
+            wram_bank: bank.filter(|_| in_wram_bank(*memory, *bank)),
+        }),
+        ValueType::Sprites => Ok(Buffer {
+            name: name.clone(),
+            address: String::from("wShadowOam"),
+            size: SPRITE_COUNT * 4,
+            in_vram: false,
+            bank: None,
+            wram_bank: None,
+        }),
+        ValueType::Region { address, size } => Ok(Buffer {
+            name: name.clone(),
+            address: format!("${:04X}", address),
+            size: *size,
+            in_vram: (0x8000..0xA000).contains(address),
+            bank: None,
+            wram_bank: None,
+        }),
+        _ => Err(error(
+            name.span,
+            format!(
+                "{} expects a variable or memory region, not {}",
+                callee.lexeme, name.lexeme
+            )
+            .as_str(),
+        )),
+    }
+}
+
+/// The number of bytes to work on, which defaults to the size of the buffer when left out.
+fn length(callee: &Token, argument: Option<&Expr>, buffers: &[&Buffer], ctx: Context) -> Result<Length, Diagnostic> {
+    let length = match argument {
+        None => buffers[0].size,
+        Some(expr) => match constant(expr, ctx) {
+            Some(value) => value,
+            None => return Ok(Length::Dynamic(gen_evaluate(expr, ctx)?)),
+        },
+    };
+
+    for buffer in buffers {
+        if length > buffer.size {
+            return Err(error(
+                callee.span,
+                format!(
+                    "{} of {} bytes overruns {} ({} bytes)",
+                    callee.lexeme, length, buffer.name.lexeme, buffer.size
+                )
+                .as_str(),
+            ));
+        }
+    }
+
+    Ok(Length::Constant(length))
+}
+
+/// Loads the size of a buffer into a, for when it's used as a value rather than as a length.
+pub(super) fn gen_size_of(callee: &Token, arguments: &[Expr], ctx: Context) -> GenResult {
+    check_arguments(callee, arguments, "sizeof(variable)", &[1])?;
+    let buffer = buffer(callee, &arguments[0], false, ctx)?;
+    let size = u8::try_from(buffer.size).map_err(|_| {
+        error(
+            callee.span,
+            format!(
+                "sizeof({}) is {} bytes, which is too large for a u8",
+                buffer.name.lexeme, buffer.size
+            )
+            .as_str(),
+        )
+    })?;
+    Ok(gen_evaluate_literal(&size))
+}
+
+/// Loads a runtime length from a into bc, skipping to `.skip_<uid>` when it's zero, which the routines in
+/// core.asm would otherwise treat as 65536.
+fn gen_dynamic_length(evaluate: &str, uid: u32) -> String {
+    let mut output = String::from(evaluate);
+    output.push_str("\tld c, a\n\tld b, 0\n");
+    output.push_str(format!("\tor a\n\tjr z, .skip_{}\n", uid).as_str());
+    output
+}
+
+/// Runs an intrinsic's code with the WRAMX bank its buffers are in mapped, if they're in one of the extra banks.
+/// Only one of those can be mapped at a time.
+fn gen_wram_banks(callee: &Token, buffers: &[&Buffer], output: String) -> GenResult {
+    let mut banked = buffers.iter().filter(|buffer| buffer.wram_bank.is_some());
+    match (banked.next(), banked.next()) {
+        (Some(first), Some(second)) if first.wram_bank != second.wram_bank => Err(error(
+            callee.span,
+            format!(
+                "{} can't reach {} and {} at once, they're in different WRAM banks",
+                callee.lexeme, first.name.lexeme, second.name.lexeme
+            )
+            .as_str(),
+        )),
+        (Some(first), _) if !output.is_empty() => Ok(gen_switch_wram_bank(&first.name, output.as_str())),
+        _ => Ok(output),
+    }
+}
+
+/// Copies between buffers, either as fast as possible or, for VRAM, only while the PPU isn't using it.
+fn gen_copy(callee: &Token, arguments: &[Expr], vram: bool, ctx: Context) -> GenResult {
+    let usage = format!("{}(destination, source[, length])", callee.lexeme);
+    check_arguments(callee, arguments, usage.as_str(), &[2, 3])?;
+    let destination = buffer(callee, &arguments[0], true, ctx)?;
+    let source = buffer(callee, &arguments[1], false, ctx)?;
+    let length = length(callee, arguments.get(2), &[&source, &destination], ctx)?;
+
+    if vram && !destination.in_vram {
+        return Err(error(
+            callee.span,
+            format!(
+                "vram_copy expects a destination in VRAM, not {}",
+                destination.name.lexeme
+            )
+            .as_str(),
+        ));
+    }
+    let routine = if vram { "tgCopyVram" } else { "tgCopyMemory" };
+
+    let mut output = String::new();
+    let mut body = format!("\tld de, {}\n\tld hl, {}\n", source.address, destination.address);
+    let mut skip = None;
+    match length {
+        Length::Constant(0) => return Ok(output),
+        Length::Constant(length) if length <= UNROLL_LIMIT && !vram => {
+            for _ in 0..length {
+                body.push_str("\tld a, [de]\n\tld [hli], a\n\tinc de\n");
+            }
+        }
+        Length::Constant(length) => {
+            output.push_str(format!("\tld bc, {}\n", length).as_str());
+            body.push_str(format!("\tcall {}\n", routine).as_str());
+        }
+        Length::Dynamic(evaluate) => {
+            let uid = get_uid();
+            output.push_str(gen_dynamic_length(&evaluate, uid).as_str());
+            body.push_str(format!("\tcall {}\n", routine).as_str());
+            skip = Some(uid);
+        }
+    }
+
+    match source.bank {
+        Some(bank) => output.push_str(gen_switch_bank(&source.name, bank, body, ctx)?.as_str()),
+        None => output.push_str(body.as_str()),
+    }
+    if let Some(uid) = skip {
+        output.push_str(format!(".skip_{}\n", uid).as_str());
+    }
+
+    gen_wram_banks(callee, &[&destination, &source], output)
+}
+
+fn gen_fill(callee: &Token, arguments: &[Expr], ctx: Context) -> GenResult {
+    check_arguments(callee, arguments, "fill(destination, value[, length])", &[2, 3])?;
+    let destination = buffer(callee, &arguments[0], true, ctx)?;
+    let value = gen_evaluate(&arguments[1], ctx)?;
+    let length = length(callee, arguments.get(2), &[&destination], ctx)?;
+
+    let mut output = String::new();
+    match length {
+        Length::Constant(0) => {}
+        Length::Constant(length) if length <= UNROLL_LIMIT => {
+            output.push_str(value.as_str());
+            output.push_str(format!("\tld hl, {}\n", destination.address).as_str());
+            for _ in 0..length {
+                output.push_str("\tld [hli], a\n");
+            }
+        }
+        Length::Constant(length) => {
+            output.push_str(value.as_str());
+            output.push_str(format!("\tld e, a\n\tld bc, {}\n", length).as_str());
+            output.push_str(format!("\tld hl, {}\n\tcall tgFillMemory\n", destination.address).as_str());
+        }
+        Length::Dynamic(evaluate) => {
+            // The value is kept on the stack while the length is worked out, since either could need any register.
+            let uid = get_uid();
+            output.push_str(value.as_str());
+            output.push_str("\tpush af\n");
+            output.push_str(evaluate.as_str());
+            output.push_str("\tld c, a\n\tld b, 0\n\tpop af\n\tld e, a\n");
+            output.push_str(format!("\tld a, c\n\tor a\n\tjr z, .skip_{}\n", uid).as_str());
+            output.push_str(format!("\tld hl, {}\n\tcall tgFillMemory\n", destination.address).as_str());
+            output.push_str(format!(".skip_{}\n", uid).as_str());
+        }
+    }
+
+    gen_wram_banks(callee, &[&destination], output)
+}
+
+fn gen_zero(callee: &Token, arguments: &[Expr], ctx: Context) -> GenResult {
+    check_arguments(callee, arguments, "zero(destination[, length])", &[1, 2])?;
+    let destination = buffer(callee, &arguments[0], true, ctx)?;
+    let length = length(callee, arguments.get(1), &[&destination], ctx)?;
+
+    let mut output = String::new();
+    match length {
+        Length::Constant(0) => {}
+        Length::Constant(length) if length <= UNROLL_LIMIT => {
+            output.push_str(format!("\txor a\n\tld hl, {}\n", destination.address).as_str());
+            for _ in 0..length {
+                output.push_str("\tld [hli], a\n");
+            }
+        }
+        Length::Constant(length) => {
+            output.push_str(format!("\tld bc, {}\n", length).as_str());
+            output.push_str(format!("\tld hl, {}\n\tcall tgZeroMemory\n", destination.address).as_str());
+        }
+        Length::Dynamic(evaluate) => {
+            let uid = get_uid();
+            output.push_str(gen_dynamic_length(&evaluate, uid).as_str());
+            output.push_str(format!("\tld hl, {}\n\tcall tgZeroMemory\n", destination.address).as_str());
+            output.push_str(format!(".skip_{}\n", uid).as_str());
+        }
+    }
+
+    gen_wram_banks(callee, &[&destination], output)
+}
+
+/// Copies the shadow OAM into OAM, which takes about 160 cycles and is only safe during VBlank.
+fn gen_oam_dma(callee: &Token, arguments: &[Expr], ctx: Context) -> GenResult {
+    check_arguments(callee, arguments, "oam_dma()", &[0])?;
+    if !ctx.options.sprites {
+        return Err(error(callee.span, "oam_dma needs the sprite runtime enabled"));
+    }
+    // The DMA routine runs from HRAM, and only the startup code copies it there.
+    if !ctx.options.startup {
+        return Err(error(
+            callee.span,
+            "oam_dma needs the startup code to install the DMA routine",
+        ));
+    }
+    Ok(String::from("\tld a, HIGH(wShadowOam)\n\tcall hOamDma\n"))
+}
+
+/// Starts a sound effect from a ROM table on one of the four channels, for `sound.update()` to play out.
+fn gen_play_sound(callee: &Token, arguments: &[Expr], ctx: Context) -> GenResult {
+    check_arguments(callee, arguments, "sound.play(effect, channel)", &[2])?;
+    let Expr::Variable { name } = &arguments[0] else {
+        return Err(error(arguments[0].span(), "sound.play expects a rom table"));
+    };
+
+    match ctx.lookup(name)? {
+        // The player reads the effect a frame at a time from wherever the program happens to be, so it can't be
+        // in a bank that might be switched out.
+        ValueType::UnsignedByte {
+            memory: Memory::RomX, ..
+        } if ctx.options.mbc != Mbc::None => {
+            return Err(error(
+                name.span,
+                format!("Sound effect {} must be in ROM0 to be played", name.lexeme).as_str(),
+            ))
+        }
+        ValueType::UnsignedByte {
+            memory: Memory::Rom0 | Memory::RomX,
+            ..
+        } => {}
+        _ => {
+            return Err(error(
+                name.span,
+                format!("sound.play expects a rom table, not {}", name.lexeme).as_str(),
+            ))
+        }
+    }
+
+    let channel = match constant(&arguments[1], ctx) {
+        Some(channel @ 1..=4) => channel,
+        _ => return Err(error(arguments[1].span(), "Sound channel must be 1, 2, 3 or 4")),
+    };
+
+    // Each channel has five registers, starting from NR10 at $FF10.
+    let registers = 0x10 + (channel - 1) * 5;
+    Ok(format!(
+        "\tld hl, {}\n\tld a, ${:02X}\n\tcall tgPlaySound\n",
+        name.lexeme, registers
+    ))
+}
+
+fn check_cgb(callee: &Token, ctx: Context) -> Result<(), Diagnostic> {
+    if ctx.options.cgb {
+        Ok(())
+    } else {
+        Err(error(
+            callee.span,
+            format!("{} needs CGB mode enabled", callee.lexeme).as_str(),
+        ))
+    }
+}
+
+/// Writes palettes of four RGB555 colours, starting from `palette`, through the specification register at
+/// `$FF00 + register` and the data register after it. Like other VRAM writes, this has to happen while the PPU
+/// isn't drawing.
+fn gen_palette(callee: &Token, arguments: &[Expr], register: u8, ctx: Context) -> GenResult {
+    let usage = format!("{}(palette, colours)", callee.lexeme);
+    check_arguments(callee, arguments, usage.as_str(), &[2])?;
+    check_cgb(callee, ctx)?;
+
+    let palette = match constant(&arguments[0], ctx) {
+        Some(palette @ 0..=7) => palette,
+        _ => return Err(error(arguments[0].span(), "Palette must be a number from 0 to 7")),
+    };
+    let colours = buffer(callee, &arguments[1], false, ctx)?;
+    if colours.size == 0 || colours.size % 8 != 0 {
+        return Err(error(
+            colours.name.span,
+            format!(
+                "{} should hold whole palettes of four colours, not {} bytes",
+                colours.name.lexeme, colours.size
+            )
+            .as_str(),
+        ));
+    }
+    if palette * 8 + colours.size > 64 {
+        return Err(error(
+            colours.name.span,
+            format!("{} runs past palette 7", colours.name.lexeme).as_str(),
+        ));
+    }
+
+    // Setting bit 7 of the specification register moves on to the next byte after every write.
+    let mut output = format!(
+        "\tld a, ${:02X}\n\tldh [$FF{:02X}], a\n",
+        0x80 | (palette * 8),
+        register
+    );
+    output.push_str(format!("\tld c, ${:02X}\n\tld b, {}\n", register + 1, colours.size).as_str());
+    let body = format!("\tld hl, {}\n\tcall tgCopyPalette\n", colours.address);
+    match colours.bank {
+        Some(bank) => output.push_str(gen_switch_bank(&colours.name, bank, body, ctx)?.as_str()),
+        None => output.push_str(body.as_str()),
+    }
+
+    gen_wram_banks(callee, &[&colours], output)
+}
+
+/// Maps in one of the CGB's VRAM or WRAMX banks through the select register at `$FF00 + register`.
+fn gen_select_bank(
+    callee: &Token,
+    arguments: &[Expr],
+    register: u8,
+    banks: std::ops::RangeInclusive<u16>,
+    ctx: Context,
+) -> GenResult {
+    let usage = format!("{}(bank)", callee.lexeme);
+    check_arguments(callee, arguments, usage.as_str(), &[1])?;
+    check_cgb(callee, ctx)?;
+
+    match constant(&arguments[0], ctx) {
+        Some(bank) if banks.contains(&bank) => Ok(format!("\tld a, {}\n\tldh [$FF{:02X}], a\n", bank, register)),
+        _ => Err(error(
+            arguments[0].span(),
+            format!(
+                "{} expects a bank from {} to {}",
+                callee.lexeme,
+                banks.start(),
+                banks.end()
+            )
+            .as_str(),
+        )),
+    }
+}
@@ -11,6 +11,17 @@ tgZeroMemory::
   jr nz, .untilAllBytesAreZeroed
   ret
 
+; Sets bc bytes starting from hl to e
+tgFillMemory::
+  .untilAllBytesAreFilled
+    ld [hl], e
+    inc hl
+    dec bc
+    ld a, b
+    or c
+  jr nz, .untilAllBytesAreFilled
+  ret
+
 ; Copies bc bytes from de to hl
 tgCopyMemory::
   .untilAllDataIsCopied
@@ -23,6 +34,41 @@ tgCopyMemory::
   jr nz, .untilAllDataIsCopied
   ret
 
+; Copies bc bytes from de to hl in VRAM, waiting for the PPU to free it up before each write
+tgCopyVram::
+  .untilAllDataIsCopied
+    .untilVramIsAccessible
+      ldh a, [$FF41]
+      and a, %10
+    jr nz, .untilVramIsAccessible
+    ld a, [de]
+    ld [hli], a
+    inc de
+    dec bc
+    ld a, b
+    or c
+  jr nz, .untilAllDataIsCopied
+  ret
+
+; Waits for the start of the next VBlank, returning straight away if the LCD is off
+tgWaitVBlank::
+  ldh a, [$FF40]
+  add a
+  ret nc
+  .untilVBlank
+    ldh a, [$FF44]
+    cp 144
+  jr nz, .untilVBlank
+  ret
+
+; Switches the LCD off, which is only safe to do during VBlank
+tgLcdOff::
+  call tgWaitVBlank
+  ldh a, [$FF40]
+  res 7, a
+  ldh [$FF40], a
+  ret
+
 ; Divides a by b, storing result in c
 tgDivideAB::
   ld c, 0
@@ -39,3 +85,7 @@ tgMultiplyAB::
     add a
     jr tgMultiplyAB
 
+SECTION "Interrupt state", HRAM
+
+; Whether interrupts are enabled, since there's no reading it back from the hardware
+hInterruptsEnabled:: ds 1
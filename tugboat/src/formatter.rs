@@ -0,0 +1,286 @@
+use super::*;
+use lexer::TokenKind::*;
+
+const INDENT: &str = "    ";
+
+/// Reprints source in the canonical style, keeping every token and comment exactly as written. Code that doesn't
+/// parse is left alone and its errors returned instead, since there's no telling how it was meant to be laid out.
+pub fn format(contents: String) -> Result<String, Vec<Diagnostic>> {
+    let (tokens, mut errors) = lexer::lex_with_comments(contents.clone());
+    let code: Vec<Token> = tokens.iter().filter(|token| token.kind != Comment).cloned().collect();
+    let (_, parser_errors) = parser::parse(code);
+
+    errors.extend(parser_errors);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut printer = Printer::new(&contents);
+    for (index, token) in tokens.iter().enumerate() {
+        printer.print(token, tokens.get(index + 1));
+    }
+    Ok(printer.finish())
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Brace {
+    /// A block of statements, with one statement to a line.
+    Block,
+    /// An initialiser's values, which keep the line breaks they were written with.
+    List,
+}
+
+struct Printer<'a> {
+    source: &'a str,
+    output: String,
+    braces: Vec<Brace>,
+    depth: usize,
+    /// The last token printed other than a comment.
+    previous: Option<&'a Token>,
+    /// The line of the last token printed, comments included.
+    last_line: u32,
+    /// Whether the line ends before the next token, unless that is a comment trailing it.
+    line_break: bool,
+    /// Whether a declaration or statement has been started but not finished, so a broken line is indented further.
+    continuing: bool,
+    /// Whether nothing has been printed since a block was opened, where blank lines are dropped.
+    block_start: bool,
+    /// Whether the last thing printed was a comment.
+    after_comment: bool,
+}
+
+impl<'a> Printer<'a> {
+    fn new(source: &'a str) -> Printer<'a> {
+        Printer {
+            source,
+            output: String::new(),
+            braces: Vec::new(),
+            depth: 0,
+            previous: None,
+            last_line: 0,
+            line_break: false,
+            continuing: false,
+            block_start: false,
+            after_comment: false,
+        }
+    }
+
+    fn print(&mut self, token: &'a Token, next: Option<&Token>) {
+        if token.kind == EOF {
+            return;
+        }
+
+        let brace = self.brace(token);
+        let closes_block = token.kind == RightBrace && brace == Some(Brace::Block);
+        if closes_block {
+            self.depth -= 1;
+        }
+
+        let text = &self.source[token.span.offset..token.span.offset + token.span.length];
+        let comment = matches!(token.kind, Comment | DocComment);
+        let later_line = token.span.line > self.last_line;
+        let in_list = self.braces.last() == Some(&Brace::List);
+        if self.output.is_empty() {
+        } else if comment && !later_line {
+            self.output.push(' ');
+        } else if self.line_break || later_line && (in_list || comment) {
+            self.output.push('\n');
+            // Keep a single blank line wherever there was at least one, except at either end of a block.
+            if token.span.line > self.last_line + 1 && !self.block_start && !closes_block {
+                self.output.push('\n');
+            }
+            let continuation = self.continuing && !(token.kind == RightBrace && brace == Some(Brace::List));
+            self.output
+                .push_str(&INDENT.repeat(self.depth + usize::from(continuation)));
+            self.line_break = false;
+        } else if self.spaced(token, brace) {
+            self.output.push(' ');
+        }
+
+        // Block comments are printed as written, however many lines they take up.
+        self.output.push_str(text);
+        self.last_line = token.span.line + text.matches('\n').count() as u32;
+        self.block_start &= comment && !later_line;
+        self.after_comment = comment;
+
+        match (&token.kind, brace) {
+            // Code can carry on after a block comment, but never after a line comment.
+            _ if comment => {
+                self.line_break |= text.starts_with("//") || next.is_some_and(|next| next.span.line > self.last_line)
+            }
+            (LeftBrace, Some(Brace::Block)) => {
+                self.braces.push(Brace::Block);
+                self.depth += 1;
+                self.line_break = next.is_some_and(|next| next.kind != RightBrace);
+                self.continuing = false;
+                self.block_start = true;
+            }
+            (LeftBrace, _) => {
+                self.braces.push(Brace::List);
+                self.continuing = true;
+            }
+            (RightBrace, _) => {
+                self.braces.pop();
+                if closes_block {
+                    self.line_break = true;
+                    self.continuing = false;
+                }
+            }
+            (Semicolon, _) => {
+                self.line_break = true;
+                self.continuing = false;
+            }
+            _ => self.continuing = true,
+        }
+        if !comment {
+            self.previous = Some(token);
+        }
+    }
+
+    /// Which kind of brace a token is, if any. Braces straight after `=` hold an initialiser's values.
+    fn brace(&self, token: &Token) -> Option<Brace> {
+        match token.kind {
+            LeftBrace if self.previous.is_some_and(|previous| previous.kind == Equals) => Some(Brace::List),
+            LeftBrace => Some(Brace::Block),
+            RightBrace => self.braces.last().copied(),
+            _ => None,
+        }
+    }
+
+    /// Whether a space separates the token from the one before it on the same line.
+    fn spaced(&self, token: &Token, brace: Option<Brace>) -> bool {
+        if self.after_comment {
+            return true;
+        }
+        let Some(previous) = self.previous else {
+            return false;
+        };
+        // A block's brace is only followed on the same line by the brace closing it, when it's empty.
+        if matches!(previous.kind, At | LeftParen | LeftBracket | LeftBrace | Dot) {
+            return false;
+        }
+
+        match token.kind {
+            RightParen | RightBracket | Comma | Semicolon | Dot => false,
+            RightBrace => brace != Some(Brace::List),
+            LeftParen => !matches!(previous.kind, Identifier | Interrupt | Incbin),
+            LeftBracket => !matches!(previous.kind, Identifier | Unsigned8),
+            _ => true,
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if !self.output.is_empty() {
+            self.output.push('\n');
+        }
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Formats `code`, checking that formatting the result again changes nothing.
+    fn formatted(code: &str) -> String {
+        let output = format(String::from(code)).unwrap();
+        assert_eq!(
+            format(output.clone()).unwrap(),
+            output,
+            "formatting again changed the output"
+        );
+        output
+    }
+
+    #[test]
+    fn canonical_layout() {
+        let code = "@section(\"Game\")   @bank( 2 )rom u8 [ 3 ]table={1 ,hw.LCDCF_ON,$F0} ;\n\
+                    u8 x;hram u8 speed={2};\n\
+                    interrupt( vblank )fn draw ( ) {x=x+1;while(x-5){halt;}atomic{di;ei;}\n}\n\
+                    fn main(){}";
+        assert_eq!(
+            formatted(code),
+            "@section(\"Game\") @bank(2) rom u8[3] table = {1, hw.LCDCF_ON, $F0};\n\
+             u8 x;\n\
+             hram u8 speed = {2};\n\
+             interrupt(vblank) fn draw() {\n    x = x + 1;\n    while (x - 5) {\n        halt;\n    }\n    \
+             atomic {\n        di;\n        ei;\n    }\n}\n\
+             fn main() {}\n"
+        );
+    }
+
+    #[test]
+    fn literals_are_kept_as_written() {
+        assert_eq!(
+            formatted("rom u8[] a={'a',#FF8000,$0f};rom u8[] b=incbin( \"b.bin\",16 );fn main(){x=sprites[0] . y;}"),
+            "rom u8[] a = {'a', #FF8000, $0f};\nrom u8[] b = incbin(\"b.bin\", 16);\n\
+             fn main() {\n    x = sprites[0].y;\n}\n"
+        );
+    }
+
+    #[test]
+    fn comments_are_kept() {
+        let code = "// Header\n\n\n// about x\nu8 x; // trailing\nfn main() { // entry\n\n  x = 1;\n\
+                    // own line\n  while (x) // why\n  { halt; }\n\n}\n// end";
+        assert_eq!(
+            formatted(code),
+            "// Header\n\n// about x\nu8 x; // trailing\nfn main() { // entry\n    x = 1;\n    // own line\n    \
+             while (x) // why\n        {\n        halt;\n    }\n}\n// end\n"
+        );
+    }
+
+    #[test]
+    fn lists_keep_their_line_breaks() {
+        let code = "rom u8[4] tiles = {\n  1, 2, // first\n3,\n      4\n};\nrom u8[2] pair = {\n5, 6};";
+        assert_eq!(
+            formatted(code),
+            "rom u8[4] tiles = {\n    1, 2, // first\n    3,\n    4\n};\nrom u8[2] pair = {\n    5, 6};\n"
+        );
+    }
+
+    #[test]
+    fn blank_lines_are_collapsed() {
+        assert_eq!(
+            formatted("\n\nu8 a;\n\n\n\nu8 b;\nfn main() {\n\n\n  a = b;\n\n\n  b = a;\n\n}\n\n"),
+            "u8 a;\n\nu8 b;\nfn main() {\n    a = b;\n\n    b = a;\n}\n"
+        );
+        assert_eq!(formatted(""), "");
+    }
+
+    #[test]
+    fn tokens_are_unchanged() {
+        let code = "@allow(unused_variable) u8[2] x = {1,2};\nfn main() { x[1] = hw.rLY & 3; copy(x, x, 2); }";
+        let output = formatted(code);
+        let texts = |code: &str| -> Vec<String> {
+            let (tokens, _) = lexer::lex_with_comments(String::from(code));
+            tokens
+                .iter()
+                .map(|token| code[token.span.offset..token.span.offset + token.span.length].to_string())
+                .collect()
+        };
+        assert_eq!(texts(&output), texts(code));
+    }
+
+    #[test]
+    fn block_and_doc_comments() {
+        let code = "/* Header\n   spanning lines */\n///   Lives left.\n@bank(1)  rom u8 lives={3};\n\
+                    fn main() {\n/* before */ lives = /* inline */ lives - 1;  /* after */\n/* a\n   b */\n}";
+        assert_eq!(
+            formatted(code),
+            "/* Header\n   spanning lines */\n///   Lives left.\n@bank(1) rom u8 lives = {3};\n\
+             fn main() {\n    /* before */ lives = /* inline */ lives - 1; /* after */\n    /* a\n   b */\n}\n"
+        );
+    }
+
+    #[test]
+    fn broken_code_is_left_alone() {
+        let errors = format(String::from("fn main() { x = ; }")).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![ExpectedError {
+                msg: String::from("Expected number or identifier in expression."),
+                line: 1,
+            }]
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::path::Path;
+
+/// Tile data converted from an image, along with a map of where each tile appears in it.
+pub struct Tileset {
+    /// 2bpp tile data, 16 bytes for every 8x8 tile.
+    pub tiles: Vec<u8>,
+    /// One tile index for every tile-sized block of the image, in reading order.
+    pub map: Vec<u8>,
+}
+
+/// Converts a PNG into Game Boy 2bpp tiles. Colours are quantised to the four shades by brightness, and tall
+/// mode lays out 8x16 blocks with the top tile first, as 8x16 sprites expect.
+pub fn convert(path: &Path, tall: bool, dedupe: bool) -> Result<Tileset, String> {
+    let image = decode(path)?;
+
+    let block_height = if tall { 16 } else { 8 };
+    if image.width % 8 != 0 || image.height % block_height != 0 {
+        return Err(format!(
+            "{}x{} isn't a whole number of 8x{} tiles",
+            image.width, image.height, block_height
+        ));
+    }
+
+    let mut tileset = Tileset {
+        tiles: Vec::new(),
+        map: Vec::new(),
+    };
+    let mut blocks: Vec<Vec<u8>> = Vec::new();
+
+    for block_y in (0..image.height).step_by(block_height) {
+        for block_x in (0..image.width).step_by(8) {
+            let mut block = Vec::new();
+            for y in block_y..block_y + block_height {
+                block.extend(encode_row(&image, block_x, y));
+            }
+
+            let index = match blocks.iter().position(|existing| dedupe && *existing == block) {
+                Some(index) => index,
+                None => {
+                    blocks.push(block);
+                    blocks.len() - 1
+                }
+            };
+
+            // Map entries count 8x8 tiles, so the index of a tall block is that of its top tile.
+            let tile = index * (block_height / 8);
+            let tile = u8::try_from(tile).map_err(|_| String::from("has more than 256 tiles to map"))?;
+            tileset.map.push(tile);
+        }
+    }
+
+    tileset.tiles = blocks.concat();
+    Ok(tileset)
+}
+
+/// An image reduced to one shade (0-3, lightest first) per pixel.
+struct Image {
+    width: usize,
+    height: usize,
+    shades: Vec<u8>,
+}
+
+fn decode(path: &Path) -> Result<Image, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|err| err.to_string())?;
+
+    let channels = info.color_type.samples();
+    let shades = buffer[..info.buffer_size()]
+        .chunks(channels)
+        .map(|pixel| {
+            let (luma, alpha) = match pixel {
+                [gray] => (u32::from(*gray), 255),
+                [gray, alpha] => (u32::from(*gray), *alpha),
+                [r, g, b] => (luma(*r, *g, *b), 255),
+                [r, g, b, alpha] => (luma(*r, *g, *b), *alpha),
+                _ => (255, 255),
+            };
+            // Transparent pixels use colour 0, which sprites treat as see-through.
+            if alpha < 128 {
+                0
+            } else {
+                ((255 - luma) / 64) as u8
+            }
+        })
+        .collect();
+
+    Ok(Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        shades,
+    })
+}
+
+fn luma(r: u8, g: u8, b: u8) -> u32 {
+    (299 * u32::from(r) + 587 * u32::from(g) + 114 * u32::from(b)) / 1000
+}
+
+/// Encodes one 8 pixel row of a tile as its pair of 2bpp bytes: the low bits of each shade, then the high bits.
+fn encode_row(image: &Image, x: usize, y: usize) -> [u8; 2] {
+    let mut row = [0, 0];
+    for (bit, shade) in image.shades[y * image.width + x..][..8].iter().enumerate() {
+        row[0] |= (shade & 1) << (7 - bit);
+        row[1] |= (shade >> 1) << (7 - bit);
+    }
+    row
+}
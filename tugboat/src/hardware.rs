@@ -0,0 +1,204 @@
+use phf::phf_map;
+
+/// Everything that can be referenced through the built-in `hw` namespace, mirroring the names used by
+/// hardware.inc (minus the leading underscore on memory regions, which isn't a valid identifier here).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Hardware {
+    /// A memory-mapped IO register, read and written as a u8.
+    Register(u16),
+    /// A named bit or value for use with the registers.
+    Constant(u8),
+    /// A block of memory that can be indexed like an array.
+    Region { address: u16, size: u16 },
+}
+
+use Hardware::*;
+
+pub static HARDWARE: phf::Map<&'static str, Hardware> = phf_map! {
+    // Joypad
+    "rP1" => Register(0xFF00),
+    "rJOYP" => Register(0xFF00),
+    "P1F_5" => Constant(0x20),
+    "P1F_4" => Constant(0x10),
+    "P1F_3" => Constant(0x08),
+    "P1F_2" => Constant(0x04),
+    "P1F_1" => Constant(0x02),
+    "P1F_0" => Constant(0x01),
+    "P1F_GET_DPAD" => Constant(0x20),
+    "P1F_GET_BTN" => Constant(0x10),
+    "P1F_GET_NONE" => Constant(0x30),
+    "PADF_DOWN" => Constant(0x80),
+    "PADF_UP" => Constant(0x40),
+    "PADF_LEFT" => Constant(0x20),
+    "PADF_RIGHT" => Constant(0x10),
+    "PADF_START" => Constant(0x08),
+    "PADF_SELECT" => Constant(0x04),
+    "PADF_B" => Constant(0x02),
+    "PADF_A" => Constant(0x01),
+
+    // Serial
+    "rSB" => Register(0xFF01),
+    "rSC" => Register(0xFF02),
+    "SCF_START" => Constant(0x80),
+    "SCF_SPEED" => Constant(0x02),
+    "SCF_SOURCE" => Constant(0x01),
+
+    // Timer
+    "rDIV" => Register(0xFF04),
+    "rTIMA" => Register(0xFF05),
+    "rTMA" => Register(0xFF06),
+    "rTAC" => Register(0xFF07),
+    "TACF_START" => Constant(0x04),
+    "TACF_STOP" => Constant(0x00),
+    "TACF_4KHZ" => Constant(0x00),
+    "TACF_16KHZ" => Constant(0x03),
+    "TACF_65KHZ" => Constant(0x02),
+    "TACF_262KHZ" => Constant(0x01),
+
+    // Interrupts
+    "rIF" => Register(0xFF0F),
+    "rIE" => Register(0xFFFF),
+    "IEF_HILO" => Constant(0x10),
+    "IEF_SERIAL" => Constant(0x08),
+    "IEF_TIMER" => Constant(0x04),
+    "IEF_STAT" => Constant(0x02),
+    "IEF_VBLANK" => Constant(0x01),
+
+    // Audio
+    "rNR10" => Register(0xFF10),
+    "rNR11" => Register(0xFF11),
+    "rNR12" => Register(0xFF12),
+    "rNR13" => Register(0xFF13),
+    "rNR14" => Register(0xFF14),
+    "rNR21" => Register(0xFF16),
+    "rNR22" => Register(0xFF17),
+    "rNR23" => Register(0xFF18),
+    "rNR24" => Register(0xFF19),
+    "rNR30" => Register(0xFF1A),
+    "rNR31" => Register(0xFF1B),
+    "rNR32" => Register(0xFF1C),
+    "rNR33" => Register(0xFF1D),
+    "rNR34" => Register(0xFF1E),
+    "rNR41" => Register(0xFF20),
+    "rNR42" => Register(0xFF21),
+    "rNR43" => Register(0xFF22),
+    "rNR44" => Register(0xFF23),
+    "rNR50" => Register(0xFF24),
+    "rNR51" => Register(0xFF25),
+    "rNR52" => Register(0xFF26),
+    "AUDENA_ON" => Constant(0x80),
+    "AUDENA_OFF" => Constant(0x00),
+    "AUDLEN_DUTY_12_5" => Constant(0x00),
+    "AUDLEN_DUTY_25" => Constant(0x40),
+    "AUDLEN_DUTY_50" => Constant(0x80),
+    "AUDLEN_DUTY_75" => Constant(0xC0),
+    "AUDHIGH_RESTART" => Constant(0x80),
+    "AUDHIGH_LENGTH_ON" => Constant(0x40),
+    "AUDHIGH_LENGTH_OFF" => Constant(0x00),
+    "AUD3ENA_ON" => Constant(0x80),
+    "AUD3ENA_OFF" => Constant(0x00),
+    "AUD3LEVEL_MUTE" => Constant(0x00),
+    "AUD3LEVEL_100" => Constant(0x20),
+    "AUD3LEVEL_50" => Constant(0x40),
+    "AUD3LEVEL_25" => Constant(0x60),
+    "AUDTERM_4_LEFT" => Constant(0x80),
+    "AUDTERM_3_LEFT" => Constant(0x40),
+    "AUDTERM_2_LEFT" => Constant(0x20),
+    "AUDTERM_1_LEFT" => Constant(0x10),
+    "AUDTERM_4_RIGHT" => Constant(0x08),
+    "AUDTERM_3_RIGHT" => Constant(0x04),
+    "AUDTERM_2_RIGHT" => Constant(0x02),
+    "AUDTERM_1_RIGHT" => Constant(0x01),
+    "waveRam" => Region { address: 0xFF30, size: 16 },
+
+    // LCD
+    "rLCDC" => Register(0xFF40),
+    "rSTAT" => Register(0xFF41),
+    "rSCY" => Register(0xFF42),
+    "rSCX" => Register(0xFF43),
+    "rLY" => Register(0xFF44),
+    "rLYC" => Register(0xFF45),
+    "rDMA" => Register(0xFF46),
+    "rBGP" => Register(0xFF47),
+    "rOBP0" => Register(0xFF48),
+    "rOBP1" => Register(0xFF49),
+    "rWY" => Register(0xFF4A),
+    "rWX" => Register(0xFF4B),
+    "LCDCF_OFF" => Constant(0x00),
+    "LCDCF_ON" => Constant(0x80),
+    "LCDCF_WIN9800" => Constant(0x00),
+    "LCDCF_WIN9C00" => Constant(0x40),
+    "LCDCF_WINOFF" => Constant(0x00),
+    "LCDCF_WINON" => Constant(0x20),
+    "LCDCF_BG8800" => Constant(0x00),
+    "LCDCF_BG8000" => Constant(0x10),
+    "LCDCF_BG9800" => Constant(0x00),
+    "LCDCF_BG9C00" => Constant(0x08),
+    "LCDCF_OBJ8" => Constant(0x00),
+    "LCDCF_OBJ16" => Constant(0x04),
+    "LCDCF_OBJOFF" => Constant(0x00),
+    "LCDCF_OBJON" => Constant(0x02),
+    "LCDCF_BGOFF" => Constant(0x00),
+    "LCDCF_BGON" => Constant(0x01),
+    "STATF_LYC" => Constant(0x40),
+    "STATF_MODE10" => Constant(0x20),
+    "STATF_MODE01" => Constant(0x10),
+    "STATF_MODE00" => Constant(0x08),
+    "STATF_LYCF" => Constant(0x04),
+    "STATF_HBL" => Constant(0x00),
+    "STATF_VBL" => Constant(0x01),
+    "STATF_OAM" => Constant(0x02),
+    "STATF_LCD" => Constant(0x03),
+    "STATF_BUSY" => Constant(0x02),
+    "OAMF_PRI" => Constant(0x80),
+    "OAMF_YFLIP" => Constant(0x40),
+    "OAMF_XFLIP" => Constant(0x20),
+    "OAMF_PAL0" => Constant(0x00),
+    "OAMF_PAL1" => Constant(0x10),
+    "OAMF_BANK0" => Constant(0x00),
+    "OAMF_BANK1" => Constant(0x08),
+
+    // Game Boy Color
+    "rKEY1" => Register(0xFF4D),
+    "rVBK" => Register(0xFF4F),
+    "rHDMA1" => Register(0xFF51),
+    "rHDMA2" => Register(0xFF52),
+    "rHDMA3" => Register(0xFF53),
+    "rHDMA4" => Register(0xFF54),
+    "rHDMA5" => Register(0xFF55),
+    "rRP" => Register(0xFF56),
+    "rBCPS" => Register(0xFF68),
+    "rBCPD" => Register(0xFF69),
+    "rOCPS" => Register(0xFF6A),
+    "rOCPD" => Register(0xFF6B),
+    "rOPRI" => Register(0xFF6C),
+    "rSVBK" => Register(0xFF70),
+    "rPCM12" => Register(0xFF76),
+    "rPCM34" => Register(0xFF77),
+    "KEY1F_DBLSPEED" => Constant(0x80),
+    "KEY1F_PREPARE" => Constant(0x01),
+    "HDMA5F_MODE_GP" => Constant(0x00),
+    "HDMA5F_MODE_HBL" => Constant(0x80),
+    "HDMA5F_BUSY" => Constant(0x80),
+    "RPF_ENREAD" => Constant(0xC0),
+    "RPF_DATAIN" => Constant(0x02),
+    "RPF_WRITE_HI" => Constant(0x01),
+    "RPF_WRITE_LO" => Constant(0x00),
+    "BCPSF_AUTOINC" => Constant(0x80),
+    "OCPSF_AUTOINC" => Constant(0x80),
+
+    // Memory regions
+    "vram" => Region { address: 0x8000, size: 0x2000 },
+    "tiles0" => Region { address: 0x8000, size: 0x0800 },
+    "tiles1" => Region { address: 0x8800, size: 0x0800 },
+    "tiles2" => Region { address: 0x9000, size: 0x0800 },
+    "tileData" => Region { address: 0x9000, size: 0x0800 },
+    "screen0" => Region { address: 0x9800, size: 0x0400 },
+    "screen1" => Region { address: 0x9C00, size: 0x0400 },
+    "oam" => Region { address: 0xFE00, size: 0x00A0 },
+};
+
+/// Registers in the $FF00 page can use the shorter and faster `ldh` instructions.
+pub fn is_high_page(address: u16) -> bool {
+    address >= 0xFF00
+}
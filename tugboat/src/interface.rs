@@ -0,0 +1,234 @@
+use super::*;
+use lexer::TokenKind;
+use lexer::TokenKind::*;
+use std::fmt;
+
+/// The exported surface of a translation unit. Other units are checked against this summary rather than
+/// against the implementation itself, so it only records what is needed to reference each symbol.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Interface {
+    pub symbols: Vec<Symbol>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SymbolKind {
+    Variable { size: u16, placement: Placement },
+    Function { placement: Placement },
+}
+
+impl Interface {
+    pub fn from_ast(ast: &[Declaration]) -> Interface {
+        let mut symbols: Vec<Symbol> = Vec::new();
+
+        for dec in ast.iter() {
+            match dec {
+                Declaration::Variable {
+                    name,
+                    size,
+                    public: true,
+                    placement,
+                    ..
+                } => symbols.push(Symbol {
+                    name: name.lexeme.clone(),
+                    kind: SymbolKind::Variable {
+                        size: size.unwrap_or(0),
+                        placement: summarise(placement),
+                    },
+                }),
+                Declaration::Function {
+                    name,
+                    public: true,
+                    placement,
+                    ..
+                } => symbols.push(Symbol {
+                    name: name.lexeme.clone(),
+                    kind: SymbolKind::Function {
+                        placement: summarise(placement),
+                    },
+                }),
+                _ => {}
+            }
+        }
+
+        Interface { symbols }
+    }
+
+    /// Reads back an interface summary written out by the `Display` implementation.
+    pub fn parse(contents: &str) -> Result<Interface, Vec<Diagnostic>> {
+        let (tokens, errors) = lexer::lex(contents.to_string());
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut queue: VecDeque<_> = tokens.into_iter().collect();
+        let mut symbols: Vec<Symbol> = Vec::new();
+        let mut errors: Vec<Diagnostic> = Vec::new();
+
+        while queue.front().is_some_and(|t| t.kind != EOF) {
+            match symbol(&mut queue) {
+                Ok(symbol) => symbols.push(symbol),
+                Err(err) => {
+                    errors.push(err);
+                    while queue.front().is_some_and(|t| t.kind != EOF && t.kind != Semicolon) {
+                        queue.pop_front();
+                    }
+                    queue.pop_front();
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(Interface { symbols })
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Other units only need to know which memory and bank a symbol is in to reference it.
+fn summarise(placement: &Placement) -> Placement {
+    Placement {
+        memory: placement.memory,
+        bank: placement.bank,
+        ..Placement::default()
+    }
+}
+
+fn symbol(queue: &mut VecDeque<Token>) -> Result<Symbol, Diagnostic> {
+    let attributes = parser::attributes(queue)?;
+    expect(queue, Pub, "Expected 'pub' at start of interface entry.")?;
+
+    let token = expect_any(queue)?;
+    let default = match token.kind {
+        Fn => Memory::Rom0,
+        Unsigned8 => Memory::Wram0,
+        Hram => {
+            expect(queue, Unsigned8, "Expected a type after 'hram'.")?;
+            Memory::Hram
+        }
+        Rom => {
+            expect(queue, Unsigned8, "Expected a type after 'rom'.")?;
+            Memory::Rom0
+        }
+        _ => return Err(error(token.span, "Expected 'fn' or a type in interface entry.")),
+    };
+    let placement = parser::placement(&attributes, default)?;
+
+    let mut size = 1;
+    if token.kind != Fn && queue.front().is_some_and(|t| t.kind == LeftBracket) {
+        queue.pop_front();
+        let size_token = expect(queue, Number, "Expected array size specifier.")?;
+        expect(queue, RightBracket, "Expected ']' ending array definition.")?;
+        size = size_token
+            .value
+            .ok_or(error(size_token.span, "Invalid array size in interface entry."))?;
+    }
+
+    let name = expect(queue, Identifier, "Expected symbol name.")?;
+    let kind = if token.kind == Fn {
+        expect(queue, LeftParen, "Expected '(' after function name.")?;
+        expect(queue, RightParen, "Expected ')' after function name.")?;
+        SymbolKind::Function { placement }
+    } else {
+        SymbolKind::Variable { size, placement }
+    };
+    expect(queue, Semicolon, "Expected ';' after interface entry.")?;
+
+    Ok(Symbol {
+        name: name.lexeme,
+        kind,
+    })
+}
+
+fn error(span: Span, msg: &str) -> Diagnostic {
+    Diagnostic::error(span, msg)
+}
+
+fn expect_any(queue: &mut VecDeque<Token>) -> Result<Token, Diagnostic> {
+    queue
+        .pop_front()
+        .ok_or(error(Span::default(), "Unexpected end of interface."))
+}
+
+fn expect(queue: &mut VecDeque<Token>, kind: TokenKind, msg: &str) -> Result<Token, Diagnostic> {
+    let token = expect_any(queue)?;
+    if token.kind == kind {
+        Ok(token)
+    } else {
+        Err(error(token.span, msg))
+    }
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for symbol in self.symbols.iter() {
+            let placement = match &symbol.kind {
+                SymbolKind::Variable { placement, .. } => placement,
+                SymbolKind::Function { placement } => placement,
+            };
+
+            match (placement.memory, placement.bank) {
+                (Memory::WramX, Some(bank)) => write!(f, "@wramx({}) ", bank)?,
+                (Memory::WramX, None) => write!(f, "@wramx ")?,
+                (_, Some(bank)) => write!(f, "@bank({}) ", bank)?,
+                _ => {}
+            }
+            write!(f, "pub ")?;
+
+            match symbol.kind {
+                SymbolKind::Variable { size, .. } => {
+                    match placement.memory {
+                        Memory::Hram => write!(f, "hram ")?,
+                        Memory::Rom0 | Memory::RomX => write!(f, "rom ")?,
+                        _ => {}
+                    }
+                    match size {
+                        1 => writeln!(f, "u8 {};", symbol.name)?,
+                        _ => writeln!(f, "u8[{}] {};", size, symbol.name)?,
+                    }
+                }
+                SymbolKind::Function { .. } => writeln!(f, "fn {}();", symbol.name)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interface_round_trip() {
+        let (tokens, _) = lexer::lex(String::from(
+            "pub u8 a; u8 b; pub u8[10] c; fn d() {} pub fn e() {} pub hram u8 f;
+            @bank(3) pub fn g() {} @wramx(2) pub u8 h; @bank(2) pub rom u8[] i = {1, 2};",
+        ));
+        let (ast, _) = parser::parse(tokens);
+
+        let interface = Interface::from_ast(&ast);
+        let summary = interface.to_string();
+
+        assert_eq!(
+            summary,
+            "pub u8 a;\npub u8[10] c;\npub fn e();\npub hram u8 f;\n\
+            @bank(3) pub fn g();\n@wramx(2) pub u8 h;\n@bank(2) pub rom u8[2] i;\n"
+        );
+        assert_eq!(Interface::parse(&summary), Ok(interface));
+    }
+
+    #[test]
+    fn interface_parse_error() {
+        let errors = Interface::parse("pub u8 a;\npub fn b;\n").expect_err("Expected a malformed interface.");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].msg, "Expected '(' after function name.");
+        assert_eq!(errors[0].span.line, 2);
+    }
+}
@@ -0,0 +1,48 @@
+
+SECTION "Joypad", ROM0
+
+; Reads the buttons, with a bit set for each one held down (d-pad in the high nibble), and works out which
+; were pressed or released since the last read
+tgReadJoypad::
+  ld a, $20
+  ldh [$FF00], a
+  ; The lines take a few cycles to settle after selecting a row, so only the last read counts
+  ldh a, [$FF00]
+  ldh a, [$FF00]
+  cpl
+  and $0F
+  swap a
+  ld b, a
+  ld a, $10
+  ldh [$FF00], a
+  ldh a, [$FF00]
+  ldh a, [$FF00]
+  ldh a, [$FF00]
+  ldh a, [$FF00]
+  ldh a, [$FF00]
+  ldh a, [$FF00]
+  cpl
+  and $0F
+  or b
+  ld b, a
+  ld a, $30
+  ldh [$FF00], a
+
+  ld a, [wJoypadHeld]
+  ld c, a
+  xor b
+  ld d, a
+  and b
+  ld [wJoypadPressed], a
+  ld a, d
+  and c
+  ld [wJoypadReleased], a
+  ld a, b
+  ld [wJoypadHeld], a
+  ret
+
+SECTION "Joypad state", WRAM0
+
+wJoypadHeld:: ds 1
+wJoypadPressed:: ds 1
+wJoypadReleased:: ds 1
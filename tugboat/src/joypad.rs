@@ -0,0 +1,27 @@
+use phf::phf_map;
+
+/// Everything that can be referenced through the built-in `joypad` namespace. The state is refreshed by calling
+/// `joypad.update()`, usually once a frame.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Joypad {
+    /// A read-only bitmask of buttons, kept at the given label by the runtime's read routine.
+    State(&'static str),
+    /// The bit for a button in each of the state masks.
+    Button(u8),
+}
+
+use Joypad::*;
+
+pub static JOYPAD: phf::Map<&'static str, Joypad> = phf_map! {
+    "held" => State("wJoypadHeld"),
+    "pressed" => State("wJoypadPressed"),
+    "released" => State("wJoypadReleased"),
+    "DOWN" => Button(0x80),
+    "UP" => Button(0x40),
+    "LEFT" => Button(0x20),
+    "RIGHT" => Button(0x10),
+    "START" => Button(0x08),
+    "SELECT" => Button(0x04),
+    "B" => Button(0x02),
+    "A" => Button(0x01),
+};
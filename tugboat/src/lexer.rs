@@ -1,6 +1,7 @@
 use super::*;
 use phf::phf_map;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum TokenKind {
     // Single-characters
@@ -12,10 +13,14 @@ pub enum TokenKind {
     RightBracket,
     Semicolon,
     Comma,
+    Dot,
+    At,
     Star,
     Plus,
     Minus,
     Exclamation,
+    Ampersand,
+    Pipe,
     // One-or-two characters
     Slash,
     Equals,
@@ -26,14 +31,29 @@ pub enum TokenKind {
     LessEqual,
     //Keywords
     Fn,
+    Pub,
+    Hram,
+    Rom,
     Unsigned8,
     While,
     True,
     False,
     Halt,
+    Interrupt,
+    Atomic,
+    Di,
+    Ei,
+    Incbin,
     // Multi-character
     Identifier,
     Number,
+    /// An `#RRGGBB` colour, with its value already reduced to the CGB's RGB555.
+    Colour,
+    StringLiteral,
+    /// A `//` or `/* */` comment, which only the formatter keeps.
+    Comment,
+    /// A `///` comment documenting the declaration after it.
+    DocComment,
     EOF,
 }
 
@@ -41,8 +61,8 @@ pub enum TokenKind {
 pub struct Token {
     pub kind: TokenKind,
     pub lexeme: String,
-    pub value: Option<u8>,
-    pub line: u32,
+    pub value: Option<u16>,
+    pub span: Span,
 }
 
 use TokenKind::*;
@@ -57,8 +77,12 @@ static SINGLE_CHAR_TOKENS: phf::Map<char, TokenKind> = phf_map! {
     ';' => Semicolon,
     '=' => Equals,
     ',' => Comma,
+    '.' => Dot,
+    '@' => At,
     '*' => Star,
     '!' => Exclamation,
+    '&' => Ampersand,
+    '|' => Pipe,
     '>' => Greater,
     '<' => Less,
     '/' => Slash,
@@ -68,54 +92,112 @@ static SINGLE_CHAR_TOKENS: phf::Map<char, TokenKind> = phf_map! {
 
 static KEYWORDS: phf::Map<&'static str, TokenKind> = phf_map! {
     "fn" => Fn,
+    "pub" => Pub,
+    "hram" => Hram,
+    "rom" => Rom,
     "u8" => Unsigned8,
     "true" => True,
     "false" => False,
     "while" => While,
     "halt" => Halt,
+    "interrupt" => Interrupt,
+    "atomic" => Atomic,
+    "di" => Di,
+    "ei" => Ei,
+    "incbin" => Incbin,
 };
 
-pub fn lex(code: String) -> (Vec<Token>, Vec<CompilationError>) {
-    let mut queue: VecDeque<_> = code.chars().collect();
+/// Lexes `code` into the tokens the parser works with, which leaves out comments other than doc comments.
+pub fn lex(code: String) -> (Vec<Token>, Vec<Diagnostic>) {
+    let (mut tokens, errors) = lex_with_comments(code);
+    tokens.retain(|token| token.kind != Comment);
+    (tokens, errors)
+}
+
+/// Lexes `code` keeping its comments as tokens, for tools that reprint the source.
+pub fn lex_with_comments(code: String) -> (Vec<Token>, Vec<Diagnostic>) {
+    let mut queue = Source::new(&code);
     let mut tokens: Vec<Token> = Vec::new();
-    let mut errors: Vec<CompilationError> = Vec::new();
-    let mut line = 1;
+    let mut errors: Vec<Diagnostic> = Vec::new();
 
-    let mut add = |kind: TokenKind, lexeme: String, value: Option<u8>, line: u32| {
-        tokens.push(build_token(kind, lexeme, value, line))
+    let mut add = |kind: TokenKind, lexeme: String, value: Option<u16>, span: Span| {
+        tokens.push(build_token(kind, lexeme, value, span))
     };
-    let mut error = |msg: String, line: u32| {
-        errors.push(CompilationError { msg, line });
+    let mut error = |msg: String, span: Span| {
+        errors.push(Diagnostic::error(span, msg));
     };
 
-    while queue.len() > 0 {
+    while !queue.is_empty() {
+        let start = queue.position();
         let next_char = queue.pop_front();
         match next_char {
-            Some('=') if is_char('=', queue.get(0)) => {
+            Some('=') if is_char('=', queue.front()) => {
                 queue.pop_front();
-                add(EqualsEquals, String::from("=="), None, line);
+                add(EqualsEquals, String::from("=="), None, queue.span_from(start));
             }
-            Some('>') if is_char('=', queue.get(0)) => {
+            Some('>') if is_char('=', queue.front()) => {
                 queue.pop_front();
-                add(GreaterEqual, String::from(">="), None, line);
+                add(GreaterEqual, String::from(">="), None, queue.span_from(start));
             }
-            Some('<') if is_char('=', queue.get(0)) => {
+            Some('<') if is_char('=', queue.front()) => {
                 queue.pop_front();
-                add(LessEqual, String::from("<="), None, line);
+                add(LessEqual, String::from("<="), None, queue.span_from(start));
             }
-            Some('/') if is_char('/', queue.get(0)) => {
-                while !is_char('\n', queue.get(0)) {
-                    queue.pop_front();
+            Some('/') if is_char('/', queue.front()) => {
+                let mut comment = String::from('/');
+                while queue.front().is_some_and(|c| *c != '\n') {
+                    comment.push(queue.pop_front().unwrap());
+                }
+                let comment = comment.trim_end();
+                let span = Span {
+                    length: comment.len(),
+                    ..start
+                };
+
+                // Exactly three slashes make a doc comment, whose lexeme is just its text.
+                match comment.strip_prefix("///") {
+                    Some(doc) if !doc.starts_with('/') => {
+                        let doc = doc.strip_prefix(' ').unwrap_or(doc);
+                        add(DocComment, doc.to_string(), None, span);
+                    }
+                    _ => add(Comment, comment.to_string(), None, span),
+                }
+            }
+            Some('/') if is_char('*', queue.front()) => {
+                queue.pop_front();
+                let mut comment = String::from("/*");
+                let mut depth = 1;
+                while depth > 0 {
+                    match queue.pop_front() {
+                        Some('*') if is_char('/', queue.front()) => {
+                            queue.pop_front();
+                            comment.push_str("*/");
+                            depth -= 1;
+                        }
+                        Some('/') if is_char('*', queue.front()) => {
+                            queue.pop_front();
+                            comment.push_str("/*");
+                            depth += 1;
+                        }
+                        Some(c) => comment.push(c),
+                        None => break,
+                    }
+                }
+
+                if depth == 0 {
+                    add(Comment, comment, None, queue.span_from(start));
+                } else {
+                    error(String::from("Unterminated block comment"), queue.span_from(start));
                 }
             }
             Some(c) if SINGLE_CHAR_TOKENS.contains_key(&c) => {
                 let kind = SINGLE_CHAR_TOKENS.get(&c).unwrap().clone();
-                add(kind, String::from(c), None, line);
+                add(kind, String::from(c), None, queue.span_from(start));
             }
             // TODO: Let's break these larger branches off into functions
             Some('\'') => {
                 let mut literal = String::new();
-                while !is_char('\'', queue.get(0)) {
+                while !is_char('\'', queue.front()) {
                     literal.push(queue.pop_front().unwrap());
                 }
                 queue.pop_front();
@@ -124,92 +206,189 @@ pub fn lex(code: String) -> (Vec<Token>, Vec<CompilationError>) {
                     let character_literal = literal.chars().next().unwrap();
                     let number_literal = u8::try_from(u32::from(character_literal));
                     if let Ok(n) = number_literal {
-                        add(Number, literal, Some(n), line);
+                        add(Number, literal, Some(u16::from(n)), queue.span_from(start));
                     } else {
                         let msg = format!("Failed to convert character to u8: '{}'", character_literal);
-                        error(msg, line);
+                        error(msg, queue.span_from(start));
                     }
                 } else {
                     let msg = format!("Character literal should be exactly one character: '{}'", literal);
-                    error(msg, line);
+                    error(msg, queue.span_from(start));
                 }
             }
             Some(c @ '0'..='9') => {
                 let mut literal = String::from(c);
-                while is_digit(queue.get(0)) {
+                while is_digit(queue.front()) {
                     literal.push(queue.pop_front().unwrap());
                 }
 
-                let parse_result = literal.parse::<u8>();
+                let parse_result = literal.parse::<u16>();
                 match parse_result {
                     Ok(value) => {
-                        add(Number, literal, Some(value), line);
+                        add(Number, literal, Some(value), queue.span_from(start));
                     }
                     Err(err) => {
-                        let msg = format!("Failed to parse literal: {}", err.to_string());
-                        error(msg, line);
+                        let msg = format!("Failed to parse literal: {}", err);
+                        error(msg, queue.span_from(start));
                     }
                 }
             }
+            Some('$') => {
+                let mut literal = String::from('$');
+                while is_hex_digit(queue.front()) {
+                    literal.push(queue.pop_front().unwrap());
+                }
+
+                match u16::from_str_radix(&literal[1..], 16) {
+                    Ok(value) => add(Number, literal, Some(value), queue.span_from(start)),
+                    Err(err) => error(
+                        format!("Failed to parse hex literal '{}': {}", literal, err),
+                        queue.span_from(start),
+                    ),
+                }
+            }
+            Some('#') => {
+                let mut literal = String::from('#');
+                while is_hex_digit(queue.front()) {
+                    literal.push(queue.pop_front().unwrap());
+                }
+
+                match u32::from_str_radix(&literal[1..], 16) {
+                    Ok(rgb) if literal.len() == 7 => add(Colour, literal, Some(rgb555(rgb)), queue.span_from(start)),
+                    _ => error(
+                        format!("Colour literal '{}' should be #RRGGBB", literal),
+                        queue.span_from(start),
+                    ),
+                }
+            }
+            Some('"') => {
+                let mut literal = String::new();
+                while queue.front().is_some_and(|c| *c != '"' && *c != '\n') {
+                    literal.push(queue.pop_front().unwrap());
+                }
+
+                if is_char('"', queue.front()) {
+                    queue.pop_front();
+                    add(StringLiteral, literal, None, queue.span_from(start));
+                } else {
+                    error(
+                        format!("Unterminated string literal: \"{}", literal),
+                        queue.span_from(start),
+                    );
+                }
+            }
             Some(c @ 'a'..='z' | c @ 'A'..='Z') => {
                 let mut identifier = String::from(c);
-                while is_identifier(queue.get(0)) {
+                while is_identifier(queue.front()) {
                     identifier.push(queue.pop_front().unwrap());
                 }
 
                 if KEYWORDS.contains_key(&identifier) {
                     let kind = KEYWORDS.get(&identifier).unwrap().clone();
-                    add(kind, identifier, None, line);
+                    add(kind, identifier, None, queue.span_from(start));
                 } else {
-                    add(Identifier, identifier, None, line);
+                    add(Identifier, identifier, None, queue.span_from(start));
                 }
             }
-            Some('\n') => {
-                line += 1;
-            }
-            Some(' ' | '\t' | '\r') => {}
+            Some(' ' | '\t' | '\r' | '\n') => {}
             Some(c) => {
-                error(format!("Unexpected character: {}", c), line);
+                error(format!("Unexpected character: {}", c), queue.span_from(start));
             }
             None => {}
         }
     }
 
-    add(EOF, String::new(), None, line);
+    let end = queue.position();
+    add(EOF, String::new(), None, queue.span_from(end));
 
     (tokens, errors)
 }
 
+/// Keeps the top 5 bits of each 8-bit component, packed with red in the lowest bits as the CGB expects.
+fn rgb555(rgb: u32) -> u16 {
+    let component = |shift: u32| ((rgb >> shift) & 0xFF) as u16 >> 3;
+    component(16) | component(8) << 5 | component(0) << 10
+}
+
 fn is_char(target: char, subject: Option<&char>) -> bool {
-    match subject {
-        Some(c) if *c == target => true,
-        _ => false,
-    }
+    matches!(subject, Some(c) if *c == target)
 }
 
 fn is_digit(c: Option<&char>) -> bool {
-    match c {
-        Some('0'..='9') => true,
-        _ => false,
-    }
+    matches!(c, Some('0'..='9'))
+}
+
+fn is_hex_digit(c: Option<&char>) -> bool {
+    matches!(c, Some('0'..='9' | 'a'..='f' | 'A'..='F'))
 }
 
 fn is_identifier(c: Option<&char>) -> bool {
-    match c {
-        Some('A'..='Z') => true,
-        Some('a'..='z') => true,
-        Some('0'..='9') => true,
-        Some('_') => true,
-        _ => false,
-    }
+    matches!(c, Some('A'..='Z' | 'a'..='z' | '0'..='9' | '_'))
 }
 
-fn build_token(kind: TokenKind, lexeme: String, value: Option<u8>, line: u32) -> Token {
+fn build_token(kind: TokenKind, lexeme: String, value: Option<u16>, span: Span) -> Token {
     Token {
         kind,
         lexeme,
         value,
-        line,
+        span,
+    }
+}
+
+/// The characters still to be lexed, keeping count of where the next one is in the source.
+struct Source {
+    chars: VecDeque<char>,
+    offset: usize,
+    line: u32,
+    column: u32,
+}
+
+impl Source {
+    fn new(code: &str) -> Source {
+        Source {
+            chars: code.chars().collect(),
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    fn front(&self) -> Option<&char> {
+        self.chars.front()
+    }
+
+    fn pop_front(&mut self) -> Option<char> {
+        let c = self.chars.pop_front()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    /// Where the next character is, as an empty span.
+    fn position(&self) -> Span {
+        Span {
+            offset: self.offset,
+            length: 0,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// The span from `start` up to the next character.
+    fn span_from(&self, start: Span) -> Span {
+        Span {
+            length: self.offset - start.offset,
+            ..start
+        }
     }
 }
 
@@ -217,20 +396,36 @@ fn build_token(kind: TokenKind, lexeme: String, value: Option<u8>, line: u32) ->
 mod tests {
     use super::*;
 
-    fn token(kind: TokenKind, lexeme: &str, value: Option<u8>, line: u32) -> Token {
-        build_token(kind, lexeme.to_string(), value, line)
+    fn token(kind: TokenKind, lexeme: &str, value: Option<u16>, line: u32) -> Token {
+        let span = Span {
+            line,
+            ..Span::default()
+        };
+        build_token(kind, lexeme.to_string(), value, span)
     }
 
-    fn error(msg: &str, line: u32) -> CompilationError {
-        CompilationError {
+    fn error(msg: &str, line: u32) -> ExpectedError {
+        ExpectedError {
             msg: msg.to_string(),
             line,
         }
     }
 
+    /// Lexes `code`, keeping only the line of each token's span, which is all most of these tests check.
+    fn lex_lines(code: &str) -> (Vec<Token>, Vec<Diagnostic>) {
+        let (mut tokens, errors) = lex(String::from(code));
+        for token in tokens.iter_mut() {
+            token.span = Span {
+                line: token.span.line,
+                ..Span::default()
+            };
+        }
+        (tokens, errors)
+    }
+
     #[test]
     fn lex_single_char() {
-        let (result, _) = lex(String::from("{}();"));
+        let (result, _) = lex_lines("{}();");
         assert_eq!(
             result,
             vec![
@@ -246,7 +441,7 @@ mod tests {
 
     #[test]
     fn lex_keywords() {
-        let (result, _) = lex(String::from("fn u8"));
+        let (result, _) = lex_lines("fn u8");
         assert_eq!(
             result,
             vec![
@@ -259,7 +454,7 @@ mod tests {
 
     #[test]
     fn lex_identifiers() {
-        let (result, _) = lex(String::from("myVar something"));
+        let (result, _) = lex_lines("myVar something");
         assert_eq!(
             result,
             vec![
@@ -272,7 +467,7 @@ mod tests {
 
     #[test]
     fn lex_basic_script() {
-        let (result, _) = lex(String::from("u8 variable;\nfn main() {\nvariable = 5;\n}\n"));
+        let (result, _) = lex_lines("u8 variable;\nfn main() {\nvariable = 5;\n}\n");
         assert_eq!(
             result,
             vec![
@@ -296,7 +491,7 @@ mod tests {
 
     #[test]
     fn lex_big_number() {
-        let (_, errors) = lex(String::from("65536"));
+        let (_, errors) = lex_lines("65536");
         assert_eq!(
             errors,
             vec![error(
@@ -306,9 +501,71 @@ mod tests {
         );
     }
 
+    #[test]
+    fn lex_qualified_name() {
+        let (result, _) = lex_lines("hw.rLY & hw.STATF_LYC | 1");
+        assert_eq!(
+            result,
+            vec![
+                token(Identifier, "hw", None, 1),
+                token(Dot, ".", None, 1),
+                token(Identifier, "rLY", None, 1),
+                token(Ampersand, "&", None, 1),
+                token(Identifier, "hw", None, 1),
+                token(Dot, ".", None, 1),
+                token(Identifier, "STATF_LYC", None, 1),
+                token(Pipe, "|", None, 1),
+                token(Number, "1", Some(1), 1),
+                token(EOF, "", None, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_attributes() {
+        let (result, errors) = lex_lines("@section(\"Game play\") @at($C1f0) 300");
+        assert!(errors.is_empty());
+        assert_eq!(
+            result,
+            vec![
+                token(At, "@", None, 1),
+                token(Identifier, "section", None, 1),
+                token(LeftParen, "(", None, 1),
+                token(StringLiteral, "Game play", None, 1),
+                token(RightParen, ")", None, 1),
+                token(At, "@", None, 1),
+                token(Identifier, "at", None, 1),
+                token(LeftParen, "(", None, 1),
+                token(Number, "$C1f0", Some(0xC1F0), 1),
+                token(RightParen, ")", None, 1),
+                token(Number, "300", Some(300), 1),
+                token(EOF, "", None, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_literal_errors() {
+        let (_, errors) = lex_lines("$ $12345\n\"open");
+        assert_eq!(
+            errors,
+            vec![
+                error(
+                    "Failed to parse hex literal '$': cannot parse integer from empty string",
+                    1
+                ),
+                error(
+                    "Failed to parse hex literal '$12345': number too large to fit in target type",
+                    1
+                ),
+                error("Unterminated string literal: \"open", 2),
+            ]
+        );
+    }
+
     #[test]
     fn lex_equals_equals() {
-        let (result, _) = lex(String::from("== = =="));
+        let (result, _) = lex_lines("== = ==");
         assert_eq!(
             result,
             vec![
@@ -322,7 +579,7 @@ mod tests {
 
     #[test]
     fn lex_comments_and_comparisons() {
-        let (result, _) = lex(String::from("// This is a comment\n> >= < <="));
+        let (result, _) = lex_lines("// This is a comment\n> >= < <=");
         assert_eq!(
             result,
             vec![
@@ -335,9 +592,66 @@ mod tests {
         );
     }
 
+    #[test]
+    fn lex_keeping_comments() {
+        let (result, _) = lex_with_comments(String::from("u8 x; // trailing \r\n// last"));
+        let comments: Vec<(&str, Span)> = result
+            .iter()
+            .filter(|token| token.kind == Comment)
+            .map(|token| (token.lexeme.as_str(), token.span))
+            .collect();
+        assert_eq!(
+            comments,
+            vec![
+                (
+                    "// trailing",
+                    Span {
+                        offset: 6,
+                        length: 11,
+                        line: 1,
+                        column: 7
+                    }
+                ),
+                (
+                    "// last",
+                    Span {
+                        offset: 20,
+                        length: 7,
+                        line: 2,
+                        column: 1
+                    }
+                ),
+            ]
+        );
+        assert_eq!(result.last().unwrap().kind, EOF);
+    }
+
+    #[test]
+    fn lex_block_and_doc_comments() {
+        let (result, errors) = lex_lines("/* outer /* inner */\n still comment */ u8\n/// Docs\n///\n//// Not docs");
+        assert!(errors.is_empty());
+        assert_eq!(
+            result,
+            vec![
+                token(Unsigned8, "u8", None, 2),
+                token(DocComment, "Docs", None, 3),
+                token(DocComment, "", None, 4),
+                token(EOF, "", None, 5),
+            ]
+        );
+
+        let (result, _) = lex_with_comments(String::from("x /* a\nb */ y"));
+        assert_eq!(result[1].kind, Comment);
+        assert_eq!(result[1].lexeme, "/* a\nb */");
+        assert_eq!(result[2].span.line, 2);
+
+        let (_, errors) = lex_lines("u8 x;\n/* open /* nested */");
+        assert_eq!(errors, vec![error("Unterminated block comment", 2)]);
+    }
+
     #[test]
     fn lex_character_literals() {
-        let (result, _) = lex(String::from("'a' '0' 'G'"));
+        let (result, _) = lex_lines("'a' '0' 'G'");
         assert_eq!(
             result,
             vec![
@@ -351,7 +665,7 @@ mod tests {
 
     #[test]
     fn lex_character_literal_errors() {
-        let (_, errors) = lex(String::from("'aaa' ''"));
+        let (_, errors) = lex_lines("'aaa' ''");
         assert_eq!(
             errors,
             vec![
@@ -360,4 +674,45 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn lex_colours() {
+        let (result, errors) = lex_lines("#FF0000 #00ff00 #0000FF #FFFFFF #123");
+        assert_eq!(
+            result,
+            vec![
+                token(Colour, "#FF0000", Some(0x001F), 1),
+                token(Colour, "#00ff00", Some(0x03E0), 1),
+                token(Colour, "#0000FF", Some(0x7C00), 1),
+                token(Colour, "#FFFFFF", Some(0x7FFF), 1),
+                token(EOF, "", None, 1),
+            ]
+        );
+        assert_eq!(errors, vec![error("Colour literal '#123' should be #RRGGBB", 1)]);
+    }
+
+    #[test]
+    fn lex_spans() {
+        let (result, _) = lex(String::from("fn main() {\n  x = \"é\";\n}"));
+        let spans: Vec<_> = result
+            .iter()
+            .map(|token| (token.span.offset, token.span.length, token.span.line, token.span.column))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (0, 2, 1, 1),
+                (3, 4, 1, 4),
+                (7, 1, 1, 8),
+                (8, 1, 1, 9),
+                (10, 1, 1, 11),
+                (14, 1, 2, 3),
+                (16, 1, 2, 5),
+                (18, 4, 2, 7),
+                (22, 1, 2, 10),
+                (24, 1, 3, 1),
+                (25, 0, 3, 2),
+            ]
+        );
+    }
 }
@@ -1,30 +1,192 @@
 mod analysis;
 mod codegen;
+mod formatter;
+mod graphics;
+mod hardware;
+mod interface;
+mod joypad;
 mod lexer;
 mod parser;
+mod sound;
+pub mod visit;
 
-use lexer::Token;
-use std::collections::VecDeque;
+pub use codegen::Section;
+pub use interface::{Interface, Symbol, SymbolKind};
+pub use lexer::{Token, TokenKind};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+pub use visit::{Visitor, VisitorMut};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Declaration {
     Variable {
         name: Token,
-        size: u8,
+        /// The number of bytes, or `None` for an asset sized by its file until [`check`] has read it.
+        size: Option<u16>,
+        public: bool,
+        placement: Placement,
+        initializer: Option<Initializer>,
+        /// Lint levels set by the declaration's attributes.
+        lints: Vec<(Lint, LintLevel)>,
+        /// The lines of the `///` comments above the declaration.
+        doc: Vec<String>,
+        /// The whole declaration, from its doc comments and attributes to its closing `;` or `}`.
+        extent: Span,
     },
     Function {
         name: Token,
         arguments: Vec<Token>,
         body: Vec<Stmt>,
+        public: bool,
+        placement: Placement,
+        /// Set for interrupt handlers, which are jumped to from their interrupt vector.
+        interrupt: Option<InterruptKind>,
+        /// Lint levels set by the declaration's attributes, which also cover everything in its body.
+        lints: Vec<(Lint, LintLevel)>,
+        /// The lines of the `///` comments above the declaration.
+        doc: Vec<String>,
+        /// The whole declaration, from its doc comments and attributes to its closing `;` or `}`.
+        extent: Span,
     },
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InterruptKind {
+    VBlank,
+    Stat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl InterruptKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "vblank" => Some(InterruptKind::VBlank),
+            "stat" => Some(InterruptKind::Stat),
+            "timer" => Some(InterruptKind::Timer),
+            "serial" => Some(InterruptKind::Serial),
+            "joypad" => Some(InterruptKind::Joypad),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InterruptKind::VBlank => "vblank",
+            InterruptKind::Stat => "stat",
+            InterruptKind::Timer => "timer",
+            InterruptKind::Serial => "serial",
+            InterruptKind::Joypad => "joypad",
+        }
+    }
+
+    /// The address the CPU jumps to when the interrupt fires.
+    pub fn vector(&self) -> u16 {
+        match self {
+            InterruptKind::VBlank => 0x40,
+            InterruptKind::Stat => 0x48,
+            InterruptKind::Timer => 0x50,
+            InterruptKind::Serial => 0x58,
+            InterruptKind::Joypad => 0x60,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Initializer {
+    /// A list of constant values, e.g. `{1, 2, 3}`.
+    Values(Vec<Expr>),
+    /// The contents of a binary file, optionally sliced to a byte range, e.g. `incbin("map.bin", 16, 320)`. The
+    /// length is filled in from the file when left out.
+    Incbin {
+        path: Token,
+        offset: u16,
+        length: Option<u16>,
+    },
+    /// Tiles or a tilemap converted from a PNG, e.g. `tiles("font.png", dedupe)`. The data is filled in from the
+    /// image.
+    Graphics {
+        path: Token,
+        output: GraphicsOutput,
+        /// Lay tiles out in 8x16 blocks, for tall sprites.
+        tall: bool,
+        /// Only keep one copy of repeated tiles, which the tilemap then refers back to.
+        dedupe: bool,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GraphicsOutput {
+    Tiles,
+    Tilemap,
+}
+
+/// Where a declaration lives, which decides the section it is emitted into and the instructions used to
+/// access it.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Placement {
+    pub memory: Memory,
+    /// An explicit section name, otherwise one is chosen based on the rest of the placement.
+    pub section: Option<String>,
+    pub bank: Option<u16>,
+    /// Alignment of the section in bits, as in RGBDS' `ALIGN[n]`.
+    pub align: Option<u8>,
+    pub address: Option<u16>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Memory {
+    #[default]
+    Wram0,
+    WramX,
+    /// High RAM, reachable with the shorter `ldh` instructions but limited to 127 bytes.
+    Hram,
+    Rom0,
+    RomX,
+}
+
+impl std::fmt::Display for Memory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Memory::Wram0 => write!(f, "WRAM0"),
+            Memory::WramX => write!(f, "WRAMX"),
+            Memory::Hram => write!(f, "HRAM"),
+            Memory::Rom0 => write!(f, "ROM0"),
+            Memory::RomX => write!(f, "ROMX"),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Stmt {
-    Halt,
-    While { condition: Expr, body: Vec<Stmt> },
-    Assign { target: Expr, value: Expr },
-    Expression { expr: Expr },
+    Halt {
+        keyword: Token,
+    },
+    DisableInterrupts {
+        keyword: Token,
+    },
+    EnableInterrupts {
+        keyword: Token,
+    },
+    /// A block that runs with interrupts disabled, leaving them enabled afterwards only if they were before.
+    Atomic {
+        keyword: Token,
+        body: Vec<Stmt>,
+    },
+    While {
+        keyword: Token,
+        condition: Expr,
+        body: Vec<Stmt>,
+    },
+    Assign {
+        target: Expr,
+        value: Expr,
+    },
+    Expression {
+        expr: Expr,
+    },
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -39,42 +201,544 @@ pub enum Expr {
     Indexed {
         name: Token,
         index: Box<Expr>,
+        /// The member picked out of a structured element, e.g. the `x` in `oam[0].x`.
+        field: Option<Token>,
     },
     Binary {
         operator: Token,
         left: Box<Expr>,
         right: Box<Expr>,
     },
+    Call {
+        callee: Token,
+        arguments: Vec<Expr>,
+    },
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct CompilationError {
-    pub msg: String,
+impl Declaration {
+    pub fn name(&self) -> &Token {
+        match self {
+            Declaration::Variable { name, .. } | Declaration::Function { name, .. } => name,
+        }
+    }
+
+    /// Where the declared name is, which is what diagnostics about the declaration point at.
+    pub fn span(&self) -> Span {
+        self.name().span
+    }
+
+    /// The whole declaration, for when it needs to be picked out as a block of source.
+    pub fn extent(&self) -> Span {
+        match self {
+            Declaration::Variable { extent, .. } | Declaration::Function { extent, .. } => *extent,
+        }
+    }
+
+    /// The declaration as it would be written, without attributes, initialiser or body.
+    pub fn signature(&self) -> String {
+        match self {
+            Declaration::Variable {
+                name,
+                size,
+                public,
+                placement,
+                ..
+            } => {
+                let keyword = match placement.memory {
+                    Memory::Hram => "hram ",
+                    Memory::Rom0 | Memory::RomX => "rom ",
+                    Memory::Wram0 | Memory::WramX => "",
+                };
+                let array = match size {
+                    Some(1) => String::new(),
+                    Some(size) => format!("[{}]", size),
+                    None => String::from("[]"),
+                };
+                let public = if *public { "pub " } else { "" };
+                format!("{}{}u8{} {}", public, keyword, array, name.lexeme)
+            }
+            Declaration::Function {
+                name,
+                public,
+                interrupt,
+                ..
+            } => {
+                let public = if *public { "pub " } else { "" };
+                let interrupt = interrupt.map_or(String::new(), |kind| format!("interrupt({}) ", kind.name()));
+                format!("{}{}fn {}()", public, interrupt, name.lexeme)
+            }
+        }
+    }
+
+    /// How big the declaration is and where it lives, e.g. `3 bytes in ROMX, bank 2`.
+    pub fn location(&self) -> String {
+        let (size, placement) = match self {
+            Declaration::Variable { size, placement, .. } => (*size, placement),
+            Declaration::Function { placement, .. } => (None, placement),
+        };
+
+        let mut location = match size {
+            Some(1) => format!("1 byte in {}", placement.memory),
+            Some(size) => format!("{} bytes in {}", size, placement.memory),
+            None => format!("in {}", placement.memory),
+        };
+        if let Some(bank) = placement.bank {
+            location.push_str(format!(", bank {}", bank).as_str());
+        }
+        if let Some(address) = placement.address {
+            location.push_str(format!(" at ${:04X}", address).as_str());
+        }
+        if let Some(section) = &placement.section {
+            location.push_str(format!(" (section \"{}\")", section).as_str());
+        }
+        location
+    }
+
+    /// The declaration's documentation, one line at a time.
+    pub fn doc(&self) -> &[String] {
+        match self {
+            Declaration::Variable { doc, .. } | Declaration::Function { doc, .. } => doc,
+        }
+    }
+}
+
+impl Stmt {
+    /// The statement's keyword, or the whole of an assignment or expression.
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Halt { keyword }
+            | Stmt::DisableInterrupts { keyword }
+            | Stmt::EnableInterrupts { keyword }
+            | Stmt::Atomic { keyword, .. }
+            | Stmt::While { keyword, .. } => keyword.span,
+            Stmt::Assign { target, value } => target.span().to(value.span()),
+            Stmt::Expression { expr } => expr.span(),
+        }
+    }
+}
+
+impl Expr {
+    /// The source covered by the expression's tokens. Closing brackets aren't kept in the tree, so the span
+    /// stops at the last token inside them.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Literal { token, .. } => token.span,
+            Expr::Variable { name } => name.span,
+            Expr::Indexed { name, index, field } => match field {
+                Some(field) => name.span.to(field.span),
+                None => name.span.to(index.span()),
+            },
+            Expr::Binary { left, right, .. } => left.span().to(right.span()),
+            Expr::Call { callee, arguments } => match arguments.last() {
+                Some(argument) => callee.span.to(argument.span()),
+                None => callee.span,
+            },
+        }
+    }
+}
+
+/// A range of source code, by byte offset for tools and by line and column for people.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub offset: usize,
+    /// The length in bytes.
+    pub length: usize,
     pub line: u32,
+    /// The column of the first character, counting characters from 1.
+    pub column: u32,
+}
+
+impl Span {
+    /// Extends the span up to the end of `end`, which comes later in the source.
+    pub fn to(self, end: Span) -> Span {
+        Span {
+            length: (end.offset + end.length).saturating_sub(self.offset),
+            ..self
+        }
+    }
+}
+
+/// An error, warning or note about the source, pointing at where it applies.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub level: Level,
+    /// The lint that raised it, for warnings and lints promoted to errors.
+    pub code: Option<Lint>,
+    pub msg: String,
+    pub span: Span,
+    /// Other places in the source that help explain the diagnostic.
+    pub labels: Vec<Label>,
+    /// Extra explanation or advice shown after the source.
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Level {
+    /// Stops the program from compiling.
+    Error,
+    Warning,
+    Note,
+}
+
+/// A secondary span attached to a diagnostic, such as where something was first declared.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub msg: String,
+}
+
+impl Diagnostic {
+    pub fn error(span: Span, msg: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            level: Level::Error,
+            code: None,
+            msg: msg.into(),
+            span,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(lint: Lint, span: Span, msg: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            level: Level::Warning,
+            code: Some(lint),
+            ..Diagnostic::error(span, msg)
+        }
+    }
+
+    pub fn with_label(mut self, span: Span, msg: impl Into<String>) -> Diagnostic {
+        self.labels.push(Label { span, msg: msg.into() });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+/// A check for code that compiles but is probably a mistake. Each one warns by default, and can be allowed or
+/// denied through [`Options::lints`] or the `@allow`, `@warn` and `@deny` attributes on a declaration.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Lint {
+    UnusedVariable,
+    UnusedFunction,
+    UnreachableCode,
+    NoEffect,
+    Overflow,
+    HramBudget,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 6] = [
+        Lint::UnusedVariable,
+        Lint::UnusedFunction,
+        Lint::UnreachableCode,
+        Lint::NoEffect,
+        Lint::Overflow,
+        Lint::HramBudget,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused_variable",
+            Lint::UnusedFunction => "unused_function",
+            Lint::UnreachableCode => "unreachable_code",
+            Lint::NoEffect => "no_effect",
+            Lint::Overflow => "overflow",
+            Lint::HramBudget => "hram_budget",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+}
+
+impl std::fmt::Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LintLevel {
+    /// Don't report it at all.
+    Allow,
+    Warn,
+    /// Report it as an error, so the program doesn't compile.
+    Deny,
+}
+
+impl LintLevel {
+    pub fn from_attribute(name: &str) -> Option<LintLevel> {
+        match name {
+            "allow" => Some(LintLevel::Allow),
+            "warn" => Some(LintLevel::Warn),
+            "deny" => Some(LintLevel::Deny),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Compile as one translation unit of a larger program: only `pub` items are exported and the shared
+    /// runtime (see [`runtime`]) is left for the caller to assemble once.
+    pub separate: bool,
+    /// Interfaces of the other units this one is allowed to reference.
+    pub imports: Vec<Interface>,
+    /// The cartridge's memory bank controller, which decides how many ROM banks can be switched between.
+    pub mbc: Mbc,
+    /// Generate the ROM header and entry point, which set up the hardware and memory before calling `main`.
+    pub startup: bool,
+    /// Reserve a shadow OAM for sprites and have the startup code install the DMA routine that copies it to OAM.
+    /// Needs `startup`.
+    pub sprites: bool,
+    /// Target the Game Boy Color, allowing its palettes, VRAM bank, WRAM banks and double speed mode to be used.
+    /// The startup code's header marks the ROM as CGB only, so a program needs `startup` as well.
+    pub cgb: bool,
+    /// The directory that asset paths in the source are relative to.
+    pub base_dir: PathBuf,
+    /// Levels for lints that shouldn't just warn. Attributes on a declaration take precedence over these.
+    pub lints: HashMap<Lint, LintLevel>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mbc {
+    /// A plain 32KB cartridge, where bank 1 is the only switchable bank and is always mapped.
+    #[default]
+    None,
+    Mbc1,
+    Mbc3,
+    Mbc5,
+}
+
+impl Mbc {
+    /// The highest ROM bank that can be switched to.
+    pub fn banks(&self) -> u16 {
+        match self {
+            Mbc::None => 1,
+            Mbc::Mbc1 => 31,
+            Mbc::Mbc3 => 127,
+            Mbc::Mbc5 => 255,
+        }
+    }
+
+    /// The address written to in order to select a ROM bank, if banks can be switched at all.
+    pub fn bank_register(&self) -> Option<u16> {
+        match self {
+            Mbc::None => None,
+            Mbc::Mbc1 | Mbc::Mbc3 => Some(0x2000),
+            // MBC5 splits the bank number across two registers, the low byte of which starts at $2000 but is
+            // conventionally written at $2100 so that the same code also works on MBC1.
+            Mbc::Mbc5 => Some(0x2100),
+        }
+    }
+}
+
+impl std::fmt::Display for Mbc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mbc::None => write!(f, "no MBC"),
+            Mbc::Mbc1 => write!(f, "MBC1"),
+            Mbc::Mbc3 => write!(f, "MBC3"),
+            Mbc::Mbc5 => write!(f, "MBC5"),
+        }
+    }
+}
+
+impl std::str::FromStr for Mbc {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Mbc::None),
+            "mbc1" => Ok(Mbc::Mbc1),
+            "mbc3" => Ok(Mbc::Mbc3),
+            "mbc5" => Ok(Mbc::Mbc5),
+            _ => Err(format!("unknown MBC '{}' (expected none, mbc1, mbc3 or mbc5)", s)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Unit {
+    pub asm: String,
+    pub interface: Interface,
+    pub warnings: Vec<Diagnostic>,
+}
+
+pub fn compile(contents: String) -> Result<String, Vec<Diagnostic>> {
+    compile_with(contents, &Options::default()).map(|unit| unit.asm)
+}
+
+pub fn compile_with(contents: String, options: &Options) -> Result<Unit, Vec<Diagnostic>> {
+    let (tokens, mut errors) = lex(contents);
+    let (ast, parser_errors) = parse(tokens);
+
+    errors.extend(parser_errors);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut checked = check(ast, options)?;
+    let (mut errors, warnings): (Vec<Diagnostic>, Vec<Diagnostic>) = std::mem::take(&mut checked.diagnostics)
+        .into_iter()
+        .partition(|diagnostic| diagnostic.level == Level::Error);
+    let (sections, lower_errors) = codegen::lower(&checked.ast, options);
+    errors.extend(lower_errors);
+
+    // Lowering errors don't stop emitting, so that everything wrong is reported at once.
+    match codegen::gen(&checked.ast, &sections, &checked.directory, options) {
+        Ok(asm) if errors.is_empty() => Ok(Unit {
+            asm,
+            interface: checked.interface,
+            warnings,
+        }),
+        Ok(_) => Err(errors),
+        Err(codegen_errors) => {
+            errors.extend(codegen_errors);
+            Err(errors)
+        }
+    }
+}
+
+/// Splits source into tokens. Comments are dropped, apart from doc comments, which the parser attaches to the
+/// declarations that follow them.
+pub fn lex(contents: String) -> (Vec<Token>, Vec<Diagnostic>) {
+    lexer::lex(contents)
+}
+
+/// Parses tokens into a syntax tree. The tree has everything that could be parsed, even when there are errors.
+pub fn parse(tokens: Vec<Token>) -> (Vec<Declaration>, Vec<Diagnostic>) {
+    parser::parse(tokens)
+}
+
+/// A syntax tree that has been checked and is ready to be lowered.
+#[derive(Debug)]
+pub struct Checked {
+    /// The declarations, with the sizes of their assets filled in.
+    pub ast: Vec<Declaration>,
+    pub interface: Interface,
+    /// Every lint that fired. Those denied by the options are errors, but are left to the caller rather than
+    /// stopping the program from being lowered.
+    pub diagnostics: Vec<Diagnostic>,
+    directory: codegen::Directory,
+}
+
+/// Loads assets, resolves names and runs the lints.
+pub fn check(mut ast: Vec<Declaration>, options: &Options) -> Result<Checked, Vec<Diagnostic>> {
+    analysis::resolve_assets(&mut ast, options)?;
+
+    let interface = Interface::from_ast(&ast);
+    let directory = analysis::generate_directory(&ast, options)?;
+    let diagnostics = analysis::lint(&ast, &directory, options);
+    Ok(Checked {
+        ast,
+        interface,
+        diagnostics,
+        directory,
+    })
+}
+
+/// A checked program laid out into the sections it will be emitted in.
+#[derive(Debug)]
+pub struct Program {
+    pub ast: Vec<Declaration>,
+    pub sections: Vec<Section>,
+    directory: codegen::Directory,
+}
+
+/// Makes sure the program can start and that its declarations fit their banks, then lays them out into sections.
+pub fn lower(checked: Checked, options: &Options) -> Result<Program, Vec<Diagnostic>> {
+    let (sections, errors) = codegen::lower(&checked.ast, options);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(Program {
+        ast: checked.ast,
+        sections,
+        directory: checked.directory,
+    })
+}
+
+/// Generates the assembly for a lowered program.
+pub fn emit(program: &Program, options: &Options) -> Result<String, Vec<Diagnostic>> {
+    codegen::gen(&program.ast, &program.sections, &program.directory, options)
+}
+
+/// Reprints source in the canonical style, or returns its errors if it doesn't parse.
+pub fn format(contents: String) -> Result<String, Vec<Diagnostic>> {
+    formatter::format(contents)
+}
+
+/// A name programs can use without declaring it, like a hardware register or built-in function.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Builtin {
+    pub name: String,
+    pub description: String,
+    /// Whether it is a built-in function rather than a value.
+    pub callable: bool,
 }
 
-pub fn compile(contents: String) -> Result<String, Vec<CompilationError>> {
-    let mut errors: Vec<CompilationError> = Vec::new();
-    let (tokens, lexer_errors) = lexer::lex(contents);
-    let (ast, parser_errors) = parser::parse(tokens);
+/// Every built-in name available with a set of options.
+pub fn builtins(options: &Options) -> Vec<Builtin> {
+    analysis::builtins(options)
+}
 
-    errors.extend(lexer_errors);
+/// Parses source and fills in the sizes of its assets without compiling it, for tools that describe the
+/// declarations rather than build them.
+pub fn declarations(contents: String, options: &Options) -> Result<Vec<Declaration>, Vec<Diagnostic>> {
+    let (tokens, mut errors) = lex(contents);
+    let (mut ast, parser_errors) = parse(tokens);
     errors.extend(parser_errors);
-    if errors.len() > 0 {
+    if !errors.is_empty() {
         return Err(errors);
     }
 
-    let directory = analysis::generate_directory(&ast);
+    analysis::resolve_assets(&mut ast, options)?;
+    Ok(ast)
+}
+
+/// Summarises the exported items of a unit without compiling it, so that other units can be compiled
+/// against it first.
+pub fn interface(contents: String, options: &Options) -> Result<Interface, Vec<Diagnostic>> {
+    // Asset sizes are part of the interface.
+    let ast = declarations(contents, options)?;
+    Ok(Interface::from_ast(&ast))
+}
+
+/// The core subroutines every program relies on. Whole-program compiles include these automatically, but
+/// separately compiled units expect them to be assembled and linked in once alongside the units.
+pub fn runtime(options: &Options) -> String {
+    codegen::gen_runtime(options)
+}
+
+/// An error as most tests describe it, by message and line. Tests that care about the rest of the span check it
+/// separately.
+#[cfg(test)]
+#[derive(Debug)]
+struct ExpectedError {
+    msg: String,
+    line: u32,
+}
 
-    Ok(codegen::gen(ast, &directory)?)
+#[cfg(test)]
+impl PartialEq<ExpectedError> for Diagnostic {
+    fn eq(&self, expected: &ExpectedError) -> bool {
+        self.msg == expected.msg && self.span.line == expected.line
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn error(msg: &str, line: u32) -> CompilationError {
-        CompilationError {
+    fn error(msg: &str, line: u32) -> ExpectedError {
+        ExpectedError {
             msg: msg.to_string(),
             line,
         }
@@ -102,15 +766,1072 @@ mod tests {
     }
 
     #[test]
-    fn error_no_nested_expressions() {
-        let src = String::from("u8 variable; fn main() { variable = 1 + 2 + 3; }");
+    fn error_duplicate_definition() {
+        let src = String::from("u8 value;\nfn value() {}");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(errors, vec![error("Duplicate definition: value", 2)]);
+        assert_eq!(errors[0].labels.len(), 1);
+        assert_eq!(errors[0].labels[0].msg, "first defined here");
+        assert_eq!(errors[0].labels[0].span.line, 1);
+
+        let options = Options {
+            sprites: true,
+            ..Options::default()
+        };
+        let errors =
+            compile_with(String::from("u8 oam;"), &options).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(errors, vec![error("Duplicate definition: oam", 1)]);
+        assert_eq!(errors[0].notes, vec!["oam is a built-in name, choose another one"]);
+    }
+
+    #[test]
+    fn separate_unit_exports_only_public_items() {
+        let options = Options {
+            separate: true,
+            ..Options::default()
+        };
+        let src = String::from("pub u8 shared; u8 hidden; pub fn main() { hidden = 1; }");
+        let unit = compile_with(src, &options).unwrap();
+
+        assert!(unit.asm.contains("SECTION FRAGMENT \"Variables\", WRAM0\n"));
+        assert!(unit.asm.contains("shared:: ds 1\n"));
+        assert!(unit.asm.contains("hidden: ds 1\n"));
+        assert!(unit.asm.contains("main::\n"));
+        assert!(!unit.asm.contains("tgZeroMemory"));
+        assert_eq!(unit.interface.to_string(), "pub u8 shared;\npub fn main();\n");
+    }
+
+    #[test]
+    fn separate_unit_checked_against_imports() {
+        let library = Interface::parse("pub u8[4] buffer;\npub fn helper();\n").unwrap();
+        let options = Options {
+            separate: true,
+            imports: vec![library],
+            ..Options::default()
+        };
+
+        let src = String::from("pub fn main() { buffer[2] = 1; }");
+        let unit = compile_with(src, &options).unwrap();
+        assert!(unit.asm.contains("\tld hl, buffer\n"));
+
+        let src = String::from("fn main() { helper = 1; }");
+        let errors = compile_with(src, &options).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(errors, vec![error("Cannot assign to function", 1)]);
+
+        let src = String::from("u8 buffer;");
+        let errors = compile_with(src, &options).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(errors[0].notes, vec!["buffer is already defined by an imported unit"]);
+    }
+
+    #[test]
+    fn hardware_registers_use_ldh() {
+        let src = String::from("u8 line; fn main() { line = hw.rLY; hw.rLCDC = hw.LCDCF_ON | hw.LCDCF_BGON; }");
+        let asm = compile(src).unwrap();
+        assert!(asm.contains("\tldh a, [$FF44]\n\tld [line], a\n"));
+        assert!(asm.contains("\tld a, 128\n\tld d, a\n\tld a, 1\n\tld b, a\n\tld a, d\n\tor a, b\n\tldh [$FF40], a\n"));
+    }
+
+    #[test]
+    fn hardware_regions_are_indexable() {
+        let src = String::from("fn main() { hw.screen0[3] = 'A'; }");
+        let asm = compile(src).unwrap();
+        assert!(asm.contains(
+            "\tld a, 65\n\tpush af\n\tld a, 3\n\tld b, 0\n\tld c, a\n\tld hl, $9800\n\tadd hl, bc\n\tpop af\n\tld [hl], a\n"
+        ));
+    }
+
+    #[test]
+    fn error_hardware_misuse() {
+        let src = String::from(
+            "u8 x;\nfn a() { hw.LCDCF_ON = 1; }\nfn b() { x = hw.vram; }\nfn c() { hw.rLY[0] = 1; }\nfn d() { x = hw.rNope; }",
+        );
         let errors = compile(src).expect_err("Expected compilation errors from bad script!");
         assert_eq!(
             errors,
             vec![
-                error("Expected ';' after expression (nested expressions not supported).", 1),
-                error("Unsupported top-level statement.", 1)
+                error("Cannot assign to constant", 2),
+                error("Cannot use memory region as a value (index into it instead)", 3),
+                error("Cannot index a hardware register or constant", 4),
+                error("Undefined variable: hw.rNope", 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn hram_variables_use_ldh() {
+        let src = String::from("hram u8 fast; u8 slow; fn main() { fast = slow; slow = fast; }");
+        let asm = compile(src).unwrap();
+        assert!(asm.contains("SECTION \"HRAM Variables\", HRAM\nfast:: ds 1\n"));
+        assert!(asm.contains("\tld a, [slow]\n\tldh [fast], a\n"));
+        assert!(asm.contains("\tldh a, [fast]\n\tld [slow], a\n"));
+    }
+
+    #[test]
+    fn doc_comments_above_labels() {
+        let src = String::from(
+            "/// How many lives are left.\n///\n/// Game over at zero.\nu8 lives;\n/* not docs */\n\
+             /// Entry point.\nfn main() { lives = 3; }",
+        );
+        let asm = compile(src).unwrap();
+        assert!(asm.contains("; How many lives are left.\n;\n; Game over at zero.\nlives:: ds 1\n"));
+        assert!(asm.contains("\n; Entry point.\nmain::\n"));
+        assert!(!asm.contains("not docs"));
+    }
+
+    #[test]
+    fn stages_match_compile() {
+        let src = String::from("@bank(1) rom u8[2] table = {1, 2};\nu8 x;\nfn main() { x = table[1]; }");
+        let options = Options::default();
+        let (tokens, errors) = lex(src.clone());
+        assert!(errors.is_empty());
+        let (ast, errors) = parse(tokens);
+        assert!(errors.is_empty());
+
+        let checked = check(ast, &options).unwrap();
+        assert!(checked.diagnostics.is_empty());
+        let program = lower(checked, &options).unwrap();
+        let names: Vec<&str> = program.sections.iter().map(|section| section.name.as_str()).collect();
+        assert_eq!(names, vec!["Variables", "Functions", "Data (bank 1)"]);
+        assert_eq!(program.sections[2].declarations, vec![0]);
+
+        assert_eq!(emit(&program, &options).unwrap(), compile(src).unwrap());
+    }
+
+    #[test]
+    fn stages_report_errors() {
+        let options = Options {
+            startup: true,
+            ..Options::default()
+        };
+        let (tokens, _) = lex(String::from("u8 x;"));
+        let (ast, _) = parse(tokens);
+        let checked = check(ast, &options).unwrap();
+        assert_eq!(checked.diagnostics, vec![error("Variable x is never used", 1)]);
+        let errors = lower(checked, &options).expect_err("Expected a missing main function");
+        assert_eq!(errors, vec![error("No main function to start the program from", 1)]);
+    }
+
+    #[test]
+    fn warning_hram_budget_exceeded() {
+        // The runtime's interrupt state takes the first byte.
+        let src = String::from("hram u8[100] a;\nhram u8[26] b;\nhram u8 c;\nhram u8 d;");
+        let unit = compile_with(src, &Options::default()).unwrap();
+        assert_eq!(
+            unit.warnings,
+            vec![error("HRAM budget exceeded by c: 128 bytes used, 127 available", 3)]
+        );
+
+        // Switching banks keeps the current one in HRAM as well.
+        let options = Options {
+            mbc: Mbc::Mbc1,
+            startup: true,
+            ..Options::default()
+        };
+        let src = String::from("hram u8[125] a;\nhram u8 b;\nfn main() { a[0] = b; }");
+        let unit = compile_with(src, &options).unwrap();
+        assert_eq!(
+            unit.warnings,
+            vec![error("HRAM budget exceeded by b: 128 bytes used, 127 available", 2)]
+        );
+    }
+
+    #[test]
+    fn lint_warnings() {
+        let options = Options {
+            startup: true,
+            ..Options::default()
+        };
+        let src = String::from(
+            "u8 x; u8 unused; pub u8 shared;\nfn helper() { helper(); }\nfn main() {\n  x = x;\n  x = 200 + 100;\n\
+             x = hw.rLY; hw.rLY = hw.rLY;\n  while (true) { halt; }\n  x = 1;\n}",
+        );
+        let unit = compile_with(src, &options).unwrap();
+        assert_eq!(
+            unit.warnings,
+            vec![
+                error("Variable unused is never used", 1),
+                error("Function helper is never called", 2),
+                error("Assigning a variable to itself has no effect", 4),
+                error("Constant arithmetic overflows a u8: 200 + 100 wraps to 44", 5),
+                error("Unreachable statement", 8),
             ]
         );
+        let codes: Vec<Option<Lint>> = unit.warnings.iter().map(|warning| warning.code).collect();
+        assert_eq!(
+            codes,
+            [
+                Lint::UnusedVariable,
+                Lint::UnusedFunction,
+                Lint::NoEffect,
+                Lint::Overflow,
+                Lint::UnreachableCode
+            ]
+            .map(Some)
+        );
+        assert_eq!(unit.warnings[4].labels[0].span.line, 7);
+
+        // Nothing is reported unused when the rest of the program might be written in assembly.
+        let src = String::from("u8 x; fn helper() {}");
+        assert!(compile_with(src, &Options::default()).unwrap().warnings.is_empty());
+    }
+
+    #[test]
+    fn lint_levels() {
+        let options = Options {
+            startup: true,
+            lints: HashMap::from([
+                (Lint::UnusedVariable, LintLevel::Deny),
+                (Lint::Overflow, LintLevel::Allow),
+            ]),
+            ..Options::default()
+        };
+        let src = String::from("u8 x;\nu8 y;\nfn main() { x = 255 + 1; }");
+        let errors = compile_with(src, &options).unwrap_err();
+        assert_eq!(errors, vec![error("Variable y is never used", 2)]);
+        assert_eq!(errors[0].level, Level::Error);
+
+        let src = String::from(
+            "u8 x;\n@allow(unused_variable) u8 y;\n@deny(overflow) fn main() { x = 255 + 1; }\n\
+             @warn(unused_variable) u8 z;",
+        );
+        let errors = compile_with(src, &options).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![error("Constant arithmetic overflows a u8: 255 + 1 wraps to 0", 3)]
+        );
+
+        let src = String::from("u8 x; @warn(unused_variable) u8 z; fn main() { x = 1; }");
+        let unit = compile_with(src, &options).unwrap();
+        assert_eq!(unit.warnings, vec![error("Variable z is never used", 1)]);
+        assert_eq!(unit.warnings[0].level, Level::Warning);
+    }
+
+    #[test]
+    fn joypad_input() {
+        let src = String::from(
+            "u8 buttons; fn main() { joypad.update(); buttons = joypad.pressed & joypad.START; buttons = joypad.held; }",
+        );
+        let asm = compile(src).unwrap();
+        assert!(asm.contains("tgReadJoypad::\n"));
+        assert!(asm.contains("wJoypadHeld:: ds 1\n"));
+        assert!(asm.contains("\tcall tgReadJoypad\n"));
+        assert!(asm.contains("\tld a, [wJoypadPressed]\n"));
+        assert!(asm.contains("\tld a, 8\n"));
+        assert!(asm.contains("\tld a, [wJoypadHeld]\n\tld [buttons], a\n"));
+        assert!(runtime(&Options::default()).contains("tgReadJoypad::\n"));
+
+        // Programs that never look at the joypad or play effects leave their runtime out.
+        let asm = compile(String::from("fn main() { sound.master = sound.ON; }")).unwrap();
+        assert!(!asm.contains("SECTION \"Joypad"));
+        assert!(!asm.contains("SECTION \"Sound effect"));
+    }
+
+    #[test]
+    fn error_joypad_misuse() {
+        let src = String::from(
+            "u8 x;\nfn a() { joypad.held = 1; }\nfn b() { x = joypad.pressed[1]; }\nfn c() { joypad.update(1); }\n\
+             fn d() { x = joypad.update(); }\nfn e() { x = joypad.X; }",
+        );
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![
+                error("Cannot assign to joypad.held, it is read-only", 2),
+                error("Cannot index joypad.pressed", 3),
+                error("Wrong number of arguments, expected joypad.update()", 4),
+                error("joypad.update does not return a value", 5),
+                error("Undefined variable: joypad.X", 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn sprites_use_shadow_oam() {
+        let options = Options {
+            startup: true,
+            sprites: true,
+            ..Options::default()
+        };
+        let src = String::from(
+            "u8 i; interrupt(vblank) fn draw() { oam_dma(); }
+            fn main() { zero(oam); oam[0].y = 16; oam[i].tile = oam[39].flags; i = sizeof(oam); }",
+        );
+        let unit = compile_with(src, &options).unwrap();
+        assert!(unit.warnings.is_empty());
+        let asm = unit.asm;
+        assert!(asm.contains("SECTION \"Shadow OAM\", WRAM0, ALIGN[8]\n\nwShadowOam:: ds 160\n"));
+        assert!(asm.contains("hOamDma:: ds tgOamDmaRoutine.end - tgOamDmaRoutine\n"));
+        assert!(asm
+            .contains("\tld de, tgOamDmaRoutine\n\tld hl, hOamDma\n\tld bc, tgOamDmaRoutine.end - tgOamDmaRoutine\n"));
+        assert!(asm.contains("\tld a, HIGH(wShadowOam)\n\tcall hOamDma\n"));
+        assert!(asm.contains("\tld bc, 160\n\tld hl, wShadowOam\n\tcall tgZeroMemory\n"));
+        assert!(asm.contains("\tld a, 16\n\tpush af\n\tld hl, wShadowOam + 0\n\tpop af\n\tld [hl], a\n"));
+        assert!(asm.contains(
+            "\tld hl, wShadowOam + 159\n\tld a, [hl]\n\tpush af\n\tld a, [i]\n\tadd a\n\tadd a\n\tadd a, 2\n\tld l, a\n\
+             \tld h, HIGH(wShadowOam)\n\tpop af\n\tld [hl], a\n"
+        ));
+
+        let src = String::from("hram u8[119] a; fn main() { a[0] = 1; }");
+        let unit = compile_with(src, &options).unwrap();
+        assert_eq!(
+            unit.warnings,
+            vec![error("HRAM budget exceeded by a: 128 bytes used, 127 available", 1)]
+        );
+    }
+
+    #[test]
+    fn error_sprite_misuse() {
+        let options = Options {
+            startup: true,
+            sprites: true,
+            ..Options::default()
+        };
+        let src = String::from(
+            "u8 x; u8[2] a;\nfn b() { oam[1] = 2; }\nfn c() { oam[0].z = 1; }\nfn d() { oam[40].x = 1; }\n\
+             fn e() { x = a[0].y; }\nfn f() { oam = 1; }\nfn main() { oam_dma(1); }",
+        );
+        let errors = compile_with(src, &options).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![
+                error("Sprites in oam are accessed by field (y, x, tile or flags)", 2),
+                error("Sprites have no field z (expected y, x, tile or flags)", 3),
+                error("Index out of bounds for oam (40 sprites)", 4),
+                error("a has no field y", 5),
+                error("Cannot assign to memory region (index into it instead)", 6),
+                error("Wrong number of arguments, expected oam_dma()", 7),
+            ]
+        );
+
+        let src = String::from("fn main() { oam_dma(); }\nfn draw() { oam[0].x = 1; }");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![
+                error("oam_dma needs the sprite runtime enabled", 1),
+                error("Undefined variable: oam", 2),
+            ]
+        );
+
+        // Library units can reserve the shadow OAM, but only a program's startup code installs the routine.
+        let options = Options {
+            sprites: true,
+            ..Options::default()
+        };
+        let errors = compile_with(String::from("fn main() { oam_dma(); }"), &options)
+            .expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![error("oam_dma needs the startup code to install the DMA routine", 1)]
+        );
+    }
+
+    #[test]
+    fn sound_registers_and_effects() {
+        let src = String::from(
+            "rom u8[] beep = {4, 0, sound.DUTY_50, $F0, $00, sound.TRIGGER, 0};
+            fn main() { sound.master = sound.ON; sound.ch2_envelope = $F3; sound.play(beep, 2); sound.update(); }",
+        );
+        let asm = compile(src).unwrap();
+        assert!(asm.contains("tgPlaySound::\n"));
+        assert!(asm.contains("beep::\n\tdb 4, 0, 128, 240, 0, 128, 0\n"));
+        assert!(asm.contains("\tld a, 128\n\tldh [$FF26], a\n"));
+        assert!(asm.contains("\tld a, 243\n\tldh [$FF17], a\n"));
+        assert!(asm.contains("\tld hl, beep\n\tld a, $15\n\tcall tgPlaySound\n"));
+        assert!(asm.contains("\tcall tgUpdateSound\n"));
+    }
+
+    #[test]
+    fn error_sound_misuse() {
+        let options = Options {
+            mbc: Mbc::Mbc1,
+            ..Options::default()
+        };
+        let src = String::from(
+            "rom u8[] beep = {0}; @bank(2) rom u8[] far = {0}; u8 x;\nfn a() { sound.play(beep, 5); }\n\
+             fn b() { sound.play(x, 1); }\nfn c() { sound.play(far, 1); }\nfn d() { sound.update(1); }\n\
+             fn e() { sound.play(beep, x); }",
+        );
+        let errors = compile_with(src, &options).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![
+                error("Sound channel must be 1, 2, 3 or 4", 2),
+                error("sound.play expects a rom table, not x", 3),
+                error("Sound effect far must be in ROM0 to be played", 4),
+                error("Wrong number of arguments, expected sound.update()", 5),
+                error("Sound channel must be 1, 2, 3 or 4", 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn cgb_features() {
+        let options = Options {
+            startup: true,
+            cgb: true,
+            ..Options::default()
+        };
+        let src = String::from(
+            "rom u8[] greys = {#FFFFFF, #AAAAAA, #555555, #000000}; @bank(1) rom u8[16] sprites = {};
+            @wramx(3) u8[64] scratch; @wramx(2) u8 level; @wramx(2) u8[4] lives;
+            fn main() { cgb_double_speed(); bg_palette(0, greys); obj_palette(6, sprites); vram_bank(1);
+            wram_bank(3); zero(scratch); level = 2; scratch[1] = level; lives[level] = 3; }",
+        );
+        let asm = compile_with(src, &options).unwrap().asm;
+        assert!(asm
+            .contains("\tjp tgStart\n\t; The rest of the header is filled in by rgbfix\n\tds $143 - @, 0\n\tdb $C0\n"));
+        assert!(asm.contains("tgCopyPalette::\n"));
+        assert!(asm.contains("greys::\n\tdb 255, 127, 181, 86, 74, 41, 0, 0\n"));
+        assert!(asm.contains("SECTION \"WRAMX Variables (bank 3)\", WRAMX, BANK[3]\nscratch:: ds 64\n"));
+        assert!(asm.contains("\tcall tgDoubleSpeed\n"));
+        assert!(asm
+            .contains("\tld a, $80\n\tldh [$FF68], a\n\tld c, $69\n\tld b, 8\n\tld hl, greys\n\tcall tgCopyPalette\n"));
+        assert!(asm.contains("\tld a, $B0\n\tldh [$FF6A], a\n\tld c, $6B\n\tld b, 16\n\tld hl, sprites\n"));
+        assert!(asm.contains("\tld a, 1\n\tldh [$FF4F], a\n"));
+        assert!(asm.contains("\tld a, 3\n\tldh [$FF70], a\n"));
+        // Variables in the extra WRAM banks switch to them around each access, then put back whatever was mapped.
+        let switch = |name: &str, body: &str| {
+            format!("\tldh a, [$FF70]\n\tpush af\n\tld a, BANK({name})\n\tldh [$FF70], a\n{body}\tpop af\n\tldh [$FF70], a\n")
+        };
+        assert!(asm.contains(&switch(
+            "scratch",
+            "\tld bc, 64\n\tld hl, scratch\n\tcall tgZeroMemory\n"
+        )));
+        assert!(asm.contains(&format!(
+            "\tld a, 2\n\tld e, a\n{}",
+            switch("level", "\tld a, e\n\tld [level], a\n")
+        )));
+        assert!(asm.contains(&format!(
+            "\tld hl, level\n{}\tld a, e\n",
+            switch("level", "\tld e, [hl]\n")
+        )));
+        assert!(asm.contains(&format!("\tld e, a\n{}", switch("scratch", "\tld [hl], e\n"))));
+        assert!(asm.contains(&format!(
+            "\tld a, 3\n\tpush af\n\tld hl, level\n{}\tld a, e\n\tld b, 0\n\tld c, a\n\tld hl, lives\n\tadd hl, bc\n\
+             \tpop af\n\tld e, a\n{}",
+            switch("level", "\tld e, [hl]\n"),
+            switch("lives", "\tld [hl], e\n")
+        )));
+
+        let asm = compile(String::from("fn main() {}")).unwrap();
+        assert!(!asm.contains("tgCopyPalette"));
+    }
+
+    #[test]
+    fn error_cgb_misuse() {
+        let options = Options {
+            cgb: true,
+            ..Options::default()
+        };
+        let src = String::from(
+            "rom u8[6] odd = {}; rom u8[16] two = {}; u8 x;\nfn a() { bg_palette(8, two); }\n\
+             fn b() { bg_palette(0, odd); }\nfn c() { obj_palette(7, two); }\nfn d() { vram_bank(2); }\n\
+             fn e() { wram_bank(x); }\nfn f() { x = #FF0000; }",
+        );
+        let errors = compile_with(src, &options).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![error("Colour literals can only be used in initialisers.", 7)]
+        );
+
+        let src = String::from(
+            "rom u8[6] odd = {}; rom u8[16] two = {}; u8 x;\nfn a() { bg_palette(8, two); }\n\
+             fn b() { bg_palette(0, odd); }\nfn c() { obj_palette(7, two); }\nfn d() { vram_bank(2); }\n\
+             fn e() { wram_bank(x); }",
+        );
+        let errors = compile_with(src, &options).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![
+                error("Palette must be a number from 0 to 7", 2),
+                error("odd should hold whole palettes of four colours, not 6 bytes", 3),
+                error("two runs past palette 7", 4),
+                error("vram_bank expects a bank from 0 to 1", 5),
+                error("wram_bank expects a bank from 1 to 7", 6),
+            ]
+        );
+
+        let src = String::from("@wramx(2) u8[4] a; @wramx(3) u8[4] b;\nfn main() { copy(a, b); }");
+        let errors = compile_with(src, &options).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![error(
+                "copy can't reach a and b at once, they're in different WRAM banks",
+                2
+            )]
+        );
+
+        let src = String::from("@wramx(2) u8 x;\nfn main() { cgb_double_speed(); }");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![
+                error("WRAM bank 2 needs CGB mode (only bank 1 without it)", 1),
+                error("cgb_double_speed needs CGB mode enabled", 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn placement_attributes_choose_sections() {
+        let src = String::from(
+            "@wramx(1) @align(8) u8[160] shadow; @at($C100) u8 fixed;
+            @section(\"Gameplay\") @bank(3) fn update() {} @section(\"Gameplay\") @bank(3) fn draw() {}",
+        );
+        let options = Options {
+            mbc: Mbc::Mbc1,
+            ..Options::default()
+        };
+        let asm = compile_with(src, &options).unwrap().asm;
+        assert!(asm.contains("SECTION \"Variables\", WRAM0\nSECTION \"Functions\", ROM0\n"));
+        assert!(
+            asm.contains("SECTION \"WRAMX Variables (bank 1) (align 8)\", WRAMX, BANK[1], ALIGN[8]\nshadow:: ds 160\n")
+        );
+        assert!(asm.contains("SECTION \"fixed\", WRAM0[$C100]\nfixed:: ds 1\n"));
+        assert!(asm.contains("SECTION \"Gameplay\", ROMX, BANK[3]\nupdate::\n\tret\ndraw::\n\tret\n"));
+    }
+
+    #[test]
+    fn bank_zero_is_rom0() {
+        let asm = compile(String::from("@bank(0) rom u8 table = {1}; @bank(0) fn main() {}")).unwrap();
+        assert!(asm.contains("SECTION \"Data\", ROM0\ntable::\n"));
+        assert!(asm.contains("SECTION \"Functions\", ROM0\n"));
+        assert!(!asm.contains("BANK[0]"));
+    }
+
+    #[test]
+    fn error_conflicting_sections() {
+        let src = String::from("@section(\"Shared\") u8 a;\n@section(\"Shared\") @wramx u8 b;");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(errors, vec![error("Section \"Shared\" is already placed elsewhere", 2)]);
+    }
+
+    #[test]
+    fn far_calls_switch_banks() {
+        let src = String::from(
+            "@bank(2) fn load() {} @bank(2) fn update() { load(); } @bank(3) fn draw() { update(); }
+            fn main() { update(); draw(); }",
+        );
+        let options = Options {
+            mbc: Mbc::Mbc5,
+            startup: true,
+            ..Options::default()
+        };
+        let asm = compile_with(src, &options).unwrap().asm;
+        assert!(asm.contains("tgSetBank::\n\tldh [hCurrentBank], a\n\tld [$2100], a\n\tret\n"));
+        assert!(asm.contains("update::\n\tcall load\n\tret\n"));
+        assert!(asm.contains("draw::\n\tcall tgFar_update\n\tret\n"));
+        assert!(asm.contains("main::\n\tcall tgFar_update\n\tcall tgFar_draw\n\tret\n"));
+        assert!(asm.contains(
+            "SECTION \"Far calls\", ROM0\ntgFar_draw:\n\tldh a, [hCurrentBank]\n\tpush af\n\tld a, BANK(draw)\n\
+             \tcall tgSetBank\n\tcall draw\n\tpop af\n\tjp tgSetBank\ntgFar_update:\n"
+        ));
+        assert!(!asm.contains("tgFar_load"));
+
+        // Without an MBC, bank 1 is always mapped and calls into it are direct.
+        let asm = compile(String::from("@bank(1) fn update() {} fn main() { update(); }")).unwrap();
+        assert!(asm.contains("main::\n\tcall update\n"));
+        assert!(!asm.contains("tgSetBank"));
+    }
+
+    #[test]
+    fn banked_rom_data() {
+        let src = String::from(
+            "rom u8[4] header = {1, hw.LCDCF_ON}; @bank(2) rom u8[] table = {3, 4}; @bank(2) rom u8 far = {5};
+            u8 value; u8[4] buffer; fn main() { value = header[1]; value = table[1]; buffer[value] = far; }",
+        );
+        let options = Options {
+            mbc: Mbc::Mbc3,
+            startup: true,
+            ..Options::default()
+        };
+        let asm = compile_with(src, &options).unwrap().asm;
+        assert!(asm.contains("SECTION \"Data\", ROM0\nheader::\n\tdb 1, 128\n\tds 2, 0\n"));
+        assert!(asm.contains("SECTION \"Data (bank 2)\", ROMX, BANK[2]\ntable::\n\tdb 3, 4\n"));
+        assert!(asm.contains("\tld hl, header\n\tadd hl, bc\n\tld a, [hl]\n"));
+        assert!(asm.contains(
+            "\tld hl, table\n\tadd hl, bc\n\tldh a, [hCurrentBank]\n\tpush af\n\tld a, BANK(table)\n\
+             \tcall tgSetBank\n\tld e, [hl]\n\tpop af\n\tcall tgSetBank\n\tld a, e\n"
+        ));
+        // Reading the value points hl at it, so the target's address is only worked out afterwards.
+        assert!(asm.contains(
+            "\tld hl, far\n\tldh a, [hCurrentBank]\n\tpush af\n\tld a, BANK(far)\n\tcall tgSetBank\n\tld e, [hl]\n\
+             \tpop af\n\tcall tgSetBank\n\tld a, e\n\tpush af\n\tld a, [value]\n\tld b, 0\n\tld c, a\n\tld hl, buffer\n\
+             \tadd hl, bc\n\tpop af\n\tld [hl], a\n"
+        ));
+    }
+
+    #[test]
+    fn error_banking_misuse() {
+        let src = String::from("@bank(2) fn far() {}\nfn main() { far(); }");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![error("ROM bank 2 is not available with no MBC (banks 1 to 1)", 1)]
+        );
+
+        let src = String::from(
+            "@bank(2) rom u8 table = {1};\n@bank(3) fn read() { table = 1; }\n@bank(3) fn write() { hw.rLY = table; }
+            fn value() { hw.rLY = read(); }\nfn args() { read(1); }\nu8 x; fn notFunction() { x(); }",
+        );
+        let options = Options {
+            mbc: Mbc::Mbc1,
+            ..Options::default()
+        };
+        let errors = compile_with(src, &options).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![
+                error("read does not return a value", 4),
+                error("Passing arguments to functions is not supported yet", 5),
+                error("Cannot call x, it is not a function", 6),
+                error("Cannot assign to ROM data", 2),
+                error("Cannot read table in ROM bank 2 from code in ROM bank 3", 3),
+            ]
+        );
+
+        // Without the startup code, nothing sets the bank that switching saves and restores.
+        let src = String::from("@bank(2) rom u8 table = {1};\n@bank(2) fn far() {}\nu8 x;\nfn main() { far(); }\nfn read() { x = table[0]; }");
+        let errors = compile_with(src, &options).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![
+                error(
+                    "Switching ROM banks to reach far needs the startup code to set the current bank",
+                    4
+                ),
+                error(
+                    "Switching ROM banks to reach table needs the startup code to set the current bank",
+                    5
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn error_rom_data_not_constant() {
+        let src = String::from("u8 x;\nrom u8[2] table = {1, x};");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![error("ROM data can only be initialised with constants", 2)]
+        );
+
+        // Constant arithmetic is folded at compile time, but the result still has to fit in a byte.
+        let asm = compile(String::from("rom u8[] table = {hw.LCDCF_ON | 1, sizeof(table) + 1};")).unwrap();
+        assert!(asm.contains("table::\n\tdb 129, 3\n"));
+        let src = String::from("rom u8[2] table = {1 - 2};");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(errors, vec![error("-1 doesn't fit in a u8", 1)]);
+    }
+
+    #[test]
+    fn interrupt_handlers_and_vectors() {
+        let src = String::from(
+            "u8 frames; interrupt(vblank) fn onVBlank() { frames = 1; atomic { halt; } }
+            interrupt(timer) fn onTimer() {} fn main() { di; ei; atomic { frames = 2; atomic { halt; } } }",
+        );
+        let options = Options {
+            startup: true,
+            ..Options::default()
+        };
+        let asm = compile_with(src, &options).unwrap().asm;
+        assert!(asm.contains("SECTION \"Interrupt state\", HRAM\n"));
+        assert!(asm.contains(
+            "onVBlank::\n\tpush af\n\tpush bc\n\tpush de\n\tpush hl\n\txor a\n\tldh [hInterruptsEnabled], a\n\
+             \tld a, 1\n\tld [frames], a\n\thalt\n\tld a, 1\n\tldh [hInterruptsEnabled], a\n\
+             \tpop hl\n\tpop de\n\tpop bc\n\tpop af\n\treti\n"
+        ));
+        // Atomic blocks put back whatever state they found, rather than always enabling interrupts afterwards.
+        assert!(asm.contains(
+            "main::\n\tdi\n\txor a\n\tldh [hInterruptsEnabled], a\n\tld a, 1\n\tldh [hInterruptsEnabled], a\n\tei\n\
+             \tldh a, [hInterruptsEnabled]\n\tpush af\n\tdi\n\txor a\n\tldh [hInterruptsEnabled], a\n\
+             \tld a, 2\n\tld [frames], a\n\thalt\n\tpop af\n\tldh [hInterruptsEnabled], a\n\tor a\n\tjr z, .endAtomic_"
+        ));
+        assert!(asm.contains("\tei\n.endAtomic_"));
+
+        let src = String::from("fn main() {\n    atomic { halt; }\n}");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![error(
+                "atomic blocks need the startup code to track whether interrupts are enabled",
+                2
+            )]
+        );
+        assert!(asm.contains("SECTION \"vblank interrupt\", ROM0[$0040]\n\tjp onVBlank\n"));
+        assert!(asm.contains("SECTION \"timer interrupt\", ROM0[$0050]\n\tjp onTimer\n"));
+    }
+
+    #[test]
+    fn error_duplicate_interrupt_handler() {
+        let src = String::from("interrupt(stat) fn first() {}\ninterrupt(stat) fn second() {}");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(errors, vec![error("The stat interrupt already has a handler", 2)]);
+    }
+
+    #[test]
+    fn startup_runtime_calls_main() {
+        let src = String::from("u8[3] lives = {3}; hram u8 speed = {2}; fn main() {}");
+        let options = Options {
+            mbc: Mbc::Mbc1,
+            startup: true,
+            ..Options::default()
+        };
+        let asm = compile_with(src, &options).unwrap().asm;
+        assert!(asm.contains("SECTION \"Header\", ROM0[$100]\n"));
+        assert!(asm.contains("  ld sp, $E000\n"));
+        assert!(asm.contains(
+            "  call tgZeroMemory\n\tld a, 1\n\tcall tgSetBank\n\
+             \tld de, tgInit_lives\n\tld hl, lives\n\tld bc, 3\n\tcall tgCopyMemory\n\
+             \tld de, tgInit_speed\n\tld hl, speed\n\tld bc, 1\n\tcall tgCopyMemory\n\tcall main\n"
+        ));
+        assert!(asm.contains("lives:: ds 3\n"));
+        assert!(
+            asm.contains("SECTION \"Initial values\", ROM0\ntgInit_lives:\n\tdb 3\n\tds 2, 0\ntgInit_speed:\n\tdb 2\n")
+        );
+
+        let runtime = runtime(&Options {
+            startup: true,
+            ..Options::default()
+        });
+        assert!(runtime.contains("tgStart::\n"));
+        assert!(!runtime.contains("tgSetBank"));
+    }
+
+    #[test]
+    fn error_startup_needs_main() {
+        let options = Options {
+            mbc: Mbc::Mbc5,
+            startup: true,
+            ..Options::default()
+        };
+        let cases = [
+            ("fn start() {}", error("No main function to start the program from", 1)),
+            ("\nu8 main;", error("main must be a function", 2)),
+            (
+                "\ninterrupt(vblank) fn main() {}",
+                error("main can't be an interrupt handler", 2),
+            ),
+            (
+                "\n@bank(2) fn main() {}",
+                error("main must be in ROM0 to be called at startup", 2),
+            ),
+        ];
+
+        for (src, expected) in cases {
+            let errors = compile_with(String::from(src), &options).expect_err(src);
+            assert_eq!(errors, vec![expected], "{}", src);
+        }
+    }
+
+    #[test]
+    fn error_ram_initialiser_without_startup() {
+        let src = String::from("fn main() {}\nu8 count = {1};");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![error(
+                "RAM variables can only be initialised with the startup runtime enabled",
+                2
+            )]
+        );
+    }
+
+    #[test]
+    fn memory_intrinsics() {
+        let src = String::from(
+            "u8[4] small; u8[64] big; u8 count; fn main() { copy(small, big, 2); copy(big, small); zero(hw.screen0);
+            fill(small, 7, count); zero(big, count); }",
+        );
+        let asm = compile(src).unwrap();
+        assert!(asm.contains("\tld de, big\n\tld hl, small\n\tld a, [de]\n\tld [hli], a\n\tinc de\n\tld a, [de]\n"));
+        assert!(asm.contains("\tld bc, 1024\n\tld hl, $9800\n\tcall tgZeroMemory\n"));
+        assert!(asm.contains("\tld de, small\n\tld hl, big\n\tld a, [de]\n"));
+        assert!(asm.contains(
+            "\tld a, 7\n\tpush af\n\tld a, [count]\n\tld c, a\n\tld b, 0\n\tpop af\n\tld e, a\n\tld a, c\n\tor a\n"
+        ));
+        assert!(asm.contains("\tld hl, small\n\tcall tgFillMemory\n"));
+        assert!(asm.contains("\tld a, [count]\n\tld c, a\n\tld b, 0\n\tor a\n"));
+        assert!(asm.contains("\tld hl, big\n\tcall tgZeroMemory\n"));
+    }
+
+    #[test]
+    fn banked_copy_switches_banks() {
+        let src = String::from("@bank(2) rom u8[] table = {1, 2, 3}; u8[3] buffer; fn main() { copy(buffer, table); }");
+        let options = Options {
+            mbc: Mbc::Mbc1,
+            startup: true,
+            ..Options::default()
+        };
+        let asm = compile_with(src, &options).unwrap().asm;
+        assert!(asm.contains(
+            "\tldh a, [hCurrentBank]\n\tpush af\n\tld a, BANK(table)\n\tcall tgSetBank\n\tld de, table\n\tld hl, buffer\n"
+        ));
+    }
+
+    #[test]
+    fn error_intrinsic_misuse() {
+        let src = String::from(
+            "u8[4] small; u8[8] big; rom u8 data = {1};
+            fn a() {\ncopy(small, big); }\nfn b() { zero(data); }\nfn c() { fill(small); }
+            fn d() { zero(hw.rLY); }\nfn e() { zero(small[1]); }",
+        );
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![
+                error("copy of 8 bytes overruns small (4 bytes)", 3),
+                error("zero can't write to ROM data", 4),
+                error(
+                    "Wrong number of arguments, expected fill(destination, value[, length])",
+                    5
+                ),
+                error("zero expects a variable or memory region, not hw.rLY", 6),
+                error("zero expects a variable or memory region", 7),
+            ]
+        );
+
+        let errors = compile(String::from("fn copy() {}")).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![error("copy is a built-in function and can't be redefined", 1)]
+        );
+    }
+
+    #[test]
+    fn lcd_and_vram_intrinsics() {
+        let src = String::from(
+            "rom u8[] tile = {1, 2}; fn main() { lcd_off(); vram_copy(hw.tiles0, tile); lcd_on(); wait_vblank(); }",
+        );
+        let asm = compile(src).unwrap();
+        assert!(asm.contains(
+            "main::\n\tcall tgLcdOff\n\tld bc, 2\n\tld de, tile\n\tld hl, $8000\n\tcall tgCopyVram\n\
+             \tldh a, [$FF40]\n\tset 7, a\n\tldh [$FF40], a\n\tcall tgWaitVBlank\n\tret\n"
+        ));
+    }
+
+    #[test]
+    fn error_vram_intrinsic_misuse() {
+        let src = String::from(
+            "u8[2] buffer; rom u8[] tile = {1, 2};\nfn a() { vram_copy(buffer, tile); }\nfn b() { wait_vblank(1); }",
+        );
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![
+                error("vram_copy expects a destination in VRAM, not buffer", 2),
+                error("Wrong number of arguments, expected wait_vblank()", 3),
+            ]
+        );
+    }
+
+    /// A directory holding a 20 byte asset, unique to each test so they can run in parallel. It is deleted again
+    /// when dropped.
+    struct AssetDir(PathBuf);
+
+    impl AssetDir {
+        fn new(test: &str) -> AssetDir {
+            let dir = std::env::temp_dir().join(format!("tugboat-{}-{}", test, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("font.2bpp"), [0u8; 20]).unwrap();
+            AssetDir(dir)
+        }
+    }
+
+    impl Drop for AssetDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn incbin_assets() {
+        let dir = AssetDir::new("incbin_assets");
+        let options = Options {
+            base_dir: dir.0.clone(),
+            ..Options::default()
+        };
+        let src = String::from(
+            "rom u8[] font = incbin(\"font.2bpp\"); @bank(1) rom u8[] map = incbin(\"font.2bpp\", 4, 8);
+            rom u8[32] padded = incbin(\"font.2bpp\"); u8[20] buffer;
+            fn main() { copy(buffer, font); hw.rSCX = sizeof(map); zero(buffer, sizeof(map)); }",
+        );
+        let asm = compile_with(src, &options).unwrap().asm;
+        let path = options.base_dir.join("font.2bpp");
+        assert!(asm.contains(format!("font::\n\tINCBIN \"{}\", 0, 20\n", path.display()).as_str()));
+        assert!(asm.contains(format!("map::\n\tINCBIN \"{}\", 4, 8\n", path.display()).as_str()));
+        assert!(asm.contains(format!("padded::\n\tINCBIN \"{}\", 0, 20\n\tds 12, 0\n", path.display()).as_str()));
+        assert!(asm.contains("\tld bc, 20\n\tld de, font\n\tld hl, buffer\n\tcall tgCopyMemory\n"));
+        assert!(asm.contains("\tld a, 8\n\tldh [$FF43], a\n"));
+        assert!(asm.contains("\txor a\n\tld hl, buffer\n\tld [hli], a\n"));
+    }
+
+    #[test]
+    fn error_incbin_assets() {
+        let dir = AssetDir::new("error_incbin_assets");
+        let options = Options {
+            base_dir: dir.0.clone(),
+            ..Options::default()
+        };
+        let src = String::from(
+            "rom u8[] a = incbin(\"missing.2bpp\");\nrom u8[] b = incbin(\"font.2bpp\", 16, 8);\n\
+             rom u8[4] c = incbin(\"font.2bpp\");\nrom u8[0] d = incbin(\"font.2bpp\");\n\
+             rom u8[] e = incbin(\"font.2bpp\", 21);\nrom u8[] f = incbin(\"font.2bpp\", 20);",
+        );
+        let errors = compile_with(src, &options).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(errors.len(), 5);
+        assert!(errors[0].msg.starts_with("Unable to read asset \"missing.2bpp\": "));
+        assert_eq!(
+            errors[1..],
+            [
+                error("Slice of \"font.2bpp\" runs past the end of the file (20 bytes)", 2),
+                error("c is too small for \"font.2bpp\" (20 bytes)", 3),
+                error("d is too small for \"font.2bpp\" (20 bytes)", 4),
+                error("Offset 21 is past the end of \"font.2bpp\" (20 bytes)", 5),
+            ]
+        );
+
+        let src = String::from("rom u8[] font = incbin(\"font.2bpp\");\nfn main() { hw.rSCX = sizeof(font); }");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert!(errors[0].msg.starts_with("Unable to read asset"));
+
+        let src = String::from("u8[300] big;\nfn main() { hw.rSCX = sizeof(big); }");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![error("sizeof(big) is 300 bytes, which is too large for a u8", 2)]
+        );
+    }
+
+    /// Writes an 8-bit greyscale PNG into the test's asset directory.
+    fn write_png(dir: &std::path::Path, name: &str, width: u32, height: u32, pixels: &[u8]) {
+        let file = std::fs::File::create(dir.join(name)).unwrap();
+        let mut encoder = png::Encoder::new(file, width, height);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(pixels).unwrap();
+    }
+
+    #[test]
+    fn graphics_assets() {
+        let dir = AssetDir::new("graphics_assets");
+        // Three tiles in a row: a gradient, solid white, then the gradient again.
+        let mut pixels = Vec::new();
+        for _ in 0..8 {
+            pixels.extend([0, 85, 170, 255, 0, 85, 170, 255]);
+            pixels.extend([255; 8]);
+            pixels.extend([0, 85, 170, 255, 0, 85, 170, 255]);
+        }
+        write_png(&dir.0, "tiles.png", 24, 8, &pixels);
+        write_png(&dir.0, "tall.png", 8, 16, &[[0; 64], [255; 64]].concat());
+
+        let options = Options {
+            base_dir: dir.0.clone(),
+            ..Options::default()
+        };
+        let src = String::from(
+            "rom u8[] all = tiles(\"tiles.png\"); rom u8[] unique = tiles(\"tiles.png\", dedupe);
+            rom u8[] map = tilemap(\"tiles.png\", dedupe); rom u8[4] padded = tilemap(\"tiles.png\");
+            rom u8[] sprite = tiles(\"tall.png\", tall); rom u8[] sprite_map = tilemap(\"tall.png\", tall);",
+        );
+        let asm = compile_with(src, &options).unwrap().asm;
+
+        let gradient = "\tdb 170, 204, 170, 204, 170, 204, 170, 204, 170, 204, 170, 204, 170, 204, 170, 204\n";
+        let white = "\tdb 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0\n";
+        let black = "\tdb 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255\n";
+        assert!(asm.contains(format!("all::\n{}{}{}", gradient, white, gradient).as_str()));
+        assert!(asm.contains(format!("unique::\n{}{}map::", gradient, white).as_str()));
+        assert!(asm.contains("map::\n\tdb 0, 1, 0\n"));
+        assert!(asm.contains("padded::\n\tdb 0, 1, 2\n\tds 1, 0\n"));
+        assert!(asm.contains(format!("sprite::\n{}{}", black, white).as_str()));
+        assert!(asm.contains("sprite_map::\n\tdb 0\n"));
+    }
+
+    #[test]
+    fn error_graphics_assets() {
+        let dir = AssetDir::new("error_graphics_assets");
+        write_png(&dir.0, "odd.png", 12, 8, &[255; 96]);
+        write_png(&dir.0, "tile.png", 8, 8, &[255; 64]);
+        let options = Options {
+            base_dir: dir.0.clone(),
+            ..Options::default()
+        };
+        let src = String::from(
+            "rom u8[] a = tiles(\"missing.png\");\nrom u8[] b = tiles(\"odd.png\");\n\
+             rom u8[] c = tiles(\"tile.png\", tall);\nrom u8[8] d = tiles(\"tile.png\");\n\
+             rom u8[] e = tiles(\"font.2bpp\");",
+        );
+        let errors = compile_with(src, &options).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(errors.len(), 5);
+        assert!(errors[0].msg.starts_with("Unable to convert \"missing.png\": "));
+        assert_eq!(
+            errors[1..4],
+            [
+                error(
+                    "Unable to convert \"odd.png\": 12x8 isn't a whole number of 8x8 tiles",
+                    2
+                ),
+                error(
+                    "Unable to convert \"tile.png\": 8x8 isn't a whole number of 8x16 tiles",
+                    3
+                ),
+                error("d is too small for \"tile.png\" (16 bytes)", 4),
+            ]
+        );
+        assert!(errors[4].msg.starts_with("Unable to convert \"font.2bpp\": "));
+    }
+
+    #[test]
+    fn error_spans_point_at_source() {
+        let src = String::from("u8 x;\nfn main() {\n  x = nope[x + 1];\n}");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        let Span {
+            offset,
+            length,
+            line,
+            column,
+        } = errors[0].span;
+        assert_eq!((offset, length, line, column), (24, 4, 3, 7));
+
+        // Running out of tokens is reported at the end of the source rather than on line 0.
+        let src = String::from("u8 x;\nfn main() {\n  x = 1;\n");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(errors[0].span.line, 4);
+        assert_eq!(errors[0].span.offset, 27);
+    }
+
+    #[test]
+    fn error_recovery_reports_each_mistake_once() {
+        let src = String::from(
+            "u8 x;\nfn a() {\n  x = 1 +;\n  while (x { halt; }\n  x = 2;\n}\nu8 = 3;\nfn b() {\n  x = );\n\
+             fn c() { x = 1 }\nfn d() {}",
+        );
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![
+                error("Expected number or identifier in expression.", 3),
+                error("Expected ')' after while condition.", 4),
+                error("Expected variable name.", 7),
+                error("Expected number or identifier in expression.", 9),
+                error("Expected '}' at end of block.", 10),
+                error("Expected ';' after expression (nested expressions not supported).", 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn error_no_nested_expressions() {
+        let src = String::from("u8 variable; fn main() { variable = 1 + 2 + 3; }");
+        let errors = compile(src).expect_err("Expected compilation errors from bad script!");
+        assert_eq!(
+            errors,
+            vec![error(
+                "Expected ';' after expression (nested expressions not supported).",
+                1
+            )]
+        );
     }
 }
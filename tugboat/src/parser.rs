@@ -2,46 +2,88 @@ use super::*;
 use lexer::TokenKind;
 use lexer::TokenKind::*;
 
-pub fn parse(tokens: Vec<Token>) -> (Vec<Declaration>, Vec<CompilationError>) {
-    let mut queue: VecDeque<_> = tokens.into_iter().collect();
+pub fn parse(tokens: Vec<Token>) -> (Vec<Declaration>, Vec<Diagnostic>) {
+    let mut queue = documented(tokens);
     let mut declarations: Vec<Declaration> = Vec::new();
-    let mut errors: Vec<CompilationError> = Vec::new();
+    let mut errors: Vec<Diagnostic> = Vec::new();
 
-    while queue.len() > 0 && !is_end(&queue) {
-        let result = declaration(&mut queue);
-        if let Ok(dec) = result {
-            declarations.push(dec);
-        } else if let Err(err) = result {
-            // TODO: Consider what else to do in error case e.g. synchronise here
-            errors.push(err);
+    while !queue.is_empty() && !is_end(&queue) {
+        let remaining = queue.len();
+        match declaration(&mut queue, &mut errors) {
+            Ok(dec) => declarations.push(dec),
+            Err(err) => {
+                errors.push(err);
+                // Always move past the token the error was found at, so it can't be reported twice.
+                if queue.len() == remaining {
+                    next(&mut queue).ok();
+                }
+                synchronise(&mut queue, true);
+            }
         }
     }
 
     (declarations, errors)
 }
 
-fn error(line: u32, msg: &'static str) -> CompilationError {
-    CompilationError {
-        line,
-        msg: msg.to_string(),
+/// Drops doc comments that aren't in front of a declaration, treating them like any other comment, so that the
+/// ones left can be taken as part of the declaration they document.
+fn documented(tokens: Vec<Token>) -> VecDeque<Token> {
+    let mut queue: VecDeque<Token> = VecDeque::new();
+    let mut depth = 0;
+    // The previous token that isn't a doc comment, and the doc comments since then.
+    let mut previous: Option<TokenKind> = None;
+    let mut docs: Vec<Token> = Vec::new();
+
+    for token in tokens {
+        if token.kind == DocComment {
+            docs.push(token);
+            continue;
+        }
+
+        let between_declarations = depth == 0 && matches!(previous, None | Some(Semicolon | RightBrace));
+        if between_declarations && starts_declaration(&token) {
+            queue.extend(docs.drain(..));
+        }
+        docs.clear();
+
+        match token.kind {
+            LeftBrace => depth += 1,
+            RightBrace => depth -= 1,
+            _ => {}
+        }
+        previous = Some(token.kind.clone());
+        queue.push_back(token);
     }
+
+    queue
+}
+
+fn error(span: Span, msg: &'static str) -> Diagnostic {
+    Diagnostic::error(span, msg)
 }
 
 fn is_end(queue: &VecDeque<Token>) -> bool {
-    queue.get(0).is_some_and(|t| t.kind == EOF)
+    queue.front().is_some_and(|t| t.kind == EOF)
 }
 
-fn peek(queue: &VecDeque<Token>) -> Result<&Token, CompilationError> {
-    queue.get(0).ok_or(error(0, "Expected a token in the parse queue."))
+fn peek(queue: &VecDeque<Token>) -> Result<&Token, Diagnostic> {
+    queue
+        .front()
+        .ok_or(error(Span::default(), "Expected a token in the parse queue."))
 }
 
-fn next(queue: &mut VecDeque<Token>) -> Result<Token, CompilationError> {
+/// Takes the next token, except for the final EOF which stays put so that running off the end of the source is
+/// reported where the source ends.
+fn next(queue: &mut VecDeque<Token>) -> Result<Token, Diagnostic> {
+    if is_end(queue) {
+        return peek(queue).cloned();
+    }
     queue
         .pop_front()
-        .ok_or(error(0, "Expected a token in the parse queue."))
+        .ok_or(error(Span::default(), "Expected a token in the parse queue."))
 }
 
-fn _next_if(queue: &mut VecDeque<Token>, kind: TokenKind) -> Result<bool, CompilationError> {
+fn _next_if(queue: &mut VecDeque<Token>, kind: TokenKind) -> Result<bool, Diagnostic> {
     if peek(queue)?.kind == kind {
         next(queue)?;
         Ok(true)
@@ -50,35 +92,262 @@ fn _next_if(queue: &mut VecDeque<Token>, kind: TokenKind) -> Result<bool, Compil
     }
 }
 
-fn expect(queue: &mut VecDeque<Token>, kind: TokenKind, msg: &'static str) -> Result<Token, CompilationError> {
-    let token = next(queue)?;
+/// Takes the next token if it is the kind expected. Otherwise it is left in place, so that error recovery can
+/// decide whether it ends the broken statement or declaration.
+fn expect(queue: &mut VecDeque<Token>, kind: TokenKind, msg: &'static str) -> Result<Token, Diagnostic> {
+    let token = peek(queue)?;
 
     if token.kind == kind {
-        Ok(token)
+        next(queue)
     } else {
-        Err(error(token.line, msg))
+        Err(error(token.span, msg))
     }
 }
 
-fn discard_line(queue: &mut VecDeque<Token>) {
-    while !is_end(queue)
-        && match queue.pop_front().unwrap().kind {
-            Semicolon => false,
-            _ => true,
+/// Whether a token can only begin a declaration, which is where parsing picks up again after an error.
+fn starts_declaration(token: &Token) -> bool {
+    matches!(
+        token.kind,
+        DocComment | At | Pub | Interrupt | Fn | Unsigned8 | Hram | Rom
+    )
+}
+
+/// Skips the rest of a statement or declaration after an error, so that parsing can carry on with the next one
+/// instead of reporting errors that follow from the first. Stops after a `;` or a `}` closing a nested block, or
+/// before anything that starts a declaration. A `}` that isn't nested ends the enclosing block, so inside a block
+/// it is left for the block to take, while at the top level it is skipped as the end of a broken function.
+fn synchronise(queue: &mut VecDeque<Token>, top_level: bool) {
+    let mut depth = 0;
+
+    while let Some(token) = queue.front().filter(|token| token.kind != EOF) {
+        if depth == 0 && (starts_declaration(token) || (!top_level && token.kind == RightBrace)) {
+            return;
+        }
+
+        match queue.pop_front().unwrap().kind {
+            Semicolon if depth == 0 => return,
+            LeftBrace => depth += 1,
+            RightBrace if depth <= 1 => return,
+            RightBrace => depth -= 1,
+            _ => {}
         }
-    {}
+    }
 }
 
-fn declaration(queue: &mut VecDeque<Token>) -> Result<Declaration, CompilationError> {
-    let token = next(queue)?;
-    match token.kind {
-        Fn => function(queue),
-        Unsigned8 => variable(queue),
-        _ => Err(error(token.line, "Unsupported top-level statement.")),
+/// An `@name(argument)` annotation preceding a declaration.
+pub struct Attribute {
+    pub name: Token,
+    pub argument: Option<Token>,
+}
+
+fn declaration(queue: &mut VecDeque<Token>, errors: &mut Vec<Diagnostic>) -> Result<Declaration, Diagnostic> {
+    let start = peek(queue)?.span;
+    let mut doc: Vec<String> = Vec::new();
+    while peek(queue)?.kind == DocComment {
+        doc.push(next(queue)?.lexeme);
+    }
+
+    let attributes = attributes(queue)?;
+    let mut token = next(queue)?;
+
+    let public = token.kind == Pub;
+    if public {
+        token = next(queue)?;
+    }
+
+    let mut interrupt = None;
+    if token.kind == Interrupt {
+        interrupt = Some(interrupt_kind(queue)?);
+        token = expect(queue, Fn, "Expected 'fn' after interrupt.")?;
     }
+
+    let mut declaration = match token.kind {
+        Fn => function(queue, public, interrupt, &attributes, errors),
+        Unsigned8 => variable(queue, public, Memory::Wram0, &attributes),
+        Hram => {
+            expect(
+                queue,
+                Unsigned8,
+                "Expected a type after 'hram' (only variables can be placed in HRAM).",
+            )?;
+            variable(queue, public, Memory::Hram, &attributes)
+        }
+        Rom => {
+            expect(queue, Unsigned8, "Expected a type after 'rom'.")?;
+            variable(queue, public, Memory::Rom0, &attributes)
+        }
+        _ if public => Err(error(token.span, "Expected 'fn' or a type after 'pub'.")),
+        _ => Err(error(token.span, "Unsupported top-level statement.")),
+    }?;
+
+    match &mut declaration {
+        Declaration::Variable { doc: lines, extent, .. } | Declaration::Function { doc: lines, extent, .. } => {
+            *lines = doc;
+            // Only the end is known where the declaration itself is parsed.
+            *extent = start.to(*extent);
+        }
+    }
+    Ok(declaration)
+}
+
+pub fn attributes(queue: &mut VecDeque<Token>) -> Result<Vec<Attribute>, Diagnostic> {
+    let mut attributes: Vec<Attribute> = Vec::new();
+
+    while peek(queue)?.kind == At {
+        next(queue)?;
+        let name = expect(queue, Identifier, "Expected attribute name after '@'.")?;
+
+        let mut argument = None;
+        if peek(queue)?.kind == LeftParen {
+            next(queue)?;
+            argument = Some(next(queue)?);
+            expect(queue, RightParen, "Expected ')' after attribute argument.")?;
+        }
+
+        attributes.push(Attribute { name, argument });
+    }
+
+    Ok(attributes)
+}
+
+/// Works out where a declaration should live from its attributes, starting from the default memory type for
+/// that kind of declaration.
+pub fn placement(attributes: &[Attribute], default: Memory) -> Result<Placement, Diagnostic> {
+    let mut placement = Placement {
+        memory: default,
+        ..Placement::default()
+    };
+    let in_rom = default == Memory::Rom0;
+
+    for attribute in attributes {
+        let span = attribute.name.span;
+        match attribute.name.lexeme.as_str() {
+            "section" => {
+                let argument = attribute_argument(attribute, StringLiteral, "@section expects a section name.")?;
+                placement.section = Some(argument.lexeme.clone());
+            }
+            "bank" if in_rom => {
+                let argument = attribute_argument(attribute, Number, "@bank expects a bank number.")?;
+                let bank = get_wide_value(argument)?;
+                // Bank 0 is ROM0, which is never switched and can't be given a bank in the section.
+                if bank == 0 {
+                    placement.memory = Memory::Rom0;
+                } else {
+                    placement.memory = Memory::RomX;
+                    placement.bank = Some(bank);
+                }
+            }
+            "bank" => return Err(error(span, "@bank only applies to ROM (use @wramx for variables).")),
+            "wramx" if default == Memory::Wram0 => {
+                placement.memory = Memory::WramX;
+                if attribute.argument.is_some() {
+                    let argument = attribute_argument(attribute, Number, "@wramx expects a bank number.")?;
+                    let bank = get_wide_value(argument)?;
+                    if !(1..=7).contains(&bank) {
+                        return Err(error(argument.span, "WRAMX banks are numbered 1 to 7."));
+                    }
+                    placement.bank = Some(bank);
+                }
+            }
+            "wramx" if in_rom => return Err(error(span, "@wramx only applies to variables (use @bank for ROM).")),
+            "wramx" => return Err(error(span, "@wramx only applies to non-HRAM variables.")),
+            "at" => {
+                let argument = attribute_argument(attribute, Number, "@at expects an address.")?;
+                placement.address = Some(get_wide_value(argument)?);
+            }
+            "align" => {
+                let argument = attribute_argument(attribute, Number, "@align expects a number of bits.")?;
+                let align = get_value(argument)?;
+                if align > 16 {
+                    return Err(error(argument.span, "@align cannot exceed 16 bits."));
+                }
+                placement.align = Some(align);
+            }
+            // Lint levels don't affect placement, see `lint_levels`.
+            "allow" | "warn" | "deny" => {}
+            _ => return Err(error(span, "Unknown attribute.")),
+        }
+    }
+
+    // A fixed address already implies which kind of memory the declaration is in.
+    if let Some(address) = placement.address {
+        let span = attributes.iter().find(|a| a.name.lexeme == "at").unwrap().name.span;
+        if placement.align.is_some() {
+            return Err(error(span, "@at cannot be combined with @align."));
+        }
+
+        let memory = match address {
+            0x0000..=0x3FFF if in_rom => Memory::Rom0,
+            0x4000..=0x7FFF if in_rom => Memory::RomX,
+            0xC000..=0xCFFF if !in_rom => Memory::Wram0,
+            0xD000..=0xDFFF if !in_rom => Memory::WramX,
+            0xFF80..=0xFFFE if !in_rom => Memory::Hram,
+            _ if in_rom => return Err(error(span, "@at address for ROM must be in ROM.")),
+            _ => return Err(error(span, "@at address for a variable must be in WRAM or HRAM.")),
+        };
+
+        let compatible = match placement.memory {
+            Memory::Wram0 | Memory::Rom0 => true,
+            Memory::RomX => memory == Memory::RomX,
+            other => other == memory,
+        };
+        if !compatible {
+            return Err(error(span, "@at address doesn't match the declaration's memory type."));
+        }
+        if memory == Memory::RomX && placement.bank.is_none() {
+            return Err(error(span, "@at address in ROMX also needs a @bank."));
+        }
+        placement.memory = memory;
+    }
+
+    Ok(placement)
+}
+
+/// The lint levels set by `@allow`, `@warn` and `@deny` attributes.
+pub fn lint_levels(attributes: &[Attribute]) -> Result<Vec<(Lint, LintLevel)>, Diagnostic> {
+    let mut lints: Vec<(Lint, LintLevel)> = Vec::new();
+
+    for attribute in attributes {
+        let Some(level) = LintLevel::from_attribute(&attribute.name.lexeme) else {
+            continue;
+        };
+        let argument = attribute_argument(attribute, Identifier, "Lint attributes expect a lint name.")?;
+        let lint = Lint::from_name(&argument.lexeme).ok_or(error(argument.span, "Unknown lint."))?;
+        lints.push((lint, level));
+    }
+
+    Ok(lints)
 }
 
-fn function(queue: &mut VecDeque<Token>) -> Result<Declaration, CompilationError> {
+fn attribute_argument<'a>(
+    attribute: &'a Attribute,
+    kind: TokenKind,
+    msg: &'static str,
+) -> Result<&'a Token, Diagnostic> {
+    match &attribute.argument {
+        Some(argument) if argument.kind == kind => Ok(argument),
+        _ => Err(error(attribute.name.span, msg)),
+    }
+}
+
+fn interrupt_kind(queue: &mut VecDeque<Token>) -> Result<InterruptKind, Diagnostic> {
+    expect(queue, LeftParen, "Expected '(' after interrupt.")?;
+    let name = expect(queue, Identifier, "Expected interrupt name.")?;
+    expect(queue, RightParen, "Expected ')' after interrupt name.")?;
+
+    InterruptKind::from_name(&name.lexeme).ok_or(error(
+        name.span,
+        "Unknown interrupt (expected vblank, stat, timer, serial or joypad).",
+    ))
+}
+
+fn function(
+    queue: &mut VecDeque<Token>,
+    public: bool,
+    interrupt: Option<InterruptKind>,
+    attributes: &[Attribute],
+    errors: &mut Vec<Diagnostic>,
+) -> Result<Declaration, Diagnostic> {
     let name = expect(queue, Identifier, "Expected identifier after 'fn'.")?;
 
     let arguments: Vec<Token> = Vec::new();
@@ -93,47 +362,219 @@ fn function(queue: &mut VecDeque<Token>) -> Result<Declaration, CompilationError
     expect(queue, RightParen, "Expected ')' after argument list.")?;
 
     expect(queue, LeftBrace, "Expected '{' after function declaration.")?;
-    let body = block(queue)?;
+    let (body, end) = block(queue, errors)?;
+
+    let placement = placement(attributes, Memory::Rom0)?;
+    let lints = lint_levels(attributes)?;
+    // The vectors jump straight to the handler, without any chance to switch banks first.
+    if interrupt.is_some() && placement.memory != Memory::Rom0 {
+        return Err(error(name.span, "Interrupt handlers must be in ROM0."));
+    }
 
-    Ok(Declaration::Function { name, arguments, body })
+    Ok(Declaration::Function {
+        name,
+        arguments,
+        body,
+        public,
+        placement,
+        interrupt,
+        lints,
+        doc: Vec::new(),
+        extent: end.span,
+    })
 }
 
-fn variable(queue: &mut VecDeque<Token>) -> Result<Declaration, CompilationError> {
-    let mut size = 1;
+fn variable(
+    queue: &mut VecDeque<Token>,
+    public: bool,
+    memory: Memory,
+    attributes: &[Attribute],
+) -> Result<Declaration, Diagnostic> {
+    let mut size = Some(1);
     if peek(queue)?.kind == LeftBracket {
         expect(queue, LeftBracket, "Expected '[' beginning array definition.")?;
-        let size_token = expect(queue, Number, "Expected array size specifier.")?;
+        // The size can be left out when it can be worked out from the initialiser.
+        size = match peek(queue)?.kind {
+            RightBracket => None,
+            _ => Some(get_wide_value(&expect(
+                queue,
+                Number,
+                "Expected array size specifier.",
+            )?)?),
+        };
         expect(queue, RightBracket, "Expected ']' ending array definition.")?;
-
-        size = get_value(&size_token)?;
     }
 
     let name = expect(queue, Identifier, "Expected variable name.")?;
-    expect(queue, Semicolon, "Expected ';' after variable declaration.")?;
 
-    Ok(Declaration::Variable { name, size })
+    let mut initializer = None;
+    if peek(queue)?.kind == Equals {
+        next(queue)?;
+        initializer = Some(match peek(queue)?.kind {
+            Incbin => incbin(queue)?,
+            Identifier => graphics(queue)?,
+            _ => initializer_list(queue)?,
+        });
+    }
+    let end = expect(queue, Semicolon, "Expected ';' after variable declaration.")?;
+
+    let placement = placement(attributes, memory)?;
+    let lints = lint_levels(attributes)?;
+    let in_rom = matches!(placement.memory, Memory::Rom0 | Memory::RomX);
+    if in_rom && initializer.is_none() {
+        return Err(error(name.span, "ROM data must be initialised."));
+    }
+    if placement.memory == Memory::WramX && initializer.is_some() {
+        return Err(error(name.span, "WRAMX variables can't be initialised."));
+    }
+
+    let size = match (size, &initializer) {
+        (Some(size), _) => Some(size),
+        (None, Some(Initializer::Values(values))) => {
+            Some(u16::try_from(values.len()).map_err(|_| error(name.span, "Initialiser has too many values."))?)
+        }
+        // The size of the whole file is filled in once it has been found.
+        (None, Some(Initializer::Incbin { length, .. })) => *length,
+        (None, Some(Initializer::Graphics { .. })) => None,
+        (None, None) => return Err(error(name.span, "Array size can only be left out with an initialiser.")),
+    };
+    if let (Some(Initializer::Values(values)), Some(size)) = (&initializer, size) {
+        if values.len() > usize::from(size) {
+            return Err(error(name.span, "Initialiser has more values than the array can hold."));
+        }
+    }
+
+    Ok(Declaration::Variable {
+        name,
+        size,
+        public,
+        placement,
+        initializer,
+        lints,
+        doc: Vec::new(),
+        extent: end.span,
+    })
 }
 
-fn statement(queue: &mut VecDeque<Token>) -> Result<Stmt, CompilationError> {
-    let stmt = match peek(queue)?.kind {
-        TokenKind::Halt => {
+fn incbin(queue: &mut VecDeque<Token>) -> Result<Initializer, Diagnostic> {
+    next(queue)?; // Consume the keyword
+    expect(queue, LeftParen, "Expected '(' after incbin.")?;
+    let path = expect(queue, StringLiteral, "Expected a file path in incbin.")?;
+
+    let mut offset = 0;
+    let mut length = None;
+    if peek(queue)?.kind == Comma {
+        next(queue)?;
+        offset = get_wide_value(&expect(queue, Number, "Expected an offset into the file.")?)?;
+        if peek(queue)?.kind == Comma {
             next(queue)?;
-            expect(queue, Semicolon, "Expected ';' after halt.")?;
-            Ok(Stmt::Halt)
+            length = Some(get_wide_value(&expect(
+                queue,
+                Number,
+                "Expected a length to include.",
+            )?)?);
         }
-        TokenKind::While => while_loop(queue),
-        _ => expression_statement(queue),
+    }
+
+    expect(queue, RightParen, "Expected ')' after incbin arguments.")?;
+
+    Ok(Initializer::Incbin { path, offset, length })
+}
+
+fn graphics(queue: &mut VecDeque<Token>) -> Result<Initializer, Diagnostic> {
+    let converter = next(queue)?;
+    let output = match converter.lexeme.as_str() {
+        "tiles" => GraphicsOutput::Tiles,
+        "tilemap" => GraphicsOutput::Tilemap,
+        _ => return Err(error(converter.span, "Expected an initialiser.")),
     };
 
-    if stmt.is_err() {
-        discard_line(queue);
+    expect(queue, LeftParen, "Expected '(' after graphics converter.")?;
+    let path = expect(queue, StringLiteral, "Expected an image path.")?;
+
+    let mut tall = false;
+    let mut dedupe = false;
+    while peek(queue)?.kind == Comma {
+        next(queue)?;
+        let option = expect(queue, Identifier, "Expected a graphics option.")?;
+        match option.lexeme.as_str() {
+            "tall" => tall = true,
+            "dedupe" => dedupe = true,
+            _ => return Err(error(option.span, "Unknown graphics option (expected tall or dedupe).")),
+        }
+    }
+
+    expect(queue, RightParen, "Expected ')' after graphics options.")?;
+
+    Ok(Initializer::Graphics {
+        path,
+        output,
+        tall,
+        dedupe,
+        data: Vec::new(),
+    })
+}
+
+fn initializer_list(queue: &mut VecDeque<Token>) -> Result<Initializer, Diagnostic> {
+    expect(queue, LeftBrace, "Expected '{' beginning initialiser.")?;
+
+    let mut values: Vec<Expr> = Vec::new();
+    while peek(queue)?.kind != RightBrace {
+        if peek(queue)?.kind == Colour {
+            // Colours are stored little-endian, taking up two bytes of the data.
+            let token = next(queue)?;
+            let colour = get_wide_value(&token)?;
+            for value in colour.to_le_bytes() {
+                values.push(Expr::Literal {
+                    token: token.clone(),
+                    value,
+                });
+            }
+        } else {
+            values.push(expression(queue)?);
+        }
+        if peek(queue)?.kind != RightBrace {
+            expect(queue, Comma, "Expected ',' between initialiser values.")?;
+        }
     }
 
-    stmt
+    expect(queue, RightBrace, "Expected '}' ending initialiser.")?;
+
+    Ok(Initializer::Values(values))
 }
 
-fn while_loop(queue: &mut VecDeque<Token>) -> Result<Stmt, CompilationError> {
-    next(queue)?; // Consume the opening keyword
+fn statement(queue: &mut VecDeque<Token>, errors: &mut Vec<Diagnostic>) -> Result<Stmt, Diagnostic> {
+    match peek(queue)?.kind {
+        TokenKind::Halt => {
+            let keyword = next(queue)?;
+            expect(queue, Semicolon, "Expected ';' after halt.")?;
+            Ok(Stmt::Halt { keyword })
+        }
+        Di => {
+            let keyword = next(queue)?;
+            expect(queue, Semicolon, "Expected ';' after di.")?;
+            Ok(Stmt::DisableInterrupts { keyword })
+        }
+        Ei => {
+            let keyword = next(queue)?;
+            expect(queue, Semicolon, "Expected ';' after ei.")?;
+            Ok(Stmt::EnableInterrupts { keyword })
+        }
+        Atomic => {
+            let keyword = next(queue)?;
+            expect(queue, LeftBrace, "Expected '{' after atomic.")?;
+            Ok(Stmt::Atomic {
+                keyword,
+                body: block(queue, errors)?.0,
+            })
+        }
+        TokenKind::While => while_loop(queue, errors),
+        _ => expression_statement(queue),
+    }
+}
+
+fn while_loop(queue: &mut VecDeque<Token>, errors: &mut Vec<Diagnostic>) -> Result<Stmt, Diagnostic> {
+    let keyword = next(queue)?;
     expect(queue, LeftParen, "Expected '(' after while.")?;
 
     let condition = expression(queue)?;
@@ -141,12 +582,16 @@ fn while_loop(queue: &mut VecDeque<Token>) -> Result<Stmt, CompilationError> {
     expect(queue, RightParen, "Expected ')' after while condition.")?;
     expect(queue, LeftBrace, "Expected '{' at beginning of while body.")?;
 
-    let body = block(queue)?;
+    let (body, _) = block(queue, errors)?;
 
-    Ok(Stmt::While { condition, body })
+    Ok(Stmt::While {
+        keyword,
+        condition,
+        body,
+    })
 }
 
-fn expression_statement(queue: &mut VecDeque<Token>) -> Result<Stmt, CompilationError> {
+fn expression_statement(queue: &mut VecDeque<Token>) -> Result<Stmt, Diagnostic> {
     let expr = expression(queue)?;
 
     if peek(queue)?.kind == Equals {
@@ -168,30 +613,41 @@ fn expression_statement(queue: &mut VecDeque<Token>) -> Result<Stmt, Compilation
     }
 }
 
-fn block(queue: &mut VecDeque<Token>) -> Result<Vec<Stmt>, CompilationError> {
+/// Parses statements up to and including the closing `}`, which is returned along with them. A statement with an
+/// error is reported and skipped, so that the rest of the block is still checked.
+fn block(queue: &mut VecDeque<Token>, errors: &mut Vec<Diagnostic>) -> Result<(Vec<Stmt>, Token), Diagnostic> {
     let mut statements: Vec<Stmt> = Vec::new();
 
     // We expect that the opening '{' has been consumed before calling this
-    while peek(queue)?.kind != RightBrace {
-        statements.push(statement(queue)?);
-    }
-
-    expect(queue, RightBrace, "Expected '}' at end of block.")?;
+    loop {
+        let token = peek(queue)?;
+        if token.kind == RightBrace {
+            return Ok((statements, next(queue)?));
+        }
+        // Running into the next declaration most likely means this block's '}' was left out, so it is left for
+        // the declaration to be parsed as normal.
+        if token.kind == EOF || starts_declaration(token) {
+            return Err(error(token.span, "Expected '}' at end of block."));
+        }
 
-    Ok(statements)
+        match statement(queue, errors) {
+            Ok(stmt) => statements.push(stmt),
+            Err(err) => {
+                errors.push(err);
+                synchronise(queue, false);
+            }
+        }
+    }
 }
 
-fn expression(queue: &mut VecDeque<Token>) -> Result<Expr, CompilationError> {
+fn expression(queue: &mut VecDeque<Token>) -> Result<Expr, Diagnostic> {
     term(queue)
 }
 
-fn term(queue: &mut VecDeque<Token>) -> Result<Expr, CompilationError> {
+fn term(queue: &mut VecDeque<Token>) -> Result<Expr, Diagnostic> {
     let mut expr = primary(queue)?;
 
-    if match peek(queue)?.kind {
-        Plus | Minus => true,
-        _ => false,
-    } {
+    if matches!(peek(queue)?.kind, Plus | Minus | Ampersand | Pipe) {
         let operator = next(queue)?;
         let right = primary(queue)?;
         expr = Expr::Binary {
@@ -204,7 +660,7 @@ fn term(queue: &mut VecDeque<Token>) -> Result<Expr, CompilationError> {
     Ok(expr)
 }
 
-fn primary(queue: &mut VecDeque<Token>) -> Result<Expr, CompilationError> {
+fn primary(queue: &mut VecDeque<Token>) -> Result<Expr, Diagnostic> {
     let token = next(queue)?;
     let expr = match token.kind {
         True => Ok(Expr::Literal { token, value: 1 }),
@@ -214,28 +670,77 @@ fn primary(queue: &mut VecDeque<Token>) -> Result<Expr, CompilationError> {
             token,
         }),
         Identifier => {
-            if peek(queue)?.kind == LeftBracket {
+            let token = qualified_name(queue, token)?;
+            if peek(queue)?.kind == LeftParen {
+                call(queue, token)
+            } else if peek(queue)?.kind == LeftBracket {
                 expect(queue, LeftBracket, "Expected '[' beginning index expression.")?;
                 let index = expression(queue)?;
                 expect(queue, RightBracket, "Expected ']' ending index expression.")?;
+                let field = if peek(queue)?.kind == Dot {
+                    next(queue)?;
+                    Some(expect(queue, Identifier, "Expected field name after '.'.")?)
+                } else {
+                    None
+                };
                 Ok(Expr::Indexed {
                     name: token,
                     index: Box::new(index),
+                    field,
                 })
             } else {
                 Ok(Expr::Variable { name: token })
             }
         }
-        _ => Err(error(token.line, "Expected number or identifier in expression.")),
+        Colour => Err(error(token.span, "Colour literals can only be used in initialisers.")),
+        _ => {
+            // Leave the token for error recovery, since it may well be the end of the statement.
+            let span = token.span;
+            queue.push_front(token);
+            Err(error(span, "Expected number or identifier in expression."))
+        }
     };
 
     expr
 }
 
-fn get_value(token: &Token) -> Result<u8, CompilationError> {
+fn call(queue: &mut VecDeque<Token>, callee: Token) -> Result<Expr, Diagnostic> {
+    expect(queue, LeftParen, "Expected '(' beginning argument list.")?;
+
+    let mut arguments: Vec<Expr> = Vec::new();
+    while peek(queue)?.kind != RightParen {
+        arguments.push(expression(queue)?);
+        if peek(queue)?.kind != RightParen {
+            expect(queue, Comma, "Expected ',' between arguments.")?;
+        }
+    }
+
+    expect(queue, RightParen, "Expected ')' ending argument list.")?;
+
+    Ok(Expr::Call { callee, arguments })
+}
+
+/// Folds a namespaced name such as `hw.rLY` into a single identifier token.
+fn qualified_name(queue: &mut VecDeque<Token>, mut name: Token) -> Result<Token, Diagnostic> {
+    if peek(queue)?.kind == Dot {
+        next(queue)?;
+        let member = expect(queue, Identifier, "Expected identifier after '.'.")?;
+        name.lexeme = format!("{}.{}", name.lexeme, member.lexeme);
+        name.span = name.span.to(member.span);
+    }
+
+    Ok(name)
+}
+
+fn get_value(token: &Token) -> Result<u8, Diagnostic> {
+    let value = get_wide_value(token)?;
+    u8::try_from(value).map_err(|_| error(token.span, "Number literal is too large for a u8."))
+}
+
+fn get_wide_value(token: &Token) -> Result<u16, Diagnostic> {
     token
         .value
-        .ok_or(error(token.line, "Expected a value in number literal."))
+        .ok_or(error(token.span, "Expected a value in number literal."))
 }
 
 #[cfg(test)]
@@ -247,21 +752,28 @@ mod tests {
             kind,
             lexeme: String::from(""),
             value: None,
-            line: 0,
+            span: Span::default(),
+        }
+    }
+
+    fn error(line: u32, msg: &str) -> ExpectedError {
+        ExpectedError {
+            msg: msg.to_string(),
+            line,
         }
     }
 
     #[test]
     fn variable_ok() {
         let mut tokens: VecDeque<_> = vec![token(Identifier), token(Semicolon)].into();
-        let result = variable(&mut tokens).unwrap();
+        let result = variable(&mut tokens, false, Memory::Wram0, &[]).unwrap();
         assert!(matches!(result, Declaration::Variable { .. }));
     }
 
     #[test]
     fn variable_err() {
         let mut tokens: VecDeque<_> = vec![token(Unsigned8), token(Identifier)].into();
-        let result = variable(&mut tokens);
+        let result = variable(&mut tokens, false, Memory::Wram0, &[]);
         assert!(matches!(result, Err { .. }));
     }
 
@@ -284,7 +796,7 @@ mod tests {
         let (tokens, _) = lexer::lex(String::from("u8 variable;\nfn main() {\nvariable = 5;\n}\n"));
         let (ast, errors) = parse(tokens);
 
-        assert_eq!(errors, vec![]);
+        assert!(errors.is_empty());
         assert!(matches!(
             ast[..],
             [Declaration::Variable { .. }, Declaration::Function { .. }]
@@ -295,13 +807,13 @@ mod tests {
     fn parse_while() {
         let (tokens, _) = lexer::lex(String::from("while (true) { halt; }"));
         let mut queue: VecDeque<Token> = tokens.into_iter().collect();
-        let stmt = while_loop(&mut queue).unwrap();
+        let stmt = while_loop(&mut queue, &mut Vec::new()).unwrap();
 
-        let Stmt::While { condition, body } = stmt else {
+        let Stmt::While { condition, body, .. } = stmt else {
             panic!("Expected while statement")
         };
         assert!(matches!(condition, Expr::Literal { value: 1, .. }));
-        assert!(matches!(body[..], [Stmt::Halt]));
+        assert!(matches!(body[..], [Stmt::Halt { .. }]));
     }
 
     #[test]
@@ -309,12 +821,452 @@ mod tests {
         let (tokens, _) = lexer::lex(String::from("u8[100] array;"));
         let (ast, errors) = parse(tokens);
 
-        assert_eq!(errors, vec![]);
-        let Declaration::Variable { name, size } = ast.get(0).unwrap() else {
+        assert!(errors.is_empty());
+        let Declaration::Variable { name, size, .. } = ast.first().unwrap() else {
             panic!("Expected variable definition.");
         };
         assert_eq!(name.lexeme, "array");
-        assert_eq!(*size, 100);
+        assert_eq!(*size, Some(100));
+    }
+
+    #[test]
+    fn parse_public_declarations() {
+        let (tokens, _) = lexer::lex(String::from("pub u8 exported; u8 local; pub fn main() {}"));
+        let (ast, errors) = parse(tokens);
+
+        assert!(errors.is_empty());
+        assert!(matches!(
+            ast[..],
+            [
+                Declaration::Variable { public: true, .. },
+                Declaration::Variable { public: false, .. },
+                Declaration::Function { public: true, .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn parse_hram_variable() {
+        let (tokens, _) = lexer::lex(String::from("pub hram u8[2] fast;"));
+        let (ast, errors) = parse(tokens);
+
+        assert!(errors.is_empty());
+        assert!(matches!(
+            ast[..],
+            [Declaration::Variable {
+                public: true,
+                placement: Placement {
+                    memory: Memory::Hram,
+                    ..
+                },
+                size: Some(2),
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn parse_hram_function_err() {
+        let (tokens, _) = lexer::lex(String::from("hram fn nope() {}"));
+        let mut queue: VecDeque<_> = tokens.into();
+        let result = declaration(&mut queue, &mut Vec::new());
+
+        assert_eq!(
+            result.unwrap_err(),
+            (error(
+                1,
+                "Expected a type after 'hram' (only variables can be placed in HRAM)."
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_placement_attributes() {
+        let (tokens, _) = lexer::lex(String::from(
+            "@section(\"Gameplay\") @bank(3) fn update() {} @wramx(2) @align(8) u8[64] buffer; @at($FF90) u8 fixed;",
+        ));
+        let (ast, errors) = parse(tokens);
+
+        assert!(errors.is_empty());
+        let placements: Vec<&Placement> = ast
+            .iter()
+            .map(|dec| match dec {
+                Declaration::Function { placement, .. } => placement,
+                Declaration::Variable { placement, .. } => placement,
+            })
+            .collect();
+        assert_eq!(
+            placements,
+            vec![
+                &Placement {
+                    memory: Memory::RomX,
+                    section: Some(String::from("Gameplay")),
+                    bank: Some(3),
+                    ..Placement::default()
+                },
+                &Placement {
+                    memory: Memory::WramX,
+                    bank: Some(2),
+                    align: Some(8),
+                    ..Placement::default()
+                },
+                &Placement {
+                    memory: Memory::Hram,
+                    address: Some(0xFF90),
+                    ..Placement::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_lint_attributes() {
+        let (tokens, _) = lexer::lex(String::from(
+            "@allow(unused_variable) @bank(1) rom u8 x = {1}; @deny(overflow) @warn(no_effect) fn f() {}",
+        ));
+        let (ast, errors) = parse(tokens);
+
+        assert!(errors.is_empty());
+        let lints: Vec<&Vec<(Lint, LintLevel)>> = ast
+            .iter()
+            .map(|dec| match dec {
+                Declaration::Function { lints, .. } | Declaration::Variable { lints, .. } => lints,
+            })
+            .collect();
+        assert_eq!(
+            lints,
+            vec![
+                &vec![(Lint::UnusedVariable, LintLevel::Allow)],
+                &vec![(Lint::Overflow, LintLevel::Deny), (Lint::NoEffect, LintLevel::Warn)],
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_doc_comments() {
+        let (tokens, _) = lexer::lex(String::from(
+            "/// The score.\n///\n/// Capped at 99.\nu8 score;\n/// Draws the score.\n@bank(1) fn draw() {\n\
+             /// Not attached to anything.\nscore = 1;\n}\n@bank(1) /// Misplaced.\nfn update() {}\n/// Dangling.",
+        ));
+        let (ast, errors) = parse(tokens);
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        let docs: Vec<&[String]> = ast.iter().map(Declaration::doc).collect();
+        assert_eq!(
+            docs,
+            vec![
+                &["The score.", "", "Capped at 99."].map(String::from)[..],
+                &[String::from("Draws the score.")][..],
+                &[][..],
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_placement_errors() {
+        let cases = [
+            (
+                "@bank(2) u8 x;",
+                "@bank only applies to ROM (use @wramx for variables).",
+            ),
+            ("@wramx(9) u8 x;", "WRAMX banks are numbered 1 to 7."),
+            (
+                "@wramx(2) rom u8 x = {1};",
+                "@wramx only applies to variables (use @bank for ROM).",
+            ),
+            ("@wramx hram u8 x;", "@wramx only applies to non-HRAM variables."),
+            ("@at($4000) fn f() {}", "@at address in ROMX also needs a @bank."),
+            (
+                "@at($8000) u8 x;",
+                "@at address for a variable must be in WRAM or HRAM.",
+            ),
+            ("@at($C000) @align(8) u8 x;", "@at cannot be combined with @align."),
+            (
+                "@wramx @at($C000) u8 x;",
+                "@at address doesn't match the declaration's memory type.",
+            ),
+            ("@section(3) fn f() {}", "@section expects a section name."),
+            ("@inline fn f() {}", "Unknown attribute."),
+            ("@allow(\"overflow\") fn f() {}", "Lint attributes expect a lint name."),
+            ("@deny(unused) u8 x;", "Unknown lint."),
+        ];
+
+        for (src, msg) in cases {
+            let (tokens, _) = lexer::lex(String::from(src));
+            let mut queue: VecDeque<_> = tokens.into();
+            assert_eq!(
+                declaration(&mut queue, &mut Vec::new()).unwrap_err(),
+                error(1, msg),
+                "{}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn parse_rom_data() {
+        let (tokens, _) = lexer::lex(String::from(
+            "@bank(2) rom u8[] table = {1, 'a', hw.LCDCF_ON}; rom u8[8] padded = {};",
+        ));
+        let (ast, errors) = parse(tokens);
+
+        assert!(errors.is_empty());
+        let [Declaration::Variable {
+            size: Some(3),
+            placement,
+            initializer: Some(Initializer::Values(values)),
+            ..
+        }, Declaration::Variable { size: Some(8), .. }] = &ast[..]
+        else {
+            panic!("Expected two ROM declarations.");
+        };
+        assert_eq!(placement.memory, Memory::RomX);
+        assert!(matches!(
+            values[..],
+            [
+                Expr::Literal { value: 1, .. },
+                Expr::Literal { value: 97, .. },
+                Expr::Variable { .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn parse_incbin() {
+        let (tokens, _) = lexer::lex(String::from(
+            "rom u8[] map = incbin(\"map.bin\", 16, 320); rom u8[] font = incbin(\"font.2bpp\");",
+        ));
+        let (ast, errors) = parse(tokens);
+
+        assert!(errors.is_empty());
+        let [Declaration::Variable {
+            size: Some(320),
+            initializer:
+                Some(Initializer::Incbin {
+                    path,
+                    offset: 16,
+                    length: Some(320),
+                }),
+            ..
+        }, Declaration::Variable {
+            size: None,
+            initializer:
+                Some(Initializer::Incbin {
+                    offset: 0,
+                    length: None,
+                    ..
+                }),
+            ..
+        }] = &ast[..]
+        else {
+            panic!("Expected two incbin declarations.");
+        };
+        assert_eq!(path.lexeme, "map.bin");
+    }
+
+    #[test]
+    fn parse_graphics() {
+        let (tokens, _) = lexer::lex(String::from(
+            "rom u8[] font = tiles(\"font.png\", tall, dedupe); rom u8[64] map = tilemap(\"map.png\");",
+        ));
+        let (ast, errors) = parse(tokens);
+
+        assert!(errors.is_empty());
+        let [Declaration::Variable {
+            size: None,
+            initializer:
+                Some(Initializer::Graphics {
+                    path,
+                    output: GraphicsOutput::Tiles,
+                    tall: true,
+                    dedupe: true,
+                    ..
+                }),
+            ..
+        }, Declaration::Variable {
+            size: Some(64),
+            initializer:
+                Some(Initializer::Graphics {
+                    output: GraphicsOutput::Tilemap,
+                    tall: false,
+                    dedupe: false,
+                    ..
+                }),
+            ..
+        }] = &ast[..]
+        else {
+            panic!("Expected two graphics declarations.");
+        };
+        assert_eq!(path.lexeme, "font.png");
+    }
+
+    #[test]
+    fn parse_colour_initializer() {
+        let (tokens, _) = lexer::lex(String::from("rom u8[] palette = {#FFFFFF, 1};"));
+        let (ast, errors) = parse(tokens);
+
+        assert!(errors.is_empty());
+        let [Declaration::Variable {
+            size: Some(3),
+            initializer: Some(Initializer::Values(values)),
+            ..
+        }] = &ast[..]
+        else {
+            panic!("Expected palette declaration.");
+        };
+        let values: Vec<u8> = values
+            .iter()
+            .map(|value| match value {
+                Expr::Literal { value, .. } => *value,
+                _ => panic!("Expected literal values."),
+            })
+            .collect();
+        assert_eq!(values, vec![0xFF, 0x7F, 1]);
+    }
+
+    #[test]
+    fn parse_initializer_errors() {
+        let cases = [
+            ("rom u8 x;", "ROM data must be initialised."),
+            ("@wramx u8[2] x = {1, 2};", "WRAMX variables can't be initialised."),
+            (
+                "rom u8[1] x = {1, 2};",
+                "Initialiser has more values than the array can hold.",
+            ),
+            ("u8[] x;", "Array size can only be left out with an initialiser."),
+            ("u8 x = #FFFFFF;", "Expected '{' beginning initialiser."),
+            ("rom u8[] x = sprites(\"a.png\");", "Expected an initialiser."),
+            (
+                "rom u8[] x = tiles(\"a.png\", wide);",
+                "Unknown graphics option (expected tall or dedupe).",
+            ),
+        ];
+
+        for (src, msg) in cases {
+            let (tokens, _) = lexer::lex(String::from(src));
+            let mut queue: VecDeque<_> = tokens.into();
+            assert_eq!(
+                declaration(&mut queue, &mut Vec::new()).unwrap_err(),
+                error(1, msg),
+                "{}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn parse_interrupt_handler() {
+        let (tokens, _) = lexer::lex(String::from(
+            "pub interrupt(joypad) fn onJoypad() { atomic { di; } ei; }",
+        ));
+        let (ast, errors) = parse(tokens);
+
+        assert!(errors.is_empty());
+        let [Declaration::Function {
+            interrupt: Some(InterruptKind::Joypad),
+            public: true,
+            body,
+            ..
+        }] = &ast[..]
+        else {
+            panic!("Expected an interrupt handler.");
+        };
+        let [Stmt::Atomic { body, .. }, Stmt::EnableInterrupts { .. }] = &body[..] else {
+            panic!("Expected an atomic block followed by ei.");
+        };
+        assert!(matches!(body[..], [Stmt::DisableInterrupts { .. }]));
+    }
+
+    #[test]
+    fn parse_interrupt_errors() {
+        let cases = [
+            (
+                "interrupt(nmi) fn f() {}",
+                "Unknown interrupt (expected vblank, stat, timer, serial or joypad).",
+            ),
+            ("interrupt(vblank) u8 x;", "Expected 'fn' after interrupt."),
+            (
+                "@bank(1) interrupt(vblank) fn f() {}",
+                "Interrupt handlers must be in ROM0.",
+            ),
+        ];
+
+        for (src, msg) in cases {
+            let (tokens, _) = lexer::lex(String::from(src));
+            let mut queue: VecDeque<_> = tokens.into();
+            assert_eq!(
+                declaration(&mut queue, &mut Vec::new()).unwrap_err(),
+                error(1, msg),
+                "{}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn parse_call() {
+        let (tokens, _) = lexer::lex(String::from("update(); copy(a, 1, hw.rLY);"));
+        let mut queue: VecDeque<_> = tokens.into();
+
+        let Stmt::Expression {
+            expr: Expr::Call { callee, arguments },
+        } = statement(&mut queue, &mut Vec::new()).unwrap()
+        else {
+            panic!("Expected call statement.");
+        };
+        assert_eq!(callee.lexeme, "update");
+        assert_eq!(arguments, vec![]);
+
+        let Stmt::Expression {
+            expr: Expr::Call { arguments, .. },
+        } = statement(&mut queue, &mut Vec::new()).unwrap()
+        else {
+            panic!("Expected call statement.");
+        };
+        assert_eq!(arguments.len(), 3);
+    }
+
+    #[test]
+    fn parse_qualified_name() {
+        let (tokens, _) = lexer::lex(String::from("hw.screen0[1]"));
+        let mut queue: VecDeque<_> = tokens.into();
+        let expr = expression(&mut queue).unwrap();
+
+        let Expr::Indexed { name, .. } = expr else {
+            panic!("Expected indexed expression.");
+        };
+        assert_eq!(name.lexeme, "hw.screen0");
+    }
+
+    #[test]
+    fn expression_spans() {
+        let (tokens, _) = lexer::lex(String::from("a + b[c].x;\ncopy(d, e);"));
+        let mut queue: VecDeque<_> = tokens.into();
+
+        let expr = expression(&mut queue).unwrap();
+        assert_eq!((expr.span().offset, expr.span().length), (0, 10));
+        next(&mut queue).unwrap();
+        let stmt = statement(&mut queue, &mut Vec::new()).unwrap();
+        let span = stmt.span();
+        assert_eq!((span.offset, span.length, span.line, span.column), (12, 9, 2, 1));
+    }
+
+    #[test]
+    fn parse_indexed_field() {
+        let (tokens, _) = lexer::lex(String::from("oam[i].tile"));
+        let mut queue: VecDeque<_> = tokens.into();
+        let expr = expression(&mut queue).unwrap();
+
+        let Expr::Indexed {
+            name,
+            field: Some(field),
+            ..
+        } = expr
+        else {
+            panic!("Expected indexed expression with a field.");
+        };
+        assert_eq!(name.lexeme, "oam");
+        assert_eq!(field.lexeme, "tile");
     }
 
     #[test]
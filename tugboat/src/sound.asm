@@ -0,0 +1,55 @@
+
+SECTION "Sound effects", ROM0
+
+; Starts playing the sound effect at hl on the channel whose registers start at $FF00 + a, replacing any effect
+; that's already playing. Effects are a series of steps of a frame count followed by the five channel registers,
+; ending with a frame count of 0.
+tgPlaySound::
+  ld [wSoundChannel], a
+  ld a, l
+  ld [wSoundStep], a
+  ld a, h
+  ld [wSoundStep + 1], a
+  ; Run straight on into the update so the first step starts this frame
+  ld a, 1
+  ld [wSoundTimer], a
+
+; Counts down the current step, moving on to the next one when it runs out
+tgUpdateSound::
+  ld a, [wSoundTimer]
+  or a
+  ret z
+  dec a
+  ld [wSoundTimer], a
+  ret nz
+
+  ld a, [wSoundStep]
+  ld l, a
+  ld a, [wSoundStep + 1]
+  ld h, a
+  ld a, [hli]
+  or a
+  ret z
+  ld [wSoundTimer], a
+
+  ld a, [wSoundChannel]
+  ld c, a
+  ld b, 5
+  .untilStepIsWritten
+    ld a, [hli]
+    ldh [c], a
+    inc c
+    dec b
+  jr nz, .untilStepIsWritten
+
+  ld a, l
+  ld [wSoundStep], a
+  ld a, h
+  ld [wSoundStep + 1], a
+  ret
+
+SECTION "Sound effect state", WRAM0
+
+wSoundStep:: ds 2
+wSoundTimer:: ds 1
+wSoundChannel:: ds 1
@@ -0,0 +1,68 @@
+use super::hardware::Hardware::{self, *};
+use phf::phf_map;
+
+/// Everything that can be referenced through the built-in `sound` namespace: the APU registers named after what
+/// they control rather than their NRxy numbers, and the values that go in them.
+pub static SOUND: phf::Map<&'static str, Hardware> = phf_map! {
+    // Channel 1: pulse with frequency sweep
+    "ch1_sweep" => Register(0xFF10),
+    "ch1_duty" => Register(0xFF11),
+    "ch1_envelope" => Register(0xFF12),
+    "ch1_frequency" => Register(0xFF13),
+    "ch1_control" => Register(0xFF14),
+
+    // Channel 2: pulse
+    "ch2_duty" => Register(0xFF16),
+    "ch2_envelope" => Register(0xFF17),
+    "ch2_frequency" => Register(0xFF18),
+    "ch2_control" => Register(0xFF19),
+
+    // Channel 3: wave
+    "ch3_enable" => Register(0xFF1A),
+    "ch3_length" => Register(0xFF1B),
+    "ch3_level" => Register(0xFF1C),
+    "ch3_frequency" => Register(0xFF1D),
+    "ch3_control" => Register(0xFF1E),
+    "wave" => Region { address: 0xFF30, size: 16 },
+
+    // Channel 4: noise
+    "ch4_length" => Register(0xFF20),
+    "ch4_envelope" => Register(0xFF21),
+    "ch4_noise" => Register(0xFF22),
+    "ch4_control" => Register(0xFF23),
+
+    // Mixing
+    "volume" => Register(0xFF24),
+    "panning" => Register(0xFF25),
+    "master" => Register(0xFF26),
+
+    // Sweep: pace in bits 4-6, step in bits 0-2
+    "SWEEP_DOWN" => Constant(0x08),
+    "SWEEP_UP" => Constant(0x00),
+
+    // Duty cycles, with the length timer in the low 6 bits
+    "DUTY_12" => Constant(0x00),
+    "DUTY_25" => Constant(0x40),
+    "DUTY_50" => Constant(0x80),
+    "DUTY_75" => Constant(0xC0),
+
+    // Envelope: initial volume in the high nibble, pace in bits 0-2
+    "ENVELOPE_UP" => Constant(0x08),
+    "ENVELOPE_DOWN" => Constant(0x00),
+
+    // Control: the top 3 bits of the frequency in bits 0-2
+    "TRIGGER" => Constant(0x80),
+    "LENGTH_ENABLE" => Constant(0x40),
+
+    // Wave channel
+    "WAVE_ON" => Constant(0x80),
+    "WAVE_OFF" => Constant(0x00),
+    "LEVEL_MUTE" => Constant(0x00),
+    "LEVEL_100" => Constant(0x20),
+    "LEVEL_50" => Constant(0x40),
+    "LEVEL_25" => Constant(0x60),
+
+    // Master
+    "ON" => Constant(0x80),
+    "OFF" => Constant(0x00),
+};
@@ -0,0 +1,20 @@
+
+SECTION "Shadow OAM", WRAM0, ALIGN[8]
+
+wShadowOam:: ds 160
+
+SECTION "OAM DMA routine", ROM0
+
+; Starts a DMA from page a to OAM and waits the 160 cycles it takes, copied to hOamDma by the startup code
+tgOamDmaRoutine:
+  ldh [$FF46], a
+  ld a, 40
+  .wait
+    dec a
+  jr nz, .wait
+  ret
+.end
+
+SECTION "OAM DMA", HRAM
+
+hOamDma:: ds tgOamDmaRoutine.end - tgOamDmaRoutine
@@ -0,0 +1,14 @@
+SECTION "Startup", ROM0
+
+; Puts the hardware into a known state and hands over to main
+tgStart::
+  call tgLcdOff
+
+  ; WRAM is cleared while the stack is still in HRAM, then the stack moves to the top of WRAM
+  ld hl, $C000
+  ld bc, $2000
+  call tgZeroMemory
+  ld sp, $E000
+  ld hl, $FF80
+  ld bc, $7F
+  call tgZeroMemory
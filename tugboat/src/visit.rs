@@ -0,0 +1,233 @@
+//! Traversal of the syntax tree. Implement [`Visitor`] or [`VisitorMut`], overriding the methods for the nodes you
+//! care about, and call the matching `walk_` function from an override to carry on into that node's children.
+
+use super::*;
+
+/// Looks at each node of the tree in source order. Every method walks into the node's children by default.
+pub trait Visitor<'ast> {
+    fn visit_declaration(&mut self, dec: &'ast Declaration) {
+        walk_declaration(self, dec);
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        walk_expr(self, expr);
+    }
+}
+
+/// Visits each declaration in turn.
+pub fn walk_ast<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, ast: &'ast [Declaration]) {
+    for dec in ast {
+        visitor.visit_declaration(dec);
+    }
+}
+
+/// Visits a variable's initial values or a function's body.
+pub fn walk_declaration<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, dec: &'ast Declaration) {
+    match dec {
+        Declaration::Variable {
+            initializer: Some(Initializer::Values(values)),
+            ..
+        } => {
+            for value in values {
+                visitor.visit_expr(value);
+            }
+        }
+        Declaration::Variable { .. } => {}
+        Declaration::Function { body, .. } => {
+            for stmt in body {
+                visitor.visit_stmt(stmt);
+            }
+        }
+    }
+}
+
+/// Visits the statements and expressions inside a statement.
+pub fn walk_stmt<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, stmt: &'ast Stmt) {
+    match stmt {
+        Stmt::Halt { .. } | Stmt::DisableInterrupts { .. } | Stmt::EnableInterrupts { .. } => {}
+        Stmt::Atomic { body, .. } => {
+            for stmt in body {
+                visitor.visit_stmt(stmt);
+            }
+        }
+        Stmt::While { condition, body, .. } => {
+            visitor.visit_expr(condition);
+            for stmt in body {
+                visitor.visit_stmt(stmt);
+            }
+        }
+        Stmt::Assign { target, value } => {
+            visitor.visit_expr(target);
+            visitor.visit_expr(value);
+        }
+        Stmt::Expression { expr } => visitor.visit_expr(expr),
+    }
+}
+
+/// Visits the expressions inside an expression.
+pub fn walk_expr<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, expr: &'ast Expr) {
+    match expr {
+        Expr::Literal { .. } | Expr::Variable { .. } => {}
+        Expr::Indexed { index, .. } => visitor.visit_expr(index),
+        Expr::Binary { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        Expr::Call { arguments, .. } => {
+            for argument in arguments {
+                visitor.visit_expr(argument);
+            }
+        }
+    }
+}
+
+/// Like [`Visitor`], but with each node borrowed mutably so that it can be rewritten in place.
+pub trait VisitorMut {
+    fn visit_declaration_mut(&mut self, dec: &mut Declaration) {
+        walk_declaration_mut(self, dec);
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+}
+
+/// Visits each declaration in turn, mutably.
+pub fn walk_ast_mut<V: VisitorMut + ?Sized>(visitor: &mut V, ast: &mut [Declaration]) {
+    for dec in ast {
+        visitor.visit_declaration_mut(dec);
+    }
+}
+
+/// Visits a variable's initial values or a function's body, mutably.
+pub fn walk_declaration_mut<V: VisitorMut + ?Sized>(visitor: &mut V, dec: &mut Declaration) {
+    match dec {
+        Declaration::Variable {
+            initializer: Some(Initializer::Values(values)),
+            ..
+        } => {
+            for value in values {
+                visitor.visit_expr_mut(value);
+            }
+        }
+        Declaration::Variable { .. } => {}
+        Declaration::Function { body, .. } => {
+            for stmt in body {
+                visitor.visit_stmt_mut(stmt);
+            }
+        }
+    }
+}
+
+/// Visits the statements and expressions inside a statement, mutably.
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(visitor: &mut V, stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Halt { .. } | Stmt::DisableInterrupts { .. } | Stmt::EnableInterrupts { .. } => {}
+        Stmt::Atomic { body, .. } => {
+            for stmt in body {
+                visitor.visit_stmt_mut(stmt);
+            }
+        }
+        Stmt::While { condition, body, .. } => {
+            visitor.visit_expr_mut(condition);
+            for stmt in body {
+                visitor.visit_stmt_mut(stmt);
+            }
+        }
+        Stmt::Assign { target, value } => {
+            visitor.visit_expr_mut(target);
+            visitor.visit_expr_mut(value);
+        }
+        Stmt::Expression { expr } => visitor.visit_expr_mut(expr),
+    }
+}
+
+/// Visits the expressions inside an expression, mutably.
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Literal { .. } | Expr::Variable { .. } => {}
+        Expr::Indexed { index, .. } => visitor.visit_expr_mut(index),
+        Expr::Binary { left, right, .. } => {
+            visitor.visit_expr_mut(left);
+            visitor.visit_expr_mut(right);
+        }
+        Expr::Call { arguments, .. } => {
+            for argument in arguments {
+                visitor.visit_expr_mut(argument);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Collects the names of the functions called.
+    #[derive(Default)]
+    struct Calls<'ast> {
+        callees: Vec<&'ast str>,
+    }
+
+    impl<'ast> Visitor<'ast> for Calls<'ast> {
+        fn visit_expr(&mut self, expr: &'ast Expr) {
+            if let Expr::Call { callee, .. } = expr {
+                self.callees.push(&callee.lexeme);
+            }
+            walk_expr(self, expr);
+        }
+    }
+
+    /// Upper-cases every variable name.
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            if let Expr::Variable { name } | Expr::Indexed { name, .. } = expr {
+                name.lexeme = name.lexeme.to_uppercase();
+            }
+            walk_expr_mut(self, expr);
+        }
+    }
+
+    #[test]
+    fn visits_in_source_order() {
+        let (tokens, _) = lex(String::from(
+            "u8 x; fn f() {} fn main() { while (f()) { atomic { x = sizeof(x); } } f(); }",
+        ));
+        let (mut ast, errors) = parse(tokens);
+        assert!(errors.is_empty());
+
+        let mut calls = Calls::default();
+        walk_ast(&mut calls, &ast);
+        assert_eq!(calls.callees, vec!["f", "sizeof", "f"]);
+
+        walk_ast_mut(&mut Rename, &mut ast);
+        let Declaration::Function { body, .. } = &ast[2] else {
+            panic!("Expected main");
+        };
+        let Stmt::While { body, .. } = &body[0] else {
+            panic!("Expected a loop");
+        };
+        let Stmt::Atomic { body, .. } = &body[0] else {
+            panic!("Expected an atomic block");
+        };
+        let Stmt::Assign {
+            target: Expr::Variable { name },
+            value: Expr::Call { arguments, .. },
+        } = &body[0]
+        else {
+            panic!("Expected an assignment");
+        };
+        assert_eq!(name.lexeme, "X");
+        assert!(matches!(&arguments[0], Expr::Variable { name } if name.lexeme == "X"));
+    }
+}
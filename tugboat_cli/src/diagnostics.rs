@@ -0,0 +1,293 @@
+use serde_json::{json, Value};
+use std::io::IsTerminal;
+use std::path::Path;
+use tugboat::{Diagnostic, Level, Span};
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warning => "warning",
+        Level::Note => "note",
+    }
+}
+
+fn level_style(level: Level) -> &'static str {
+    match level {
+        Level::Error => RED,
+        Level::Warning => YELLOW,
+        Level::Note => GREEN,
+    }
+}
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const GREEN: &str = "\x1b[1;32m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders errors and warnings with the source they point at, in the style of rustc.
+pub struct Renderer {
+    colour: bool,
+}
+
+/// A span to underline in the snippet, with the character to underline it with.
+struct Annotation<'a> {
+    span: Span,
+    msg: &'a str,
+    marker: char,
+    style: &'static str,
+}
+
+impl Renderer {
+    pub fn new(colour: bool) -> Renderer {
+        Renderer { colour }
+    }
+
+    /// Colours output only when it goes straight to a terminal, and never when `NO_COLOR` is set.
+    pub fn for_stderr() -> Renderer {
+        Renderer::new(std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none())
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.colour {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn render(&self, file: &Path, source: &str, err: &Diagnostic) -> String {
+        let heading = match err.code {
+            Some(lint) => format!("{}[{}]", level_name(err.level), lint),
+            None => level_name(err.level).to_string(),
+        };
+        let mut output = format!(
+            "{}{}\n",
+            self.paint(level_style(err.level), &heading),
+            self.paint(BOLD, format!(": {}", err.msg).as_str())
+        );
+
+        let mut annotations = vec![Annotation {
+            span: err.span,
+            msg: "",
+            marker: '^',
+            style: level_style(err.level),
+        }];
+        annotations.extend(err.labels.iter().map(|label| Annotation {
+            span: label.span,
+            msg: &label.msg,
+            marker: '-',
+            style: BLUE,
+        }));
+        // Spans without a line, like those from interface files, can't be shown in the source.
+        annotations.retain(|annotation| line_text(source, annotation.span.line).is_some());
+        annotations.sort_by_key(|annotation| annotation.span.line);
+
+        let last_line = annotations.iter().map(|a| a.span.line).max().unwrap_or(err.span.line);
+        let width = last_line.to_string().len();
+        let gutter = self.paint(BLUE, format!("{} |", " ".repeat(width)).as_str());
+
+        let location = match err.span.line {
+            0 => file.display().to_string(),
+            line => format!("{}:{}:{}", file.display(), line, err.span.column),
+        };
+        output.push_str(format!("{}{} {}\n", " ".repeat(width), self.paint(BLUE, "-->"), location).as_str());
+
+        if !annotations.is_empty() {
+            output.push_str(format!("{}\n", gutter).as_str());
+        }
+        let mut previous: Option<u32> = None;
+        for annotation in annotations.iter() {
+            let line = annotation.span.line;
+            let text = line_text(source, line).unwrap_or_default();
+            if previous != Some(line) {
+                if previous.is_some_and(|previous| line > previous + 1) {
+                    output.push_str(format!("{}\n", self.paint(BLUE, "...")).as_str());
+                }
+                let number = self.paint(BLUE, format!("{:>width$} |", line).as_str());
+                output.push_str(format!("{} {}\n", number, text).as_str());
+                previous = Some(line);
+            }
+
+            let underline = format!(
+                "{}{}",
+                indent(text, annotation.span.column),
+                annotation
+                    .marker
+                    .to_string()
+                    .repeat(underline_width(source, text, annotation.span))
+            );
+            let underline = match annotation.msg {
+                "" => underline,
+                msg => format!("{} {}", underline, msg),
+            };
+            output.push_str(format!("{} {}\n", gutter, self.paint(annotation.style, &underline)).as_str());
+        }
+
+        for note in err.notes.iter() {
+            let help = format!("{} {}", self.paint(BLUE, "="), self.paint(BOLD, "help:"));
+            output.push_str(format!("{} {} {}\n", " ".repeat(width), help, note).as_str());
+        }
+
+        output
+    }
+}
+
+/// A diagnostic as one line of JSON, for editors and other tools to read. Positions are left out (as `null`) for
+/// diagnostics about a file as a whole.
+pub fn to_json(file: &Path, diagnostic: &Diagnostic) -> String {
+    let mut object = json!({
+        "severity": level_name(diagnostic.level),
+        "code": diagnostic.code.map(|lint| lint.name()),
+        "message": diagnostic.msg,
+        "file": file.display().to_string(),
+        "labels": diagnostic.labels.iter().map(|label| {
+            let mut object = json!({ "message": label.msg });
+            add_position(&mut object, label.span);
+            object
+        }).collect::<Vec<Value>>(),
+        "suggestions": diagnostic.notes,
+    });
+    add_position(&mut object, diagnostic.span);
+    object.to_string()
+}
+
+fn add_position(object: &mut Value, span: Span) {
+    let known = span.line > 0;
+    object["line"] = json!(known.then_some(span.line));
+    object["column"] = json!(known.then_some(span.column));
+    object["offset"] = json!(known.then_some(span.offset));
+    object["length"] = json!(known.then_some(span.length));
+}
+
+/// The text of a line, counting from 1.
+fn line_text(source: &str, line: u32) -> Option<&str> {
+    let index = usize::try_from(line).ok()?.checked_sub(1)?;
+    let text = source.split('\n').nth(index)?;
+    Some(text.strip_suffix('\r').unwrap_or(text))
+}
+
+/// Whitespace reaching the given column, keeping tabs so the underline lines up however they are displayed.
+fn indent(text: &str, column: u32) -> String {
+    text.chars()
+        .take(column.saturating_sub(1) as usize)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect()
+}
+
+/// How many characters of the line a span covers, stopping at the end of the line and always at least one.
+fn underline_width(source: &str, text: &str, span: Span) -> usize {
+    let covered = source
+        .get(span.offset..span.offset + span.length)
+        .map(|covered| covered.split('\n').next().unwrap_or_default().chars().count())
+        .unwrap_or(span.length);
+    let available = text
+        .chars()
+        .count()
+        .saturating_sub(span.column.saturating_sub(1) as usize);
+    covered.min(available).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tugboat::Lint;
+
+    fn span(offset: usize, length: usize, line: u32, column: u32) -> Span {
+        Span {
+            offset,
+            length,
+            line,
+            column,
+        }
+    }
+
+    #[test]
+    fn render_snippet_with_caret() {
+        let source = "u8 x;\nfn main() {\n  x = nope[x + 1];\n}";
+        let err = Diagnostic::error(span(24, 4, 3, 7), "Undefined variable: nope");
+        let output = Renderer::new(false).render(Path::new("main.tg"), source, &err);
+        assert_eq!(
+            output,
+            "error: Undefined variable: nope\n --> main.tg:3:7\n  |\n3 |   x = nope[x + 1];\n  |       ^^^^\n"
+        );
+    }
+
+    #[test]
+    fn render_labels_and_notes() {
+        let source = "u8 value;\n\n\n\n\n\n\n\n\nfn value() {}";
+        let err = Diagnostic::error(span(22, 5, 10, 4), "Duplicate definition: value")
+            .with_label(span(3, 5, 1, 4), "first defined here")
+            .with_note("rename one of them");
+        let output = Renderer::new(false).render(Path::new("dup.tg"), source, &err);
+        assert_eq!(
+            output,
+            "error: Duplicate definition: value\n  --> dup.tg:10:4\n   |\n 1 | u8 value;\n   |    ----- first defined here\n\
+             ...\n10 | fn value() {}\n   |    ^^^^^\n   = help: rename one of them\n"
+        );
+    }
+
+    #[test]
+    fn render_lint_code() {
+        let err = Diagnostic::warning(Lint::UnusedVariable, span(3, 1, 1, 4), "Variable x is never used");
+        let output = Renderer::new(false).render(Path::new("main.tg"), "u8 x;", &err);
+        assert_eq!(
+            output,
+            "warning[unused_variable]: Variable x is never used\n --> main.tg:1:4\n  |\n1 | u8 x;\n  |    ^\n"
+        );
+    }
+
+    #[test]
+    fn json_output() {
+        let err = Diagnostic::warning(
+            Lint::UnusedVariable,
+            span(22, 5, 10, 4),
+            "Variable \"value\" is never used",
+        )
+        .with_label(span(3, 5, 1, 4), "first defined here")
+        .with_note("remove it");
+        let output: Value = serde_json::from_str(&to_json(Path::new("src/main.tg"), &err)).unwrap();
+        assert_eq!(
+            output,
+            json!({
+                "severity": "warning",
+                "code": "unused_variable",
+                "message": "Variable \"value\" is never used",
+                "file": "src/main.tg",
+                "line": 10,
+                "column": 4,
+                "offset": 22,
+                "length": 5,
+                "labels": [{ "message": "first defined here", "line": 1, "column": 4, "offset": 3, "length": 5 }],
+                "suggestions": ["remove it"],
+            })
+        );
+
+        let err = Diagnostic::error(Span::default(), "Unable to open \"x.tg\"");
+        let output = to_json(Path::new("x.tg"), &err);
+        assert!(!output.contains('\n'));
+        let output: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(output["code"], Value::Null);
+        assert_eq!(output["line"], Value::Null);
+        assert_eq!(output["labels"], json!([]));
+    }
+
+    #[test]
+    fn render_without_source_line() {
+        let err = Diagnostic::error(Span::default(), "Unexpected end of interface.");
+        let output = Renderer::new(true).render(Path::new("lib.tgi"), "", &err);
+        assert_eq!(
+            output,
+            "\x1b[1;31merror\x1b[0m\x1b[1m: Unexpected end of interface.\x1b[0m\n \x1b[1;34m-->\x1b[0m lib.tgi\n"
+        );
+    }
+
+    #[test]
+    fn underline_keeps_tabs_and_stops_at_line_end() {
+        let source = "\tx = 1 +\n2;";
+        let err = Diagnostic::error(span(1, 10, 1, 2), "Nope");
+        let output = Renderer::new(false).render(Path::new("t.tg"), source, &err);
+        assert!(output.ends_with("1 | \tx = 1 +\n  | \t^^^^^^^\n"), "{}", output);
+    }
+}
@@ -0,0 +1,331 @@
+use tugboat::{Declaration, Expr, GraphicsOutput, Initializer, Memory};
+
+/// How many of an initialiser's values, as written, are shown before the rest are left out.
+const SHOWN_VALUES: usize = 16;
+
+/// Everything a page says about one declaration.
+struct Item {
+    name: String,
+    signature: String,
+    location: String,
+    /// What read-only data is initialised with.
+    value: Option<String>,
+    doc: Vec<String>,
+}
+
+/// The documentation for one source file, with its declarations grouped into functions, variables and constants.
+pub struct Page {
+    pub title: String,
+    sections: Vec<(&'static str, Vec<Item>)>,
+}
+
+impl Page {
+    /// Documents the public declarations, or with `private` all of them, in the order they were declared.
+    pub fn new(title: &str, source: &str, declarations: &[Declaration], private: bool) -> Page {
+        let mut sections: Vec<(&'static str, Vec<Item>)> = vec![
+            ("Functions", Vec::new()),
+            ("Variables", Vec::new()),
+            ("Constants", Vec::new()),
+        ];
+
+        for dec in declarations {
+            let (public, section, value) = match dec {
+                Declaration::Function { public, .. } => (*public, 0, None),
+                Declaration::Variable {
+                    public,
+                    placement,
+                    initializer,
+                    ..
+                } if matches!(placement.memory, Memory::Rom0 | Memory::RomX) => (
+                    *public,
+                    2,
+                    initializer
+                        .as_ref()
+                        .map(|initializer| initializer_text(initializer, source)),
+                ),
+                Declaration::Variable { public, .. } => (*public, 1, None),
+            };
+            if !public && !private {
+                continue;
+            }
+
+            sections[section].1.push(Item {
+                name: dec.name().lexeme.clone(),
+                signature: dec.signature(),
+                location: dec.location(),
+                value,
+                doc: dec.doc().to_vec(),
+            });
+        }
+        sections.retain(|(_, items)| !items.is_empty());
+
+        Page {
+            title: title.to_string(),
+            sections,
+        }
+    }
+
+    pub fn markdown(&self) -> String {
+        let mut output = format!("# {}\n", self.title);
+        if self.sections.is_empty() {
+            output.push_str("\nThere is nothing to document.\n");
+        }
+
+        for (heading, items) in self.sections.iter() {
+            output.push_str(format!("\n## {}\n", heading).as_str());
+            for item in items {
+                output.push_str(format!("\n### `{}`\n\n{}\n", item.signature, item.location).as_str());
+                if let Some(value) = &item.value {
+                    output.push_str(format!("\nInitial value: `{}`\n", value).as_str());
+                }
+                if !item.doc.is_empty() {
+                    output.push_str(format!("\n{}\n", item.doc.join("\n")).as_str());
+                }
+            }
+        }
+
+        output
+    }
+
+    pub fn html(&self) -> String {
+        let mut body = format!("<h1>{}</h1>\n", escape(&self.title));
+        if self.sections.is_empty() {
+            body.push_str("<p>There is nothing to document.</p>\n");
+        }
+
+        for (heading, items) in self.sections.iter() {
+            body.push_str(format!("<h2>{}</h2>\n", heading).as_str());
+            for item in items {
+                body.push_str(format!("<section id=\"{}\">\n", escape(&item.name)).as_str());
+                body.push_str(format!("<h3><code>{}</code></h3>\n", escape(&item.signature)).as_str());
+                body.push_str(format!("<p class=\"location\">{}</p>\n", escape(&item.location)).as_str());
+                if let Some(value) = &item.value {
+                    body.push_str(format!("<p>Initial value: <code>{}</code></p>\n", escape(value)).as_str());
+                }
+                body.push_str(paragraphs(&item.doc).as_str());
+                body.push_str("</section>\n");
+            }
+        }
+
+        html_page(&self.title, &body)
+    }
+}
+
+/// A Markdown page linking to each documented file, given as its title and the page's file name.
+pub fn index_markdown(pages: &[(String, String)]) -> String {
+    let mut output = String::from("# Documentation\n\n");
+    for (title, link) in pages {
+        output.push_str(format!("- [{}]({})\n", title, link).as_str());
+    }
+    output
+}
+
+/// An HTML page linking to each documented file, given as its title and the page's file name.
+pub fn index_html(pages: &[(String, String)]) -> String {
+    let mut body = String::from("<h1>Documentation</h1>\n<ul>\n");
+    for (title, link) in pages {
+        body.push_str(format!("<li><a href=\"{}\">{}</a></li>\n", escape(link), escape(title)).as_str());
+    }
+    body.push_str("</ul>\n");
+    html_page("Documentation", &body)
+}
+
+fn html_page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n\
+         body {{ font-family: sans-serif; max-width: 50em; margin: 2em auto; padding: 0 1em; line-height: 1.5; }}\n\
+         code {{ background: #f4f4f4; padding: 0 0.2em; }}\n\
+         h3 {{ margin-bottom: 0; }}\n\
+         .location {{ color: #666; margin-top: 0.2em; }}\n\
+         </style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape(title),
+        body
+    )
+}
+
+/// Doc comment lines as HTML paragraphs, which are separated by blank lines. Text in backticks becomes code.
+fn paragraphs(doc: &[String]) -> String {
+    let mut output = String::new();
+    for paragraph in doc.split(|line| line.trim().is_empty()) {
+        if paragraph.is_empty() {
+            continue;
+        }
+
+        let text = escape(&paragraph.join("\n"));
+        // Every other part is inside a pair of backticks, as long as none are left unclosed.
+        let html = if text.matches('`').count().is_multiple_of(2) {
+            text.split('`')
+                .enumerate()
+                .map(|(index, part)| {
+                    if index % 2 == 1 {
+                        format!("<code>{}</code>", part)
+                    } else {
+                        part.to_string()
+                    }
+                })
+                .collect()
+        } else {
+            text
+        };
+        output.push_str(format!("<p>{}</p>\n", html).as_str());
+    }
+    output
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The initialiser as it was written, with long lists of values cut short.
+fn initializer_text(initializer: &Initializer, source: &str) -> String {
+    match initializer {
+        Initializer::Values(values) => {
+            // A colour is parsed into a literal for each of its bytes, which all point back at the one token.
+            let mut written: Vec<&Expr> = Vec::new();
+            for value in values {
+                let repeated = match (written.last(), value) {
+                    (Some(Expr::Literal { token: last, .. }), Expr::Literal { token, .. }) => last.span == token.span,
+                    _ => false,
+                };
+                if !repeated {
+                    written.push(value);
+                }
+            }
+
+            let mut shown: Vec<String> = written
+                .iter()
+                .take(SHOWN_VALUES)
+                .map(|value| expr_text(value, source))
+                .collect();
+            if written.len() > SHOWN_VALUES {
+                shown.push(String::from("..."));
+            }
+            format!("{{{}}}", shown.join(", "))
+        }
+        Initializer::Incbin { path, offset, length } => match length {
+            Some(length) => format!("incbin(\"{}\", {}, {})", path.lexeme, offset, length),
+            None if *offset > 0 => format!("incbin(\"{}\", {})", path.lexeme, offset),
+            None => format!("incbin(\"{}\")", path.lexeme),
+        },
+        Initializer::Graphics {
+            path,
+            output,
+            tall,
+            dedupe,
+            ..
+        } => {
+            let converter = match output {
+                GraphicsOutput::Tiles => "tiles",
+                GraphicsOutput::Tilemap => "tilemap",
+            };
+            let mut arguments = vec![format!("\"{}\"", path.lexeme)];
+            if *tall {
+                arguments.push(String::from("tall"));
+            }
+            if *dedupe {
+                arguments.push(String::from("dedupe"));
+            }
+            format!("{}({})", converter, arguments.join(", "))
+        }
+    }
+}
+
+/// An expression as it would be written, keeping literals the way they appear in the source.
+fn expr_text(expr: &Expr, source: &str) -> String {
+    match expr {
+        Expr::Literal { token, .. } => source
+            .get(token.span.offset..token.span.offset + token.span.length)
+            .unwrap_or(&token.lexeme)
+            .to_string(),
+        Expr::Variable { name } => name.lexeme.clone(),
+        Expr::Indexed { name, index, field } => {
+            let field = field
+                .as_ref()
+                .map_or(String::new(), |field| format!(".{}", field.lexeme));
+            format!("{}[{}]{}", name.lexeme, expr_text(index, source), field)
+        }
+        Expr::Binary { operator, left, right } => format!(
+            "{} {} {}",
+            expr_text(left, source),
+            operator.lexeme,
+            expr_text(right, source)
+        ),
+        Expr::Call { callee, arguments } => {
+            let arguments: Vec<String> = arguments.iter().map(|argument| expr_text(argument, source)).collect();
+            format!("{}({})", callee.lexeme, arguments.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "/// Draws the `score`.\n///\n/// Call once a frame.\npub fn draw() {}\n\
+                          fn helper() {}\n/// Current score.\npub u8[2] score;\n\
+                          /// Digits & such.\n@bank(2) pub rom u8[] digits = {'0', $31, hw.LCDCF_ON, sizeof(score)};";
+
+    fn page(private: bool) -> Page {
+        let (tokens, _) = tugboat::lex(String::from(SOURCE));
+        let (declarations, errors) = tugboat::parse(tokens);
+        assert!(errors.is_empty(), "{:?}", errors);
+        Page::new("score", SOURCE, &declarations, private)
+    }
+
+    #[test]
+    fn markdown_page() {
+        assert_eq!(
+            page(false).markdown(),
+            "# score\n\n## Functions\n\n### `pub fn draw()`\n\nin ROM0\n\nDraws the `score`.\n\nCall once a frame.\n\n\
+             ## Variables\n\n### `pub u8[2] score`\n\n2 bytes in WRAM0\n\nCurrent score.\n\n\
+             ## Constants\n\n### `pub rom u8[4] digits`\n\n4 bytes in ROMX, bank 2\n\n\
+             Initial value: `{'0', $31, hw.LCDCF_ON, sizeof(score)}`\n\nDigits & such.\n"
+        );
+        assert!(page(true).markdown().contains("\n### `fn helper()`\n\nin ROM0\n"));
+    }
+
+    #[test]
+    fn html_page() {
+        let html = page(false).html();
+        assert!(html.starts_with("<!DOCTYPE html>\n"));
+        assert!(html.contains("<title>score</title>"));
+        assert!(html.contains(
+            "<section id=\"draw\">\n<h3><code>pub fn draw()</code></h3>\n<p class=\"location\">in ROM0</p>\n\
+             <p>Draws the <code>score</code>.</p>\n<p>Call once a frame.</p>\n</section>\n"
+        ));
+        assert!(html.contains("<p>Initial value: <code>{'0', $31, hw.LCDCF_ON, sizeof(score)}</code></p>\n"));
+        assert!(html.contains("<p>Digits &amp; such.</p>"));
+        assert!(!html.contains("helper"));
+    }
+
+    #[test]
+    fn long_initialisers_are_cut_short() {
+        let values = vec![String::from("1"); 20].join(", ");
+        let source = format!("rom u8[] ones = {{{}}};", values);
+        let (tokens, _) = tugboat::lex(source.clone());
+        let (declarations, _) = tugboat::parse(tokens);
+        let markdown = Page::new("ones", &source, &declarations, true).markdown();
+        assert!(markdown.contains(format!("`{{{}, ...}}`", vec!["1"; 16].join(", ")).as_str()));
+
+        // Colours take two bytes each, but are shown and counted once.
+        let colours = vec![String::from("#FF0000"); 10].join(", ");
+        let source = format!("rom u8[] reds = {{{}}};", colours);
+        let (tokens, _) = tugboat::lex(source.clone());
+        let (declarations, _) = tugboat::parse(tokens);
+        let markdown = Page::new("reds", &source, &declarations, true).markdown();
+        assert!(markdown.contains(format!("`{{{}}}`", colours).as_str()));
+    }
+
+    #[test]
+    fn index_pages() {
+        let pages = vec![(String::from("text"), String::from("text.md"))];
+        assert_eq!(index_markdown(&pages), "# Documentation\n\n- [text](text.md)\n");
+        assert!(index_html(&pages).contains("<li><a href=\"text.md\">text</a></li>"));
+        assert!(Page::new("empty", "", &[], false)
+            .markdown()
+            .ends_with("nothing to document.\n"));
+    }
+}
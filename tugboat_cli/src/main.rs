@@ -1,60 +1,480 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use diagnostics::Renderer;
+use docs::Page;
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
-use tugboat::CompilationError;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tugboat::{Diagnostic, Interface, Level, Lint, LintLevel, Mbc, Options, Span};
+
+mod diagnostics;
+mod docs;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[arg(required = true)]
-    file: PathBuf,
+    files: Vec<PathBuf>,
+    /// Output file, or output directory when compiling separate units
     #[arg(short, long)]
     output: Option<PathBuf>,
     #[arg(short, long, default_value_t = false)]
     verbose: bool,
+    /// Compile each file as its own unit with an interface summary (implied by passing several files)
+    #[arg(short, long, default_value_t = false)]
+    separate: bool,
+    /// Interface summaries (.tgi) of previously compiled units to check against
+    #[arg(short = 'I', long = "import")]
+    imports: Vec<PathBuf>,
+    /// Memory bank controller to switch ROM banks with: none, mbc1, mbc3 or mbc5 (all but none need --startup)
+    #[arg(long, default_value = "none", ignore_case = true, requires_ifs = [("mbc1", "startup"), ("mbc3", "startup"), ("mbc5", "startup")])]
+    mbc: Mbc,
+    /// Generate the ROM header and startup code that calls main
+    #[arg(long, default_value_t = false)]
+    startup: bool,
+    /// Reserve a shadow OAM and install the DMA routine that copies it to the screen's sprites
+    #[arg(long, default_value_t = false, requires = "startup")]
+    sprites: bool,
+    /// Target the Game Boy Color, enabling its palettes, VRAM and WRAM banks and double speed mode
+    #[arg(long, default_value_t = false, requires = "startup")]
+    cgb: bool,
+    /// Silence a lint, or all of them with `warnings`
+    #[arg(short = 'A', long = "allow", value_name = "LINT", value_parser = parse_lint)]
+    allow: Vec<LintName>,
+    /// Report a lint as a warning
+    #[arg(short = 'W', long = "warn", value_name = "LINT", value_parser = parse_lint)]
+    warn: Vec<LintName>,
+    /// Report a lint as an error, or all of them with `warnings`
+    #[arg(short = 'D', long = "deny", value_name = "LINT", value_parser = parse_lint)]
+    deny: Vec<LintName>,
+    /// How to print errors and warnings
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Human, global = true)]
+    error_format: ErrorFormat,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Rewrite source files in the canonical style
+    Fmt {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Report files that aren't formatted instead of rewriting them, failing if there are any
+        #[arg(long, default_value_t = false)]
+        check: bool,
+    },
+    /// Write documentation pages for source files, along with an index linking to them
+    Doc {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Directory to write the pages to
+        #[arg(short, long, default_value = "doc")]
+        output: PathBuf,
+        #[arg(long, value_enum, default_value_t = DocFormat::Html)]
+        format: DocFormat,
+        /// Include declarations that aren't `pub`
+        #[arg(long, default_value_t = false)]
+        private: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DocFormat {
+    Html,
+    Markdown,
+}
+
+impl DocFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            DocFormat::Html => "html",
+            DocFormat::Markdown => "md",
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ErrorFormat {
+    /// Messages with the source they point at
+    Human,
+    /// One JSON object per line, for editors and other tools
+    Json,
+}
+
+/// A lint given on the command line, where `None` stands for every lint.
+type LintName = Option<Lint>;
+
+fn parse_lint(name: &str) -> Result<LintName, String> {
+    match name {
+        "warnings" => Ok(None),
+        _ => Lint::from_name(name).map(Some).ok_or_else(|| {
+            let names: Vec<&str> = Lint::ALL.iter().map(Lint::name).collect();
+            format!("unknown lint '{}' (expected warnings, {})", name, names.join(", "))
+        }),
+    }
+}
+
+/// The lint levels from the command line. Naming a single lint takes precedence over `warnings`, and otherwise the
+/// stricter level wins.
+fn lint_levels(args: &Args) -> HashMap<Lint, LintLevel> {
+    let mut lints: HashMap<Lint, LintLevel> = HashMap::new();
+    let flags = [
+        (&args.allow, LintLevel::Allow),
+        (&args.warn, LintLevel::Warn),
+        (&args.deny, LintLevel::Deny),
+    ];
+
+    for specific in [false, true] {
+        for (names, level) in flags.iter() {
+            for name in names.iter().filter(|name| name.is_some() == specific) {
+                match name {
+                    Some(lint) => {
+                        lints.insert(*lint, *level);
+                    }
+                    None => lints.extend(Lint::ALL.map(|lint| (lint, *level))),
+                }
+            }
+        }
+    }
+
+    lints
 }
 
-fn main() {
+fn main() -> ExitCode {
     let args = Args::parse();
+    let reporter = Reporter {
+        format: args.error_format,
+        renderer: Renderer::for_stderr(),
+    };
+
+    match run(&args, &reporter) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure) => ExitCode::FAILURE,
+    }
+}
+
+/// Marks that something went wrong and has already been reported.
+struct Failure;
+
+fn run(args: &Args, reporter: &Reporter) -> Result<(), Failure> {
+    match &args.command {
+        Some(Command::Fmt { files, check }) => return format_files(files, *check, reporter),
+        Some(Command::Doc {
+            files,
+            output,
+            format,
+            private,
+        }) => return document_files(args, files, output, *format, *private, reporter),
+        None => {}
+    }
+
+    let imports = read_imports(&args.imports, reporter)?;
+
+    if args.files.len() > 1 || args.separate {
+        return compile_separately(args, imports, reporter);
+    }
+
+    let file = &args.files[0];
+    let output = match &args.output {
+        Some(path) => path.clone(),
+        None => file.with_extension("asm"),
+    };
 
-    let output = match args.output {
-        Some(path) => path,
-        None => {
-            let mut renamed = args.file.clone();
-            renamed.set_extension("asm");
-            renamed
+    let options = Options {
+        imports,
+        ..options_for(args, file, false)
+    };
+    let contents = read(file, reporter)?;
+    compile(file, contents, output, &options, args.verbose, reporter)
+}
+
+fn compile_separately(args: &Args, imports: Vec<Interface>, reporter: &Reporter) -> Result<(), Failure> {
+    let mut sources: Vec<(&PathBuf, String)> = Vec::new();
+    for file in args.files.iter() {
+        sources.push((file, read(file, reporter)?));
+    }
+
+    // Summarise every unit up front so each one can be checked against the rest.
+    let mut interfaces: Vec<Interface> = Vec::new();
+    for (file, contents) in sources.iter() {
+        match tugboat::interface(contents.clone(), &options_for(args, file, true)) {
+            Ok(interface) => {
+                write(&output_path(args, file, "tgi"), interface.to_string(), reporter)?;
+                interfaces.push(interface);
+            }
+            Err(errors) => return reporter.report(file, contents, &errors),
         }
+    }
+
+    // Every unit is compiled even once one fails, so that all of their errors are reported together.
+    let mut result = Ok(());
+    for (index, (file, contents)) in sources.iter().enumerate() {
+        let mut options = Options {
+            imports: imports.clone(),
+            ..options_for(args, file, true)
+        };
+        options.imports.extend(
+            interfaces
+                .iter()
+                .enumerate()
+                .filter(|(other, _)| *other != index)
+                .map(|(_, interface)| interface.clone()),
+        );
+
+        let output = output_path(args, file, "asm");
+        result = result.and(compile(
+            file,
+            contents.clone(),
+            output,
+            &options,
+            args.verbose,
+            reporter,
+        ));
+    }
+
+    let runtime = match &args.output {
+        Some(directory) => directory.join("runtime.asm"),
+        None => args.files[0].with_file_name("runtime.asm"),
     };
+    write(
+        &runtime,
+        tugboat::runtime(&options_for(args, &args.files[0], true)),
+        reporter,
+    )?;
+    result
+}
+
+/// Formats each file in place, or with `check` reports where the first change would be. Every file is looked at
+/// even once one fails.
+fn format_files(files: &[PathBuf], check: bool, reporter: &Reporter) -> Result<(), Failure> {
+    let mut result = Ok(());
+    for file in files {
+        let contents = match read(file, reporter) {
+            Ok(contents) => contents,
+            Err(failure) => {
+                result = Err(failure);
+                continue;
+            }
+        };
+        let formatted = match tugboat::format(contents.clone()) {
+            Ok(formatted) if formatted == contents => continue,
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                result = result.and(reporter.report(file, &contents, &errors));
+                continue;
+            }
+        };
+
+        result = result.and(if check {
+            reporter.report(file, &contents, &[unformatted(&contents, &formatted)])
+        } else {
+            write(file, formatted, reporter)
+        });
+    }
+    result
+}
+
+/// Writes a page for each file into `output`, then an index of them. Files that don't parse are reported and left
+/// out, while the rest are still documented.
+fn document_files(
+    args: &Args,
+    files: &[PathBuf],
+    output: &Path,
+    format: DocFormat,
+    private: bool,
+    reporter: &Reporter,
+) -> Result<(), Failure> {
+    fs::create_dir_all(output)
+        .map_err(|err| reporter.fail(output, format!("Failed to create {:?}: {}", output, err)))?;
+
+    let mut result = Ok(());
+    let mut pages: Vec<(String, String)> = Vec::new();
+    for file in files {
+        let contents = match read(file, reporter) {
+            Ok(contents) => contents,
+            Err(failure) => {
+                result = Err(failure);
+                continue;
+            }
+        };
+        let declarations = match tugboat::declarations(contents.clone(), &options_for(args, file, false)) {
+            Ok(declarations) => declarations,
+            Err(errors) => {
+                result = result.and(reporter.report(file, &contents, &errors));
+                continue;
+            }
+        };
 
-    let file = fs::read_to_string(&args.file);
-    match file {
-        Err(err) => println!("Unable to open {:?}: {}", args.file, err),
-        Ok(contents) => {
-            compile(contents, output, args.verbose);
+        let title = file.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        let page = Page::new(&title, &contents, &declarations, private);
+        let link = format!("{}.{}", title, format.extension());
+        let rendered = match format {
+            DocFormat::Html => page.html(),
+            DocFormat::Markdown => page.markdown(),
+        };
+        write(&output.join(&link), rendered, reporter)?;
+        pages.push((title, link));
+    }
+
+    let index = match format {
+        DocFormat::Html => docs::index_html(&pages),
+        DocFormat::Markdown => docs::index_markdown(&pages),
+    };
+    write(&output.join(format!("index.{}", format.extension())), index, reporter)?;
+    result
+}
+
+/// An error pointing at the first line formatting would change, with what it would become.
+fn unformatted(contents: &str, formatted: &str) -> Diagnostic {
+    let mut offset = 0;
+    let mut expected = formatted.split('\n');
+    for (index, line) in contents.split('\n').enumerate() {
+        let replacement = expected.next();
+        if replacement != Some(line) {
+            let span = Span {
+                offset,
+                length: line.len(),
+                line: index as u32 + 1,
+                column: 1,
+            };
+            let note = match replacement {
+                Some(replacement) => format!("this line should read `{}`", replacement.trim()),
+                None => String::from("this line should be removed"),
+            };
+            return Diagnostic::error(span, "File is not formatted").with_note(note);
         }
+        offset += line.len() + 1;
     }
+
+    // Every line matched, so all that's missing is the newline at the end.
+    let last = contents.rsplit('\n').next().unwrap_or_default();
+    let end = Span {
+        offset: contents.len(),
+        length: 0,
+        line: contents.split('\n').count() as u32,
+        column: last.chars().count() as u32 + 1,
+    };
+    Diagnostic::error(end, "File is not formatted").with_note("the file should end with a newline")
 }
 
-fn compile(contents: String, output: PathBuf, verbose: bool) {
-    let result = tugboat::compile(contents);
-    match result {
-        Ok(asm) => {
+/// The options shared by every unit, with asset paths relative to the file being compiled.
+fn options_for(args: &Args, file: &Path, separate: bool) -> Options {
+    Options {
+        separate,
+        imports: Vec::new(),
+        mbc: args.mbc,
+        startup: args.startup,
+        sprites: args.sprites,
+        cgb: args.cgb,
+        base_dir: file.parent().map(Path::to_path_buf).unwrap_or_default(),
+        lints: lint_levels(args),
+    }
+}
+
+fn output_path(args: &Args, file: &Path, extension: &str) -> PathBuf {
+    let renamed = file.with_extension(extension);
+    match &args.output {
+        Some(directory) => directory.join(renamed.file_name().unwrap()),
+        None => renamed,
+    }
+}
+
+fn read_imports(paths: &[PathBuf], reporter: &Reporter) -> Result<Vec<Interface>, Failure> {
+    let mut imports: Vec<Interface> = Vec::new();
+    for path in paths {
+        let contents = read(path, reporter)?;
+        match Interface::parse(&contents) {
+            Ok(interface) => imports.push(interface),
+            Err(errors) => return reporter.report(path, &contents, &errors).and(Err(Failure)),
+        }
+    }
+    Ok(imports)
+}
+
+fn read(path: &Path, reporter: &Reporter) -> Result<String, Failure> {
+    fs::read_to_string(path).map_err(|err| reporter.fail(path, format!("Unable to open {:?}: {}", path, err)))
+}
+
+fn write(path: &Path, contents: String, reporter: &Reporter) -> Result<(), Failure> {
+    fs::write(path, contents).map_err(|err| reporter.fail(path, format!("Failed to write {:?}: {}", path, err)))
+}
+
+fn compile(
+    file: &Path,
+    contents: String,
+    output: PathBuf,
+    options: &Options,
+    verbose: bool,
+    reporter: &Reporter,
+) -> Result<(), Failure> {
+    match tugboat::compile_with(contents.clone(), options) {
+        Ok(unit) => {
+            reporter.report(file, &contents, &unit.warnings)?;
             if verbose {
-                println!("{}", asm);
+                println!("{}", unit.asm);
             }
-            let write_result = fs::write(output.clone(), asm);
-            if let Err(error) = write_result {
-                println!("Failed to write {:?}: {}", output, error);
+            write(&output, unit.asm, reporter)
+        }
+        Err(errors) => reporter.report(file, &contents, &errors),
+    }
+}
+
+/// Prints diagnostics in the chosen format to stderr, leaving stdout to the assembly printed by `--verbose`.
+struct Reporter {
+    format: ErrorFormat,
+    renderer: Renderer,
+}
+
+impl Reporter {
+    /// Prints every diagnostic, failing if any of them are errors.
+    fn report(&self, file: &Path, source: &str, diagnostics: &[Diagnostic]) -> Result<(), Failure> {
+        for diagnostic in diagnostics {
+            match self.format {
+                ErrorFormat::Human => eprintln!("{}", self.renderer.render(file, source, diagnostic)),
+                ErrorFormat::Json => eprintln!("{}", diagnostics::to_json(file, diagnostic)),
             }
         }
-        Err(errors) => {
-            report(errors);
+
+        if diagnostics.iter().any(|diagnostic| diagnostic.level == Level::Error) {
+            Err(Failure)
+        } else {
+            Ok(())
         }
     }
+
+    /// Reports a problem with a file itself rather than its contents.
+    fn fail(&self, file: &Path, msg: String) -> Failure {
+        let _ = self.report(file, "", &[Diagnostic::error(Span::default(), msg)]);
+        Failure
+    }
 }
 
-fn report(errors: Vec<CompilationError>) {
-    for err in errors {
-        println!("[line {}] error: {}", err.line, err.msg);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_the_files_it_can_read() {
+        let dir = std::env::temp_dir().join(format!("tugc-doc-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let good = dir.join("good.tg");
+        fs::write(&good, "/// Draws.\npub fn draw() {}\n").unwrap();
+        let output = dir.join("doc");
+
+        let args = Args::try_parse_from(["tugc", "doc", "good.tg"]).unwrap();
+        let reporter = Reporter {
+            format: ErrorFormat::Json,
+            renderer: Renderer::new(false),
+        };
+        let files = [dir.join("missing.tg"), good];
+        let result = document_files(&args, &files, &output, DocFormat::Markdown, false, &reporter);
+        let page = fs::read_to_string(output.join("good.md"));
+        let index = fs::read_to_string(output.join("index.md"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_err());
+        assert!(page.unwrap().contains("### `pub fn draw()`"));
+        assert!(index.unwrap().contains("- [good](good.md)"));
     }
 }
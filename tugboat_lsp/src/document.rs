@@ -0,0 +1,394 @@
+use lsp_types::{
+    CompletionItem, CompletionItemKind, DocumentSymbol, Documentation, Hover, HoverContents, MarkupContent, MarkupKind,
+    Position, Range, SymbolKind,
+};
+use tugboat::{visit, Builtin, Declaration, Diagnostic, Expr, Span, Token, Visitor};
+
+/// An open source file, parsed so that names can be looked up by position.
+pub struct Document {
+    pub text: String,
+    pub ast: Vec<Declaration>,
+    /// Errors from lexing and parsing, which leave out whatever couldn't be parsed from `ast`.
+    pub errors: Vec<Diagnostic>,
+    /// Every mention of a name, in source order.
+    pub names: Vec<Name>,
+}
+
+/// A name as it appears in the source, either where it is declared or where it is used.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Name {
+    pub name: String,
+    pub span: Span,
+    pub declaration: bool,
+}
+
+impl Document {
+    pub fn new(text: String) -> Document {
+        let (tokens, mut errors) = tugboat::lex(text.clone());
+        let (ast, parser_errors) = tugboat::parse(tokens);
+        errors.extend(parser_errors);
+        let mut names: Vec<Name> = Vec::new();
+
+        visit::walk_ast(&mut Names(&mut names), &ast);
+        names.sort_by_key(|name| name.span.offset);
+
+        Document {
+            text,
+            ast,
+            errors,
+            names,
+        }
+    }
+
+    /// The byte offset of an LSP position, which counts UTF-16 code units along the line.
+    pub fn offset(&self, position: Position) -> Option<usize> {
+        let mut line_start = 0;
+        for _ in 0..position.line {
+            line_start += self.text[line_start..].find('\n')? + 1;
+        }
+
+        let mut units = 0;
+        for (index, c) in self.text[line_start..].char_indices() {
+            if units >= position.character as usize || c == '\n' {
+                return Some(line_start + index);
+            }
+            units += c.len_utf16();
+        }
+        Some(self.text.len())
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let before = &self.text[..offset];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        Position {
+            line: before.matches('\n').count() as u32,
+            character: before[line_start..].encode_utf16().count() as u32,
+        }
+    }
+
+    pub fn range(&self, span: Span) -> Range {
+        Range {
+            start: self.position(span.offset),
+            end: self.position(span.offset + span.length),
+        }
+    }
+
+    /// The name under the cursor, including when the cursor is just after it.
+    pub fn name_at(&self, position: Position) -> Option<&Name> {
+        let offset = self.offset(position)?;
+        self.names
+            .iter()
+            .find(|name| (name.span.offset..=name.span.offset + name.span.length).contains(&offset))
+    }
+
+    pub fn declaration(&self, name: &str) -> Option<&Declaration> {
+        self.ast.iter().find(|dec| dec.name().lexeme == name)
+    }
+
+    /// Every mention of the same name as the one under the cursor.
+    pub fn references(&self, position: Position, include_declaration: bool) -> Vec<Range> {
+        let Some(target) = self.name_at(position) else {
+            return Vec::new();
+        };
+        self.names
+            .iter()
+            .filter(|name| name.name == target.name && (include_declaration || !name.declaration))
+            .map(|name| self.range(name.span))
+            .collect()
+    }
+
+    pub fn hover(&self, position: Position, builtins: &[Builtin]) -> Option<Hover> {
+        let name = self.name_at(position)?;
+        let value = match self.declaration(&name.name) {
+            Some(dec) => describe(dec),
+            None => {
+                let builtin = builtins.iter().find(|builtin| builtin.name == name.name)?;
+                format!("```tugboat\n{}\n```\n{}", builtin.name, builtin.description)
+            }
+        };
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(self.range(name.span)),
+        })
+    }
+
+    /// Names that could be typed at the cursor. After a namespace such as `hw.`, only the members of that namespace
+    /// are offered, since editors don't usually treat the dot as part of the word being completed.
+    pub fn completions(&self, position: Position, builtins: &[Builtin]) -> Vec<CompletionItem> {
+        let offset = self.offset(position).unwrap_or(self.text.len());
+        let before = &self.text[..offset];
+        let word_start = before
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+            .map_or(0, |index| index + 1);
+        let word = &before[word_start..];
+
+        if let Some((namespace, _)) = word.rsplit_once('.') {
+            let prefix = format!("{}.", namespace);
+            return builtins
+                .iter()
+                .filter_map(|builtin| {
+                    let member = builtin.name.strip_prefix(&prefix)?;
+                    Some(builtin_completion(builtin, member))
+                })
+                .collect();
+        }
+
+        let declared = self.ast.iter().map(|dec| {
+            let kind = match dec {
+                Declaration::Variable { .. } => CompletionItemKind::VARIABLE,
+                Declaration::Function { .. } => CompletionItemKind::FUNCTION,
+            };
+            CompletionItem {
+                label: dec.name().lexeme.clone(),
+                kind: Some(kind),
+                detail: Some(dec.signature()),
+                documentation: (!dec.doc().is_empty()).then(|| {
+                    Documentation::MarkupContent(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: dec.doc().join("\n"),
+                    })
+                }),
+                ..CompletionItem::default()
+            }
+        });
+        declared
+            .chain(
+                builtins
+                    .iter()
+                    .map(|builtin| builtin_completion(builtin, &builtin.name)),
+            )
+            .collect()
+    }
+
+    #[allow(deprecated)]
+    pub fn symbols(&self) -> Vec<DocumentSymbol> {
+        self.ast
+            .iter()
+            .map(|dec| {
+                let name = dec.name();
+                let kind = match dec {
+                    Declaration::Variable { .. } => SymbolKind::VARIABLE,
+                    Declaration::Function { .. } => SymbolKind::FUNCTION,
+                };
+                DocumentSymbol {
+                    name: name.lexeme.clone(),
+                    detail: Some(dec.signature()),
+                    kind,
+                    tags: None,
+                    deprecated: None,
+                    range: self.range(dec.extent()),
+                    selection_range: self.range(name.span),
+                    children: None,
+                }
+            })
+            .collect()
+    }
+}
+
+fn builtin_completion(builtin: &Builtin, label: &str) -> CompletionItem {
+    let kind = if builtin.callable {
+        CompletionItemKind::FUNCTION
+    } else {
+        CompletionItemKind::CONSTANT
+    };
+    CompletionItem {
+        label: label.to_string(),
+        kind: Some(kind),
+        detail: Some(builtin.description.clone()),
+        ..CompletionItem::default()
+    }
+}
+
+fn declared(name: &Token) -> Name {
+    Name {
+        name: name.lexeme.clone(),
+        span: name.span,
+        declaration: true,
+    }
+}
+
+/// Markdown describing a declaration, with its type, size and where it lives.
+fn describe(dec: &Declaration) -> String {
+    let mut value = format!("```tugboat\n{}\n```\n{}", dec.signature(), dec.location());
+    if !dec.doc().is_empty() {
+        value.push_str(format!("\n\n{}", dec.doc().join("\n")).as_str());
+    }
+    value
+}
+
+/// Collects every name used in the expressions it visits.
+struct Names<'a>(&'a mut Vec<Name>);
+
+impl Visitor<'_> for Names<'_> {
+    fn visit_declaration(&mut self, dec: &Declaration) {
+        self.0.push(declared(dec.name()));
+        visit::walk_declaration(self, dec);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Variable { name } | Expr::Indexed { name, .. } | Expr::Call { callee: name, .. } = expr {
+            self.0.push(Name {
+                name: name.lexeme.clone(),
+                span: name.span,
+                declaration: false,
+            });
+        }
+        visit::walk_expr(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tugboat::Options;
+
+    fn position(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    #[test]
+    fn positions_count_utf16() {
+        let document = Document::new(String::from("u8 a; // 😀 x\nfn main() {}\n"));
+        // The emoji is four bytes but two UTF-16 code units.
+        let offset = document.text.find('x').unwrap();
+        assert_eq!(offset, 14);
+        assert_eq!(document.position(offset), position(0, 12));
+        assert_eq!(document.offset(position(0, 12)), Some(offset));
+        assert_eq!(document.offset(position(0, 99)), Some(15));
+        assert_eq!(document.position(document.text.len()), position(2, 0));
+        assert_eq!(document.offset(position(9, 0)), None);
+    }
+
+    #[test]
+    fn names_and_references() {
+        let document = Document::new(String::from(
+            "u8 count;\nfn tick() { count = count + 1; }\nfn main() { tick(); hw.rLY = count; }",
+        ));
+
+        let name = document.name_at(position(1, 14)).unwrap();
+        assert_eq!((name.name.as_str(), name.declaration), ("count", false));
+        assert!(matches!(
+            document.declaration(&name.name),
+            Some(Declaration::Variable { size: Some(1), .. })
+        ));
+
+        let lines: Vec<(u32, u32)> = document
+            .references(position(0, 4), true)
+            .iter()
+            .map(|range| (range.start.line, range.start.character))
+            .collect();
+        assert_eq!(lines, vec![(0, 3), (1, 12), (1, 20), (2, 29)]);
+        assert_eq!(document.references(position(0, 4), false).len(), 3);
+
+        let hw = document.name_at(position(2, 23)).unwrap();
+        assert_eq!(hw.name, "hw.rLY");
+        assert_eq!(document.range(hw.span).end, position(2, 26));
+    }
+
+    #[test]
+    fn hover_describes_declarations_and_builtins() {
+        let document = Document::new(String::from(
+            "@bank(2) rom u8[3] table = {1, 2, 3};\nhram u8 fast;\nfn main() { fast = hw.rLY; }",
+        ));
+        let builtins = tugboat::builtins(&Options::default());
+
+        let hover = |line, character| match document.hover(position(line, character), &builtins) {
+            Some(Hover {
+                contents: HoverContents::Markup(markup),
+                ..
+            }) => markup.value,
+            other => panic!("Expected markdown hover, got {:?}", other),
+        };
+        assert_eq!(
+            hover(0, 20),
+            "```tugboat\nrom u8[3] table\n```\n3 bytes in ROMX, bank 2"
+        );
+        assert_eq!(hover(2, 13), "```tugboat\nhram u8 fast\n```\n1 byte in HRAM");
+        assert_eq!(hover(2, 22), "```tugboat\nhw.rLY\n```\nhardware register at $FF44");
+        assert!(document.hover(position(2, 1), &builtins).is_none());
+
+        let document = Document::new(String::from("/// Lives left.\n///\n/// Game over at zero.\nu8 lives;"));
+        assert_eq!(
+            document.hover(position(3, 4), &builtins).map(|hover| hover.contents),
+            Some(HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: String::from("```tugboat\nu8 lives\n```\n1 byte in WRAM0\n\nLives left.\n\nGame over at zero."),
+            }))
+        );
+    }
+
+    #[test]
+    fn completion_offers_declarations_and_namespaces() {
+        let document = Document::new(String::from("u8 lives;\nfn main() { lives = hw.rL }"));
+        let builtins = tugboat::builtins(&Options::default());
+
+        let labels: Vec<String> = document
+            .completions(position(1, 25), &builtins)
+            .into_iter()
+            .map(|item| item.label)
+            .collect();
+        assert!(labels.contains(&String::from("rLY")));
+        assert!(labels.iter().all(|label| !label.contains('.')));
+
+        let items = document.completions(position(1, 12), &builtins);
+        let lives = items.iter().find(|item| item.label == "lives").unwrap();
+        assert_eq!(lives.kind, Some(CompletionItemKind::VARIABLE));
+        let copy = items.iter().find(|item| item.label == "copy").unwrap();
+        assert_eq!(copy.kind, Some(CompletionItemKind::FUNCTION));
+        assert!(items.iter().any(|item| item.label == "hw.rLY"));
+    }
+
+    #[test]
+    fn document_symbols() {
+        let document = Document::new(String::from(
+            "pub u8[4] buffer;\n/// Runs every frame.\n@section(\"Drawing\")\ninterrupt(vblank) fn draw() {\n}",
+        ));
+        let ranges: Vec<(Range, Range)> = document
+            .symbols()
+            .into_iter()
+            .map(|symbol| (symbol.range, symbol.selection_range))
+            .collect();
+        let range = |start: (u32, u32), end: (u32, u32)| Range {
+            start: position(start.0, start.1),
+            end: position(end.0, end.1),
+        };
+        // The whole declaration is the symbol, with its name picked out.
+        assert_eq!(
+            ranges,
+            vec![
+                (range((0, 0), (0, 17)), range((0, 10), (0, 16))),
+                (range((1, 0), (4, 1)), range((3, 21), (3, 25))),
+            ]
+        );
+
+        let symbols: Vec<(String, Option<String>, SymbolKind)> = document
+            .symbols()
+            .into_iter()
+            .map(|symbol| (symbol.name, symbol.detail, symbol.kind))
+            .collect();
+        assert_eq!(
+            symbols,
+            vec![
+                (
+                    String::from("buffer"),
+                    Some(String::from("pub u8[4] buffer")),
+                    SymbolKind::VARIABLE
+                ),
+                (
+                    String::from("draw"),
+                    Some(String::from("interrupt(vblank) fn draw()")),
+                    SymbolKind::FUNCTION
+                ),
+            ]
+        );
+    }
+}
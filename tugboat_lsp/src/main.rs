@@ -0,0 +1,396 @@
+use document::Document;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, References, Request as LspRequest,
+};
+use lsp_types::{
+    CompletionOptions, CompletionParams, CompletionResponse, DiagnosticRelatedInformation, DiagnosticSeverity,
+    DocumentSymbolParams, DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverParams,
+    HoverProviderCapability, InitializeParams, Location, NumberOrString, OneOf, PublishDiagnosticsParams,
+    ReferenceParams, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, Uri,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+use tugboat::{Builtin, Level, Mbc, Options};
+
+mod document;
+
+fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![String::from(".")]),
+            ..CompletionOptions::default()
+        }),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    let params = connection.initialize(serde_json::to_value(capabilities)?)?;
+    let params: InitializeParams = serde_json::from_value(params)?;
+
+    let mut server = Server::new(options(params.initialization_options.as_ref()));
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                connection.sender.send(Message::Response(server.respond(request)))?;
+            }
+            Message::Notification(notification) => {
+                for published in server.notify(notification) {
+                    connection.sender.send(Message::Notification(published))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+
+    // The writer thread only finishes once the connection is gone.
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+/// Compiler options from the client's `initializationOptions`, which can set `startup`, `sprites`, `cgb` and
+/// `mbc` as they would be passed to `tugc`.
+fn options(initialization: Option<&Value>) -> Options {
+    let flag = |name: &str| initialization.and_then(|value| value[name].as_bool()).unwrap_or(false);
+    Options {
+        startup: flag("startup"),
+        sprites: flag("sprites"),
+        cgb: flag("cgb"),
+        mbc: initialization
+            .and_then(|value| value["mbc"].as_str())
+            .and_then(|mbc| mbc.parse::<Mbc>().ok())
+            .unwrap_or_default(),
+        ..Options::default()
+    }
+}
+
+struct Server {
+    options: Options,
+    builtins: Vec<Builtin>,
+    documents: HashMap<Uri, Document>,
+}
+
+impl Server {
+    fn new(options: Options) -> Server {
+        Server {
+            builtins: tugboat::builtins(&options),
+            options,
+            documents: HashMap::new(),
+        }
+    }
+
+    fn respond(&self, request: Request) -> Response {
+        None.or_else(|| self.handle::<GotoDefinition>(&request, |params| self.definition(params)))
+            .or_else(|| self.handle::<References>(&request, |params| self.references(params)))
+            .or_else(|| self.handle::<HoverRequest>(&request, |params| self.hover(params)))
+            .or_else(|| self.handle::<Completion>(&request, |params| self.completion(params)))
+            .or_else(|| self.handle::<DocumentSymbolRequest>(&request, |params| self.symbols(params)))
+            .unwrap_or_else(|| {
+                Response::new_err(
+                    request.id.clone(),
+                    ErrorCode::MethodNotFound as i32,
+                    format!("Unsupported request {}", request.method),
+                )
+            })
+    }
+
+    /// Answers the request with `handler` if it is an `R` request.
+    fn handle<R: LspRequest>(&self, request: &Request, handler: impl Fn(R::Params) -> R::Result) -> Option<Response> {
+        if request.method != R::METHOD {
+            return None;
+        }
+
+        let response = match serde_json::from_value::<R::Params>(request.params.clone()) {
+            Ok(params) => Response::new_ok(request.id.clone(), handler(params)),
+            Err(err) => Response::new_err(request.id.clone(), ErrorCode::InvalidParams as i32, err.to_string()),
+        };
+        Some(response)
+    }
+
+    /// Keeps track of open documents, returning the diagnostics to publish for any that changed.
+    fn notify(&mut self, notification: Notification) -> Vec<Notification> {
+        let uri = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let Ok(params) =
+                    notification.extract::<lsp_types::DidOpenTextDocumentParams>(DidOpenTextDocument::METHOD)
+                else {
+                    return Vec::new();
+                };
+                let uri = params.text_document.uri;
+                self.documents
+                    .insert(uri.clone(), Document::new(params.text_document.text));
+                uri
+            }
+            DidChangeTextDocument::METHOD => {
+                let Ok(mut params) =
+                    notification.extract::<lsp_types::DidChangeTextDocumentParams>(DidChangeTextDocument::METHOD)
+                else {
+                    return Vec::new();
+                };
+                // Documents are synchronised in full, so the last change has the whole text.
+                let Some(change) = params.content_changes.pop() else {
+                    return Vec::new();
+                };
+                let uri = params.text_document.uri;
+                self.documents.insert(uri.clone(), Document::new(change.text));
+                uri
+            }
+            DidCloseTextDocument::METHOD => {
+                let Ok(params) =
+                    notification.extract::<lsp_types::DidCloseTextDocumentParams>(DidCloseTextDocument::METHOD)
+                else {
+                    return Vec::new();
+                };
+                self.documents.remove(&params.text_document.uri);
+                return vec![publish(params.text_document.uri, Vec::new())];
+            }
+            _ => return Vec::new(),
+        };
+
+        vec![publish(uri.clone(), self.diagnostics(&uri))]
+    }
+
+    /// Checks the document for errors and warnings, stopping short of generating code, which is too slow to redo on
+    /// every keystroke. Problems that only turn up while laying out sections or emitting code are left for the
+    /// compiler to report.
+    fn diagnostics(&self, uri: &Uri) -> Vec<lsp_types::Diagnostic> {
+        let Some(document) = self.documents.get(uri) else {
+            return Vec::new();
+        };
+        let options = Options {
+            base_dir: file_path(uri)
+                .and_then(|path| path.parent().map(PathBuf::from))
+                .unwrap_or_default(),
+            ..self.options.clone()
+        };
+
+        let diagnostics = if !document.errors.is_empty() {
+            document.errors.clone()
+        } else {
+            match tugboat::check(document.ast.clone(), &options) {
+                Ok(checked) => checked.diagnostics,
+                Err(errors) => errors,
+            }
+        };
+
+        diagnostics
+            .into_iter()
+            .map(|diagnostic| {
+                let mut message = diagnostic.msg;
+                for note in diagnostic.notes {
+                    message.push_str(format!("\nhelp: {}", note).as_str());
+                }
+                let related = diagnostic
+                    .labels
+                    .into_iter()
+                    .map(|label| DiagnosticRelatedInformation {
+                        location: Location::new(uri.clone(), document.range(label.span)),
+                        message: label.msg,
+                    })
+                    .collect::<Vec<_>>();
+
+                lsp_types::Diagnostic {
+                    range: document.range(diagnostic.span),
+                    severity: Some(match diagnostic.level {
+                        Level::Error => DiagnosticSeverity::ERROR,
+                        Level::Warning => DiagnosticSeverity::WARNING,
+                        Level::Note => DiagnosticSeverity::INFORMATION,
+                    }),
+                    code: diagnostic
+                        .code
+                        .map(|lint| NumberOrString::String(lint.name().to_string())),
+                    source: Some(String::from("tugboat")),
+                    message,
+                    related_information: (!related.is_empty()).then_some(related),
+                    ..lsp_types::Diagnostic::default()
+                }
+            })
+            .collect()
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let document = self.documents.get(&uri)?;
+        let name = document.name_at(params.text_document_position_params.position)?;
+        let declaration = document.declaration(&name.name)?;
+        let location = Location::new(uri.clone(), document.range(declaration.span()));
+        Some(GotoDefinitionResponse::Scalar(location))
+    }
+
+    fn references(&self, params: ReferenceParams) -> Option<Vec<Location>> {
+        let uri = params.text_document_position.text_document.uri;
+        let document = self.documents.get(&uri)?;
+        let ranges = document.references(
+            params.text_document_position.position,
+            params.context.include_declaration,
+        );
+        Some(
+            ranges
+                .into_iter()
+                .map(|range| Location::new(uri.clone(), range))
+                .collect(),
+        )
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let document = self
+            .documents
+            .get(&params.text_document_position_params.text_document.uri)?;
+        document.hover(params.text_document_position_params.position, &self.builtins)
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let document = self.documents.get(&params.text_document_position.text_document.uri)?;
+        let items = document.completions(params.text_document_position.position, &self.builtins);
+        Some(CompletionResponse::Array(items))
+    }
+
+    fn symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let document = self.documents.get(&params.text_document.uri)?;
+        Some(DocumentSymbolResponse::Nested(document.symbols()))
+    }
+}
+
+fn publish(uri: Uri, diagnostics: Vec<lsp_types::Diagnostic>) -> Notification {
+    Notification::new(
+        PublishDiagnostics::METHOD.to_string(),
+        PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        },
+    )
+}
+
+/// The path of a `file:` URI, which asset paths are relative to.
+fn file_path(uri: &Uri) -> Option<PathBuf> {
+    let path = uri.as_str().strip_prefix("file://")?;
+
+    // Decode percent-escapes byte by byte, since they may make up multi-byte characters.
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn server_with(text: &str) -> (Server, Uri) {
+        let uri = Uri::from_str("file:///home/me/my%20game/main.tg").unwrap();
+        let mut server = Server::new(Options {
+            startup: true,
+            ..Options::default()
+        });
+        let opened = Notification::new(
+            DidOpenTextDocument::METHOD.to_string(),
+            lsp_types::DidOpenTextDocumentParams {
+                text_document: lsp_types::TextDocumentItem::new(uri.clone(), String::from("tugboat"), 1, text.into()),
+            },
+        );
+        let published = server.notify(opened);
+        assert_eq!(published.len(), 1);
+        (server, uri)
+    }
+
+    #[test]
+    fn publishes_diagnostics() {
+        let (server, uri) = server_with("u8 x;\nu8 x;\nfn main() {}");
+        let diagnostics = server.diagnostics(&uri);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "Duplicate definition: x");
+        assert_eq!(diagnostics[0].range.start, lsp_types::Position::new(1, 3));
+        assert_eq!(
+            diagnostics[0].related_information.as_ref().unwrap()[0].message,
+            "first defined here"
+        );
+
+        // Only the checks that are quick enough to run on every change are made, so problems left for code
+        // generation, like a missing main, aren't reported.
+        let (server, uri) = server_with("u8 x;\npub fn start() { x = 1; }");
+        assert!(server.diagnostics(&uri).is_empty());
+        let (server, uri) = server_with("u8 x;\nfn main() { x = ; }");
+        let diagnostics = server.diagnostics(&uri);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start, lsp_types::Position::new(1, 16));
+
+        let (server, uri) = server_with("u8 unused;\nfn main() {}");
+        let diagnostics = server.diagnostics(&uri);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(
+            diagnostics[0].code,
+            Some(NumberOrString::String(String::from("unused_variable")))
+        );
+    }
+
+    #[test]
+    fn answers_requests() {
+        let (server, uri) = server_with("u8 x;\nfn main() { x = 1; }");
+        let request = Request::new(
+            1.into(),
+            GotoDefinition::METHOD.to_string(),
+            GotoDefinitionParams {
+                text_document_position_params: lsp_types::TextDocumentPositionParams::new(
+                    lsp_types::TextDocumentIdentifier::new(uri.clone()),
+                    lsp_types::Position::new(1, 12),
+                ),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            },
+        );
+
+        let response = server.respond(request);
+        let location: Location = serde_json::from_value(response.result.unwrap()).unwrap();
+        assert_eq!(location.range.start, lsp_types::Position::new(0, 3));
+
+        let unsupported = server.respond(Request::new(2.into(), String::from("textDocument/rename"), Value::Null));
+        assert_eq!(unsupported.error.unwrap().code, ErrorCode::MethodNotFound as i32);
+    }
+
+    #[test]
+    fn file_paths_are_decoded() {
+        let uri = Uri::from_str("file:///home/me/my%20game/caf%C3%A9.tg").unwrap();
+        assert_eq!(file_path(&uri), Some(PathBuf::from("/home/me/my game/café.tg")));
+        assert_eq!(file_path(&Uri::from_str("untitled:Untitled-1").unwrap()), None);
+    }
+
+    #[test]
+    fn reads_initialization_options() {
+        let options = options(Some(&serde_json::json!({ "startup": true, "mbc": "mbc5" })));
+        assert!(options.startup && !options.cgb);
+        assert_eq!(options.mbc, Mbc::Mbc5);
+    }
+}